@@ -2,7 +2,7 @@ use std::io::ErrorKind;
 
 use bytes::Bytes;
 use futures::io::Error;
-use futures::SinkExt;
+use futures::{SinkExt, Stream, StreamExt};
 use tokio::net::tcp::ReadHalf;
 use tokio::net::tcp::WriteHalf;
 use tokio::net::TcpStream;
@@ -46,4 +46,19 @@ impl<'a> Channel<'a> {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Send the payloads as the stream yields them. The stream is polled only when the
+    /// socket takes more, a slow client slows the response down instead of the payloads
+    /// piling up, and what is encoded goes out whenever the stream has to wait.
+    pub async fn send_stream<S: Stream<Item = Bytes> + Unpin>(&mut self, payloads: Option<S>) -> Result<(), Error> {
+        match payloads {
+            Some(payloads) => {
+                let mut payloads = payloads.map(Ok);
+                self.sink.send_all(&mut payloads).await
+            }
+            _ => {
+                Err(Error::new(ErrorKind::InvalidData, "empty payload!!!"))
+            }
+        }
+    }
+}
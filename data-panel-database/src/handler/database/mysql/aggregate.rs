@@ -0,0 +1,1034 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use sqlparser::ast::{BinaryOperator, Expr, FunctionArg, OrderByExpr, SelectItem, UnaryOperator};
+
+use crate::discovery::database::DisRules;
+use crate::handler::database::mysql::merge::{compare_rows, compare_values, RowStream, SortKey, SortKind};
+use crate::handler::database::mysql::rdbc::ExecuteResult;
+use crate::handler::database::parser::sql::{DerivedColumn, SQLStatementContext};
+use crate::protocol::database::mysql::constant::MySQLColumnType;
+use crate::protocol::database::mysql::resultset::{Column, Value};
+
+pub type AggregateResult<T> = data_panel_common::common::Result<T>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFunction {
+    /// The mergeable aggregate `expr` is a call of, `None` for anything else.
+    pub fn of(expr: &Expr) -> Option<Self> {
+        let function = match expr {
+            Expr::Function(function) if function.over.is_none() => function,
+            _ => return None,
+        };
+        let name = function.name.0.last()?.value.to_uppercase();
+        match name.as_str() {
+            "COUNT" => Some(AggregateFunction::Count),
+            "SUM" => Some(AggregateFunction::Sum),
+            "MIN" => Some(AggregateFunction::Min),
+            "MAX" => Some(AggregateFunction::Max),
+            "AVG" => Some(AggregateFunction::Avg),
+            _ => None,
+        }
+    }
+}
+
+/// Where a value of the merged rows comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    /// An item of the original select list.
+    Projection(usize),
+    /// One of the derived columns appended after it.
+    Derived(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Aggregate {
+    function: AggregateFunction,
+    position: Position,
+    /// Derived `SUM` and `COUNT` of an `AVG`.
+    avg_parts: Option<(Position, Position)>,
+    /// Derived arguments of `COUNT(DISTINCT ...)`, `SUM(DISTINCT ...)` and `AVG(DISTINCT ...)`,
+    /// whose distinct values are collected by the merge.
+    distinct_args: Vec<Position>,
+}
+
+/// How the rows of the segments are regrouped when the outermost query aggregates: the
+/// derived columns the segments have to return, the partial aggregates to combine,
+/// and `HAVING` / `ORDER BY` applied to the merged groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregationPlan {
+    projection: Vec<SelectItem>,
+    derived_columns: Vec<DerivedColumn>,
+    derived_group_by: Vec<Expr>,
+    /// The only dis key of the distributed tables: each of its values lives on one segment.
+    partition_key: Option<String>,
+    aggregates: Vec<Aggregate>,
+    group_by: Vec<Position>,
+    having: Option<Expr>,
+    /// Columns and aggregates `HAVING` refers to.
+    having_positions: Vec<(Expr, Position)>,
+    order_by: Vec<(OrderByExpr, Position)>,
+}
+
+impl AggregationPlan {
+    /// The plan for the analysed statement, `None` unless it is a `SELECT` with
+    /// `GROUP BY` or aggregates in its select list or `HAVING`.
+    pub fn new(stmt_ctx: &SQLStatementContext, dis_rules: &DisRules) -> AggregateResult<Option<Self>> {
+        let projection = stmt_ctx.get_projection();
+        let has_aggregates = projection.iter().any(|item| match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => AggregateFunction::of(expr).is_some(),
+            _ => false,
+        });
+        if stmt_ctx.get_group_by().is_empty() && stmt_ctx.get_having().is_none() && !has_aggregates {
+            return Ok(None);
+        }
+
+        let mut plan = AggregationPlan {
+            projection: projection.to_vec(),
+            derived_columns: vec![],
+            derived_group_by: vec![],
+            partition_key: partition_key(stmt_ctx, dis_rules),
+            aggregates: vec![],
+            group_by: vec![],
+            having: stmt_ctx.get_having().cloned(),
+            having_positions: vec![],
+            order_by: vec![],
+        };
+        for (index, item) in projection.iter().enumerate() {
+            let expr = match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => expr,
+                _ => continue,
+            };
+            plan.add_aggregate(expr, Position::Projection(index))?;
+        }
+        for expr in stmt_ctx.get_group_by() {
+            let position = plan.position(expr, "GROUP_BY");
+            plan.group_by.push(position);
+        }
+        if let Some(having) = stmt_ctx.get_having() {
+            plan.add_having_positions(having)?;
+        }
+        for order_by_expr in stmt_ctx.get_order_by() {
+            let position = plan.position(&order_by_expr.expr, "ORDER_BY");
+            plan.add_aggregate(&order_by_expr.expr, position)?;
+            plan.order_by.push((order_by_expr.clone(), position));
+        }
+        Ok(Some(plan))
+    }
+
+    pub fn get_derived_columns(&self) -> &[DerivedColumn] {
+        self.derived_columns.as_slice()
+    }
+
+    pub fn get_derived_group_by(&self) -> &[Expr] {
+        self.derived_group_by.as_slice()
+    }
+
+    pub fn has_having(&self) -> bool {
+        self.having.is_some()
+    }
+
+    fn add_aggregate(&mut self, expr: &Expr, position: Position) -> AggregateResult<()> {
+        let function = match AggregateFunction::of(expr) {
+            Some(function) => function,
+            None => return Ok(()),
+        };
+        if self.aggregates.iter().any(|a| a.position == position) {
+            return Ok(());
+        }
+        let function_call = match expr {
+            Expr::Function(function_call) => function_call,
+            _ => return Ok(()),
+        };
+        let args: Vec<&Expr> = function_call.args.iter()
+            .map(|arg| match arg {
+                FunctionArg::Named { arg, .. } => arg,
+                FunctionArg::Unnamed(arg) => arg,
+            })
+            .collect();
+        // The distinct values of the partition key on different segments are different,
+        // so the segments' results combine like those of the plain aggregate.
+        let partitioned = args.len() == 1 && self.is_partition_key(args[0]);
+        let mut distinct_args = vec![];
+        if function_call.distinct && !partitioned && function != AggregateFunction::Min && function != AggregateFunction::Max {
+            if function != AggregateFunction::Count && args.len() != 1 {
+                return Err(data_panel_common::common::Error::General(format!("{} can not be merged across data segments", expr)));
+            }
+            for arg in args {
+                distinct_args.push(self.derive(arg.clone(), "DISTINCT"));
+                if !self.derived_group_by.contains(arg) {
+                    self.derived_group_by.push(arg.clone());
+                }
+            }
+        }
+        let avg_parts = if function == AggregateFunction::Avg && distinct_args.is_empty() {
+            let mut sum = function_call.clone();
+            sum.name.0.last_mut().unwrap().value = String::from("SUM");
+            let mut count = function_call.clone();
+            count.name.0.last_mut().unwrap().value = String::from("COUNT");
+            let sum_position = self.derive(Expr::Function(sum), "AVG_SUM");
+            let count_position = self.derive(Expr::Function(count), "AVG_COUNT");
+            Some((sum_position, count_position))
+        } else {
+            None
+        };
+        self.aggregates.push(Aggregate {
+            function,
+            position,
+            avg_parts,
+            distinct_args,
+        });
+        Ok(())
+    }
+
+    fn add_having_positions(&mut self, expr: &Expr) -> AggregateResult<()> {
+        match expr {
+            Expr::Identifier(ident) if ident.value == "?" => {
+                Err(data_panel_common::common::Error::General(String::from("parameters in HAVING are not supported across data segments")))
+            }
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) | Expr::Function(_) => {
+                let position = self.position(expr, "HAVING");
+                self.add_aggregate(expr, position)?;
+                self.having_positions.push((expr.clone(), position));
+                Ok(())
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.add_having_positions(left)?;
+                self.add_having_positions(right)
+            }
+            Expr::UnaryOp { expr, .. } | Expr::Nested(expr) | Expr::IsNull(expr) | Expr::IsNotNull(expr) => {
+                self.add_having_positions(expr)
+            }
+            Expr::Between { expr, low, high, .. } => {
+                self.add_having_positions(expr)?;
+                self.add_having_positions(low)?;
+                self.add_having_positions(high)
+            }
+            Expr::InList { expr, list, .. } => {
+                self.add_having_positions(expr)?;
+                for item in list {
+                    self.add_having_positions(item)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn is_partition_key(&self, expr: &Expr) -> bool {
+        self.partition_key.as_ref().map(|key| is_column(expr, key)).unwrap_or(false)
+    }
+
+    /// The select list item `expr` refers to, by position, alias or the same expression,
+    /// or a new derived column for it.
+    fn position(&mut self, expr: &Expr, kind: &str) -> Position {
+        if let Expr::Value(sqlparser::ast::Value::Number(n, _)) = expr {
+            if let Ok(n) = n.parse::<usize>() {
+                if n >= 1 && n <= self.projection.len() {
+                    return Position::Projection(n - 1);
+                }
+            }
+        }
+        let found = self.projection.iter().position(|item| match item {
+            SelectItem::UnnamedExpr(item_expr) => same_column(item_expr, expr),
+            SelectItem::ExprWithAlias { expr: item_expr, alias } => {
+                same_column(item_expr, expr) || match expr {
+                    Expr::Identifier(ident) => ident.value.eq_ignore_ascii_case(alias.value.as_str()),
+                    _ => false,
+                }
+            }
+            _ => false,
+        });
+        match found {
+            Some(index) => Position::Projection(index),
+            None => self.derive(expr.clone(), kind),
+        }
+    }
+
+    fn derive(&mut self, expr: Expr, kind: &str) -> Position {
+        if let Some(index) = self.derived_columns.iter().position(|d| *d.get_expr() == expr) {
+            return Position::Derived(index);
+        }
+        let index = self.derived_columns.len();
+        self.derived_columns.push(DerivedColumn::new(expr, format!("DP_DERIVED_{}_{}", kind, index)));
+        Position::Derived(index)
+    }
+
+    /// Column index of a position in the rows of the segments, which have the derived
+    /// columns last. A wildcard in the select list takes the columns left over.
+    fn column_index(&self, position: Position, column_count: usize) -> ExecuteResult<usize> {
+        let visible_count = column_count.saturating_sub(self.derived_columns.len());
+        let index = match position {
+            Position::Derived(index) => Some(visible_count + index),
+            Position::Projection(index) => {
+                let wildcards = self.projection.iter()
+                    .filter(|item| matches!(item, SelectItem::Wildcard | SelectItem::QualifiedWildcard(_)))
+                    .count();
+                if wildcards == 0 {
+                    Some(index)
+                } else if wildcards == 1 {
+                    let expansion = (visible_count + 1).checked_sub(self.projection.len());
+                    let mut column_index = 0;
+                    for item in &self.projection[..index] {
+                        column_index += match item {
+                            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => expansion.unwrap_or(0),
+                            _ => 1,
+                        };
+                    }
+                    expansion.map(|_| column_index)
+                } else if self.projection[..index].iter().any(|item| matches!(item, SelectItem::Wildcard | SelectItem::QualifiedWildcard(_))) {
+                    None
+                } else {
+                    Some(index)
+                }
+            }
+        };
+        match index {
+            Some(index) if index < column_count => Ok(index),
+            _ => Err((10000, String::from("HY000"), String::from("can not locate the aggregated columns in the merged result set"))),
+        }
+    }
+
+    /// Regroup the rows of all segments, combine their partial aggregates, filter the
+    /// groups by `HAVING`, sort them by `ORDER BY` and drop the derived columns.
+    pub fn merge(&self, columns: Vec<Column>, streams: Vec<RowStream>, max_distinct_rows: u64) -> ExecuteResult<(Vec<Column>, Vec<Vec<Value>>)> {
+        let column_count = columns.len();
+        let mut group_by = Vec::with_capacity(self.group_by.len());
+        for position in self.group_by.iter() {
+            let index = self.column_index(*position, column_count)?;
+            group_by.push((index, SortKind::of(&columns[index])));
+        }
+        let mut aggregates = Vec::with_capacity(self.aggregates.len());
+        for aggregate in self.aggregates.iter() {
+            let index = self.column_index(aggregate.position, column_count)?;
+            let avg_parts = match aggregate.avg_parts {
+                Some((sum, count)) => Some((self.column_index(sum, column_count)?, self.column_index(count, column_count)?)),
+                None => None,
+            };
+            let mut distinct_args = Vec::with_capacity(aggregate.distinct_args.len());
+            for position in aggregate.distinct_args.iter() {
+                let arg_index = self.column_index(*position, column_count)?;
+                distinct_args.push((arg_index, SortKind::of(&columns[arg_index])));
+            }
+            aggregates.push((aggregate.function, index, avg_parts, SortKind::of(&columns[index]), distinct_args));
+        }
+        let mut distinct_values: u64 = 0;
+        let new_accumulators = || -> Vec<Accumulator> {
+            aggregates.iter()
+                .map(|(function, _, _, _, distinct_args)| {
+                    if distinct_args.is_empty() { Accumulator::new(*function) } else { Accumulator::distinct(*function) }
+                })
+                .collect()
+        };
+
+        let mut groups: Vec<(Vec<Value>, Vec<Accumulator>)> = Vec::new();
+        let mut group_indexes: HashMap<Vec<Option<Vec<u8>>>, usize> = HashMap::new();
+        for row in streams.into_iter().flatten() {
+            let row = row?;
+            let key: Vec<Option<Vec<u8>>> = group_by.iter()
+                .map(|(index, kind)| group_key(row.get(*index).unwrap_or(&Value::NULL), *kind))
+                .collect();
+            let group_index = match group_indexes.get(&key) {
+                Some(group_index) => *group_index,
+                None => {
+                    groups.push((row.clone(), new_accumulators()));
+                    group_indexes.insert(key, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+            let accumulators = &mut groups[group_index].1;
+            for ((_, index, avg_parts, kind, distinct_args), accumulator) in aggregates.iter().zip(accumulators.iter_mut()) {
+                if !distinct_args.is_empty() {
+                    let values: Vec<&Value> = distinct_args.iter()
+                        .map(|(arg_index, _)| row.get(*arg_index).unwrap_or(&Value::NULL))
+                        .collect();
+                    let kinds: Vec<SortKind> = distinct_args.iter().map(|(_, kind)| *kind).collect();
+                    if accumulator.add_distinct(&values, &kinds) {
+                        distinct_values += 1;
+                        if max_distinct_rows > 0 && distinct_values > max_distinct_rows {
+                            return Err(too_many_distinct_rows(max_distinct_rows));
+                        }
+                    }
+                    continue;
+                }
+                match avg_parts {
+                    Some((sum, count)) => {
+                        accumulator.add_avg(row.get(*sum).unwrap_or(&Value::NULL), row.get(*count).unwrap_or(&Value::NULL));
+                    }
+                    None => accumulator.add(row.get(*index).unwrap_or(&Value::NULL), *kind),
+                }
+            }
+        }
+
+        // Without GROUP BY there is one group even when no segment returned a row, as when
+        // the segments group by the arguments of DISTINCT aggregates: COUNT is 0, the other
+        // aggregates and the plain columns NULL.
+        if self.group_by.is_empty() && groups.is_empty() {
+            let mut row = vec![Value::NULL; column_count];
+            for (_, index, _, _, _) in aggregates.iter() {
+                row[*index] = zero(&columns[*index]);
+            }
+            groups.push((row, new_accumulators()));
+        }
+
+        let mut rows = Vec::with_capacity(groups.len());
+        for (mut row, accumulators) in groups {
+            for ((_, index, _, _, _), accumulator) in aggregates.iter().zip(accumulators.into_iter()) {
+                let template = row.get(*index).cloned().unwrap_or(Value::NULL);
+                row[*index] = accumulator.finish(&template, columns[*index].get_decimals());
+            }
+            rows.push(row);
+        }
+
+        if let Some(ref having) = self.having {
+            let mut having_columns = Vec::with_capacity(self.having_positions.len());
+            for (expr, position) in self.having_positions.iter() {
+                having_columns.push((expr, self.column_index(*position, column_count)?));
+            }
+            let mut filtered = Vec::with_capacity(rows.len());
+            for row in rows {
+                if eval(having, &row, &having_columns)?.is_true() {
+                    filtered.push(row);
+                }
+            }
+            rows = filtered;
+        }
+
+        if !self.order_by.is_empty() {
+            let mut sort_keys = Vec::with_capacity(self.order_by.len());
+            for (order_by_expr, position) in self.order_by.iter() {
+                let index = self.column_index(*position, column_count)?;
+                sort_keys.push(SortKey::new(index, SortKind::of(&columns[index]), order_by_expr.asc.unwrap_or(true), order_by_expr.nulls_first));
+            }
+            rows.sort_by(|a, b| compare_rows(a, b, &sort_keys));
+        }
+
+        let visible_count = column_count.saturating_sub(self.derived_columns.len());
+        let mut columns = columns;
+        columns.truncate(visible_count);
+        for row in rows.iter_mut() {
+            row.truncate(visible_count);
+        }
+        Ok((columns, rows))
+    }
+}
+
+/// A value of an aggregate column for the partial aggregates to take the type of, when no
+/// segment returned one.
+fn zero(column: &Column) -> Value {
+    match column.get_column_type() {
+        MySQLColumnType::MysqlTypeTiny
+        | MySQLColumnType::MysqlTypeShort
+        | MySQLColumnType::MysqlTypeLong
+        | MySQLColumnType::MysqlTypeInt24
+        | MySQLColumnType::MysqlTypeLonglong if column.is_unsigned() => Value::UInt(0),
+        MySQLColumnType::MysqlTypeTiny
+        | MySQLColumnType::MysqlTypeShort
+        | MySQLColumnType::MysqlTypeLong
+        | MySQLColumnType::MysqlTypeInt24
+        | MySQLColumnType::MysqlTypeLonglong => Value::Int(0),
+        MySQLColumnType::MysqlTypeFloat => Value::Float(0.0),
+        MySQLColumnType::MysqlTypeDouble => Value::Double(0.0),
+        _ => Value::Bytes(b"0".to_vec()),
+    }
+}
+
+/// The dis key shared by all distributed tables of the statement, if they have only one.
+fn partition_key(stmt_ctx: &SQLStatementContext, dis_rules: &DisRules) -> Option<String> {
+    let mut partition_key: Option<String> = None;
+    for table in stmt_ctx.get_table_names() {
+        let dis_table = match dis_rules.get_distributed_table(table.as_str()) {
+            Some(dis_table) => dis_table,
+            None => continue,
+        };
+        if dis_table.get_dis_keys().len() != 1 {
+            return None;
+        }
+        let dis_key = &dis_table.get_dis_keys()[0];
+        match partition_key {
+            Some(ref key) if !key.eq_ignore_ascii_case(dis_key) => return None,
+            Some(_) => {}
+            None => partition_key = Some(dis_key.clone()),
+        }
+    }
+    partition_key
+}
+
+/// Whether the select list contains the partition key, so the rows of different
+/// segments are never equal and `DISTINCT` needs no merge.
+pub fn is_partitioned_projection(stmt_ctx: &SQLStatementContext, dis_rules: &DisRules) -> bool {
+    let partition_key = match partition_key(stmt_ctx, dis_rules) {
+        Some(partition_key) => partition_key,
+        None => return false,
+    };
+    stmt_ctx.get_projection().iter().any(|item| match item {
+        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => is_column(expr, partition_key.as_str()),
+        _ => false,
+    })
+}
+
+fn is_column(expr: &Expr, column: &str) -> bool {
+    match expr {
+        Expr::Identifier(ident) => ident.value.eq_ignore_ascii_case(column),
+        Expr::CompoundIdentifier(idents) => idents.last().map(|ident| ident.value.eq_ignore_ascii_case(column)).unwrap_or(false),
+        _ => false,
+    }
+}
+
+pub fn too_many_distinct_rows(max_distinct_rows: u64) -> (u32, String, String) {
+    (1038, String::from("HY001"), format!("more than max_distinct_rows {} distinct rows to merge", max_distinct_rows))
+}
+
+/// Whether two expressions name the same column, ignoring the qualifier of one of them.
+pub fn same_column(a: &Expr, b: &Expr) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a, b) {
+        (Expr::CompoundIdentifier(idents), Expr::Identifier(ident))
+        | (Expr::Identifier(ident), Expr::CompoundIdentifier(idents)) => {
+            idents.last().map(|last| last.value.eq_ignore_ascii_case(ident.value.as_str())).unwrap_or(false)
+        }
+        (Expr::Identifier(a), Expr::Identifier(b)) => a.value.eq_ignore_ascii_case(b.value.as_str()),
+        _ => false,
+    }
+}
+
+/// The value rows are grouped by: NULLs group together, strings of a `_ci` collation
+/// ignoring case and trailing spaces, numbers by value.
+pub fn group_key(value: &Value, kind: SortKind) -> Option<Vec<u8>> {
+    match value {
+        Value::NULL => None,
+        Value::Bytes(bytes) => match kind {
+            SortKind::Collated => Some(String::from_utf8_lossy(bytes).trim_end_matches(' ').to_lowercase().into_bytes()),
+            SortKind::Numeric => match Numeric::parse(bytes) {
+                Some(numeric) => Some(numeric.normalize().to_string().into_bytes()),
+                None => Some(bytes.clone()),
+            },
+            SortKind::Binary => Some(bytes.clone()),
+        },
+        Value::Int(i) => Some(i.to_string().into_bytes()),
+        Value::UInt(u) => Some(u.to_string().into_bytes()),
+        value => Some(value.to_sql().into_bytes()),
+    }
+}
+
+/// An exact decimal as integer mantissa and scale, or a double.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Numeric {
+    Decimal(i128, u32),
+    Double(f64),
+}
+
+impl Numeric {
+    fn parse(bytes: &[u8]) -> Option<Numeric> {
+        let text = std::str::from_utf8(bytes).ok()?.trim();
+        if text.contains(|c| c == 'e' || c == 'E') {
+            return text.parse::<f64>().ok().map(Numeric::Double);
+        }
+        let (integer, fraction) = match text.find('.') {
+            Some(i) => (&text[..i], &text[i + 1..]),
+            None => (text, ""),
+        };
+        match format!("{}{}", integer, fraction).parse::<i128>() {
+            Ok(mantissa) => Some(Numeric::Decimal(mantissa, fraction.len() as u32)),
+            Err(_) => text.parse::<f64>().ok().map(Numeric::Double),
+        }
+    }
+
+    fn of(value: &Value) -> Option<Numeric> {
+        match value {
+            Value::Int(i) => Some(Numeric::Decimal(*i as i128, 0)),
+            Value::UInt(u) => Some(Numeric::Decimal(*u as i128, 0)),
+            Value::Float(f) => Some(Numeric::Double(*f as f64)),
+            Value::Double(f) => Some(Numeric::Double(*f)),
+            Value::Bytes(bytes) => Numeric::parse(bytes),
+            _ => None,
+        }
+    }
+
+    fn add(self, other: Numeric) -> Numeric {
+        match (self, other) {
+            (Numeric::Decimal(a, a_scale), Numeric::Decimal(b, b_scale)) => {
+                let scale = a_scale.max(b_scale);
+                let a = a.checked_mul(10i128.pow(scale - a_scale));
+                let b = b.checked_mul(10i128.pow(scale - b_scale));
+                match (a, b) {
+                    (Some(a), Some(b)) => match a.checked_add(b) {
+                        Some(sum) => Numeric::Decimal(sum, scale),
+                        None => Numeric::Double(self.to_f64() + other.to_f64()),
+                    },
+                    _ => Numeric::Double(self.to_f64() + other.to_f64()),
+                }
+            }
+            _ => Numeric::Double(self.to_f64() + other.to_f64()),
+        }
+    }
+
+    /// `self / count` with 4 more decimals, like MySQL's `div_precision_increment`.
+    fn div(self, count: i128) -> Numeric {
+        match self {
+            Numeric::Decimal(mantissa, scale) => {
+                let scaled = match mantissa.checked_mul(10_000) {
+                    Some(scaled) => scaled,
+                    None => return Numeric::Double(self.to_f64() / count as f64),
+                };
+                let quotient = scaled / count;
+                let remainder = scaled % count;
+                let rounded = if remainder.abs() * 2 >= count.abs() {
+                    quotient + if (scaled < 0) != (count < 0) { -1 } else { 1 }
+                } else {
+                    quotient
+                };
+                Numeric::Decimal(rounded, scale + 4)
+            }
+            Numeric::Double(f) => Numeric::Double(f / count as f64),
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            Numeric::Decimal(mantissa, scale) => mantissa as f64 / 10f64.powi(scale as i32),
+            Numeric::Double(f) => f,
+        }
+    }
+
+    fn normalize(self) -> Numeric {
+        match self {
+            Numeric::Decimal(mut mantissa, mut scale) => {
+                while scale > 0 && mantissa % 10 == 0 {
+                    mantissa /= 10;
+                    scale -= 1;
+                }
+                Numeric::Decimal(mantissa, scale)
+            }
+            numeric => numeric,
+        }
+    }
+
+    /// Rescale to `decimals` digits, rounding half away from zero.
+    fn with_scale(self, decimals: u32) -> Numeric {
+        match self {
+            Numeric::Decimal(mantissa, scale) if scale > decimals => {
+                let divisor = 10i128.pow(scale - decimals);
+                let quotient = mantissa / divisor;
+                let remainder = mantissa % divisor;
+                let rounded = if remainder.abs() * 2 >= divisor { quotient + mantissa.signum() } else { quotient };
+                Numeric::Decimal(rounded, decimals)
+            }
+            Numeric::Decimal(mantissa, scale) if scale < decimals => {
+                match mantissa.checked_mul(10i128.pow(decimals - scale)) {
+                    Some(mantissa) => Numeric::Decimal(mantissa, decimals),
+                    None => self,
+                }
+            }
+            numeric => numeric,
+        }
+    }
+
+    /// The value in the representation the segments used for the column.
+    fn to_value(self, template: &Value) -> Value {
+        match (template, self) {
+            (Value::Int(_), Numeric::Decimal(mantissa, 0)) => Value::Int(mantissa as i64),
+            (Value::UInt(_), Numeric::Decimal(mantissa, 0)) => Value::UInt(mantissa as u64),
+            (Value::Int(_), numeric) | (Value::UInt(_), numeric) | (Value::Double(_), numeric) => Value::Double(numeric.to_f64()),
+            (Value::Float(_), numeric) => Value::Float(numeric.to_f64() as f32),
+            (_, numeric) => Value::Bytes(numeric.to_string().into_bytes()),
+        }
+    }
+}
+
+impl std::fmt::Display for Numeric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Numeric::Decimal(mantissa, 0) => write!(f, "{}", mantissa),
+            Numeric::Decimal(mantissa, scale) => {
+                let digits = format!("{:0width$}", mantissa.abs(), width = *scale as usize + 1);
+                let (integer, fraction) = digits.split_at(digits.len() - *scale as usize);
+                write!(f, "{}{}.{}", if *mantissa < 0 { "-" } else { "" }, integer, fraction)
+            }
+            Numeric::Double(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+/// Combines the partial aggregates of one group.
+enum Accumulator {
+    Count(Option<Numeric>),
+    Sum(Option<Numeric>),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg(Option<Numeric>, i128),
+    /// The distinct argument values seen, with the sum and count of them.
+    Distinct(AggregateFunction, HashSet<Vec<Option<Vec<u8>>>>, Option<Numeric>, i128),
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(None),
+            AggregateFunction::Sum => Accumulator::Sum(None),
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+            AggregateFunction::Avg => Accumulator::Avg(None, 0),
+        }
+    }
+
+    fn distinct(function: AggregateFunction) -> Self {
+        Accumulator::Distinct(function, HashSet::new(), None, 0)
+    }
+
+    /// Add the arguments of a distinct aggregate, true if they were not seen before.
+    fn add_distinct(&mut self, values: &[&Value], kinds: &[SortKind]) -> bool {
+        if let Accumulator::Distinct(_, seen, total, count) = self {
+            if values.iter().any(|value| **value == Value::NULL) {
+                return false;
+            }
+            let key = values.iter().zip(kinds.iter()).map(|(value, kind)| group_key(value, *kind)).collect();
+            if !seen.insert(key) {
+                return false;
+            }
+            *count += 1;
+            if let Some(numeric) = Numeric::of(values[0]) {
+                *total = Some(match total.take() {
+                    Some(total) => total.add(numeric),
+                    None => numeric,
+                });
+            }
+            return true;
+        }
+        false
+    }
+
+    fn add(&mut self, value: &Value, kind: SortKind) {
+        if *value == Value::NULL {
+            return;
+        }
+        match self {
+            Accumulator::Count(total) | Accumulator::Sum(total) => {
+                if let Some(numeric) = Numeric::of(value) {
+                    *total = Some(match total.take() {
+                        Some(sum) => sum.add(numeric),
+                        None => numeric,
+                    });
+                }
+            }
+            Accumulator::Min(min) => {
+                if min.as_ref().map(|m| compare_values(value, m, kind) == Ordering::Less).unwrap_or(true) {
+                    *min = Some(value.clone());
+                }
+            }
+            Accumulator::Max(max) => {
+                if max.as_ref().map(|m| compare_values(value, m, kind) == Ordering::Greater).unwrap_or(true) {
+                    *max = Some(value.clone());
+                }
+            }
+            Accumulator::Avg(..) | Accumulator::Distinct(..) => {}
+        }
+    }
+
+    fn add_avg(&mut self, sum: &Value, count: &Value) {
+        if let Accumulator::Avg(total, total_count) = self {
+            if let Some(Numeric::Decimal(count, 0)) = Numeric::of(count) {
+                *total_count += count;
+            }
+            if let Some(numeric) = Numeric::of(sum) {
+                *total = Some(match total.take() {
+                    Some(total) => total.add(numeric),
+                    None => numeric,
+                });
+            }
+        }
+    }
+
+    fn finish(self, template: &Value, decimals: u8) -> Value {
+        match self {
+            Accumulator::Count(total) => total.unwrap_or(Numeric::Decimal(0, 0)).to_value(template),
+            Accumulator::Sum(total) => match total {
+                Some(total) => total.to_value(template),
+                None => Value::NULL,
+            },
+            Accumulator::Min(value) | Accumulator::Max(value) => value.unwrap_or(Value::NULL),
+            Accumulator::Avg(total, count) => match total {
+                Some(total) if count > 0 => {
+                    let avg = total.div(count);
+                    let avg = if decimals < 31 { avg.with_scale(decimals as u32) } else { avg };
+                    avg.to_value(template)
+                }
+                _ => Value::NULL,
+            },
+            Accumulator::Distinct(function, _, total, count) => match function {
+                AggregateFunction::Count => Numeric::Decimal(count, 0).to_value(template),
+                AggregateFunction::Avg => match total {
+                    Some(total) if count > 0 => {
+                        let avg = total.div(count);
+                        let avg = if decimals < 31 { avg.with_scale(decimals as u32) } else { avg };
+                        avg.to_value(template)
+                    }
+                    _ => Value::NULL,
+                },
+                _ => match total {
+                    Some(total) => total.to_value(template),
+                    None => Value::NULL,
+                },
+            },
+        }
+    }
+}
+
+/// The value of a `HAVING` expression for a merged row.
+#[derive(Debug, Clone, PartialEq)]
+enum Scalar {
+    Null,
+    Number(f64),
+    Text(String),
+}
+
+impl Scalar {
+    fn of(value: &Value) -> Scalar {
+        match value {
+            Value::NULL => Scalar::Null,
+            Value::Bytes(bytes) => {
+                let text = String::from_utf8_lossy(bytes).to_string();
+                match text.trim().parse::<f64>() {
+                    Ok(f) => Scalar::Number(f),
+                    Err(_) => Scalar::Text(text),
+                }
+            }
+            value => match Numeric::of(value) {
+                Some(numeric) => Scalar::Number(numeric.to_f64()),
+                None => Scalar::Text(value.to_sql()),
+            },
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Scalar::Number(f) => Some(*f),
+            Scalar::Text(text) => text.trim().parse::<f64>().ok(),
+            Scalar::Null => None,
+        }
+    }
+
+    fn boolean(b: bool) -> Scalar {
+        Scalar::Number(if b { 1.0 } else { 0.0 })
+    }
+
+    fn is_true(&self) -> bool {
+        self.number().map(|f| f != 0.0).unwrap_or(false)
+    }
+
+    fn compare(&self, other: &Scalar) -> Option<Ordering> {
+        match (self, other) {
+            (Scalar::Null, _) | (_, Scalar::Null) => None,
+            (Scalar::Text(a), Scalar::Text(b)) => {
+                Some(compare_values(&Value::Bytes(a.clone().into_bytes()), &Value::Bytes(b.clone().into_bytes()), SortKind::Collated))
+            }
+            (a, b) => match (a.number(), b.number()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn eval(expr: &Expr, row: &[Value], columns: &[(&Expr, usize)]) -> ExecuteResult<Scalar> {
+    if let Some((_, index)) = columns.iter().find(|(column_expr, _)| *column_expr == expr) {
+        return Ok(Scalar::of(row.get(*index).unwrap_or(&Value::NULL)));
+    }
+    let scalar = match expr {
+        Expr::Value(sqlparser::ast::Value::Number(n, _)) => Scalar::Number(n.parse::<f64>().unwrap_or(0.0)),
+        Expr::Value(sqlparser::ast::Value::SingleQuotedString(s)) => Scalar::Text(s.clone()),
+        Expr::Value(sqlparser::ast::Value::Boolean(b)) => Scalar::boolean(*b),
+        Expr::Value(sqlparser::ast::Value::Null) => Scalar::Null,
+        Expr::Nested(expr) => eval(expr, row, columns)?,
+        Expr::IsNull(expr) => Scalar::boolean(eval(expr, row, columns)? == Scalar::Null),
+        Expr::IsNotNull(expr) => Scalar::boolean(eval(expr, row, columns)? != Scalar::Null),
+        Expr::UnaryOp { op, expr } => {
+            let value = eval(expr, row, columns)?;
+            match (op, value.number()) {
+                (_, None) => Scalar::Null,
+                (UnaryOperator::Not, Some(f)) => Scalar::boolean(f == 0.0),
+                (UnaryOperator::Minus, Some(f)) => Scalar::Number(-f),
+                (UnaryOperator::Plus, Some(f)) => Scalar::Number(f),
+                _ => return Err(unsupported_having(expr)),
+            }
+        }
+        Expr::Between { expr, negated, low, high } => {
+            let value = eval(expr, row, columns)?;
+            let low = value.compare(&eval(low, row, columns)?);
+            let high = value.compare(&eval(high, row, columns)?);
+            match (low, high) {
+                (Some(low), Some(high)) => Scalar::boolean((low != Ordering::Less && high != Ordering::Greater) != *negated),
+                _ => Scalar::Null,
+            }
+        }
+        Expr::InList { expr, list, negated } => {
+            let value = eval(expr, row, columns)?;
+            let mut found = false;
+            for item in list {
+                if value.compare(&eval(item, row, columns)?) == Some(Ordering::Equal) {
+                    found = true;
+                    break;
+                }
+            }
+            if value == Scalar::Null { Scalar::Null } else { Scalar::boolean(found != *negated) }
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let left = eval(left, row, columns)?;
+            let right = eval(right, row, columns)?;
+            match op {
+                BinaryOperator::And => match (left.number(), right.number()) {
+                    (Some(l), _) if l == 0.0 => Scalar::boolean(false),
+                    (_, Some(r)) if r == 0.0 => Scalar::boolean(false),
+                    (Some(_), Some(_)) => Scalar::boolean(true),
+                    _ => Scalar::Null,
+                },
+                BinaryOperator::Or => match (left.number(), right.number()) {
+                    (Some(l), _) if l != 0.0 => Scalar::boolean(true),
+                    (_, Some(r)) if r != 0.0 => Scalar::boolean(true),
+                    (Some(_), Some(_)) => Scalar::boolean(false),
+                    _ => Scalar::Null,
+                },
+                BinaryOperator::Eq | BinaryOperator::NotEq | BinaryOperator::Gt | BinaryOperator::Lt
+                | BinaryOperator::GtEq | BinaryOperator::LtEq => match left.compare(&right) {
+                    Some(ordering) => Scalar::boolean(match op {
+                        BinaryOperator::Eq => ordering == Ordering::Equal,
+                        BinaryOperator::NotEq => ordering != Ordering::Equal,
+                        BinaryOperator::Gt => ordering == Ordering::Greater,
+                        BinaryOperator::Lt => ordering == Ordering::Less,
+                        BinaryOperator::GtEq => ordering != Ordering::Less,
+                        _ => ordering != Ordering::Greater,
+                    }),
+                    None => Scalar::Null,
+                },
+                BinaryOperator::Plus | BinaryOperator::Minus | BinaryOperator::Multiply
+                | BinaryOperator::Divide | BinaryOperator::Modulus => match (left.number(), right.number()) {
+                    (Some(l), Some(r)) => match op {
+                        BinaryOperator::Plus => Scalar::Number(l + r),
+                        BinaryOperator::Minus => Scalar::Number(l - r),
+                        BinaryOperator::Multiply => Scalar::Number(l * r),
+                        _ if r == 0.0 => Scalar::Null,
+                        BinaryOperator::Divide => Scalar::Number(l / r),
+                        _ => Scalar::Number(l % r),
+                    },
+                    _ => Scalar::Null,
+                },
+                _ => return Err(unsupported_having(expr)),
+            }
+        }
+        _ => return Err(unsupported_having(expr)),
+    };
+    Ok(scalar)
+}
+
+fn unsupported_having(expr: &Expr) -> (u32, String, String) {
+    (1235, String::from("42000"), format!("'{}' in HAVING is not supported across data segments", expr))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::aggregate::{Accumulator, AggregateFunction, AggregationPlan, Numeric};
+    use crate::handler::database::mysql::merge::SortKind;
+    use crate::handler::database::parser::sql::route::tests::with_route_ctx;
+    use crate::protocol::database::mysql::constant::MySQLColumnType;
+    use crate::protocol::database::mysql::resultset::{Column, Value};
+
+    fn plan(sql: &str) -> Option<AggregationPlan> {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        with_route_ctx(&cluster, sql, &[], |route_ctx, stmt_ctx| {
+            AggregationPlan::new(stmt_ctx, route_ctx.get_cluster().get_dis_rules()).unwrap()
+        })
+    }
+
+    #[test]
+    fn test_aggregation_plan() {
+        assert!(plan("SELECT * FROM t_order ORDER BY order_id").is_none());
+
+        let plan = plan("SELECT user_id, AVG(amount) AS a FROM t_order GROUP BY user_id, status HAVING SUM(amount) > 10 ORDER BY a").unwrap();
+        let derived: Vec<String> = plan.get_derived_columns().iter()
+            .map(|d| format!("{} AS {}", d.get_expr(), d.get_alias()))
+            .collect();
+        assert_eq!(vec!["SUM(amount) AS DP_DERIVED_AVG_SUM_0",
+                        "COUNT(amount) AS DP_DERIVED_AVG_COUNT_1",
+                        "status AS DP_DERIVED_GROUP_BY_2"], derived);
+        assert!(plan.has_having());
+    }
+
+    #[test]
+    fn test_distinct_aggregation_plan() {
+        let distinct = plan("SELECT user_id, COUNT(DISTINCT status), SUM(amount) FROM t_order GROUP BY user_id").unwrap();
+        assert_eq!("status", distinct.get_derived_group_by()[0].to_string());
+        assert_eq!("DP_DERIVED_DISTINCT_0", distinct.get_derived_columns()[0].get_alias());
+
+        // Every user_id lives on one segment, their counts add up.
+        let partitioned = plan("SELECT COUNT(DISTINCT user_id) FROM t_order").unwrap();
+        assert!(partitioned.get_derived_group_by().is_empty());
+        assert!(partitioned.get_derived_columns().is_empty());
+    }
+
+    #[test]
+    fn test_accumulators() {
+        let text = |s: &str| Value::Bytes(s.as_bytes().to_vec());
+
+        let mut sum = Accumulator::new(AggregateFunction::Sum);
+        sum.add(&text("1.25"), SortKind::Numeric);
+        sum.add(&Value::NULL, SortKind::Numeric);
+        sum.add(&text("2.5"), SortKind::Numeric);
+        assert_eq!(text("3.75"), sum.finish(&text("0"), 2));
+
+        let mut count = Accumulator::new(AggregateFunction::Count);
+        count.add(&Value::Int(3), SortKind::Numeric);
+        count.add(&Value::Int(4), SortKind::Numeric);
+        assert_eq!(Value::Int(7), count.finish(&Value::Int(0), 0));
+
+        let mut avg = Accumulator::new(AggregateFunction::Avg);
+        avg.add_avg(&text("10"), &text("4"));
+        avg.add_avg(&text("5"), &text("2"));
+        assert_eq!(text("2.5000"), avg.finish(&text("0"), 4));
+
+        let mut max = Accumulator::new(AggregateFunction::Max);
+        max.add(&text("b"), SortKind::Collated);
+        max.add(&text("C"), SortKind::Collated);
+        max.add(&text("a"), SortKind::Collated);
+        assert_eq!(text("C"), max.finish(&Value::NULL, 0));
+
+        let mut count_distinct = Accumulator::distinct(AggregateFunction::Count);
+        assert!(count_distinct.add_distinct(&[&text("a")], &[SortKind::Collated]));
+        assert!(!count_distinct.add_distinct(&[&text("A ")], &[SortKind::Collated]));
+        assert!(!count_distinct.add_distinct(&[&Value::NULL], &[SortKind::Collated]));
+        assert!(count_distinct.add_distinct(&[&text("b")], &[SortKind::Collated]));
+        assert_eq!(text("2"), count_distinct.finish(&text("1"), 0));
+
+        assert_eq!("-0.05", Numeric::Decimal(-5, 2).to_string());
+    }
+
+    #[test]
+    fn test_merge_without_rows() {
+        // The segments group by the argument of DISTINCT and none has a group, MySQL still
+        // answers with one row.
+        let distinct = plan("SELECT COUNT(DISTINCT status), SUM(DISTINCT amount), MAX(amount), user_id FROM t_order WHERE 1 = 0").unwrap();
+        let columns: Vec<Column> = (0..4 + distinct.get_derived_columns().len())
+            .map(|_| Column::new(MySQLColumnType::MysqlTypeLonglong))
+            .collect();
+        let (columns, rows) = distinct.merge(columns, vec![Box::new(std::iter::empty()), Box::new(std::iter::empty())], 0).unwrap();
+        assert_eq!(4, columns.len());
+        assert_eq!(vec![vec![Value::Int(0), Value::NULL, Value::NULL, Value::NULL]], rows);
+
+        // With GROUP BY there is no group.
+        let grouped = plan("SELECT user_id, COUNT(*) FROM t_order WHERE 1 = 0 GROUP BY user_id").unwrap();
+        let columns: Vec<Column> = (0..2).map(|_| Column::new(MySQLColumnType::MysqlTypeLonglong)).collect();
+        let (_, rows) = grouped.merge(columns, vec![Box::new(std::iter::empty())], 0).unwrap();
+        assert!(rows.is_empty());
+    }
+}
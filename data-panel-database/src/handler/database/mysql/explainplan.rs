@@ -1,7 +1,21 @@
-use bytes::Bytes;
+use async_trait::async_trait;
+use chrono::Local;
+use futures::future::{BoxFuture, FutureExt};
 use sqlparser::ast::Statement;
 
+use crate::handler::database::mysql::aggregate::{AggregationPlan, is_partitioned_projection};
+use crate::handler::database::mysql::federated::FederatedPlan;
+use crate::handler::database::mysql::merge::{MergeContext, order_by_derived_columns, ShardResultSet};
+use crate::handler::database::mysql::metadata::{self, MetadataRows};
+use crate::handler::database::mysql::PayloadStream;
 use crate::handler::database::mysql::rdbc::{bin_query, text_query};
+use crate::handler::database::mysql::relocate::RelocatePlan;
+use crate::handler::database::parser::sql::route::{is_write_statement, pagination, partition, route, RouteContext};
+use crate::handler::database::parser::sql::route::readwrite::{is_primary_statement, transaction_end};
+use crate::handler::database::parser::sql::SQLStatementContext;
+use crate::protocol::database::mysql::constant::MySQLColumnType;
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+use crate::protocol::database::mysql::resultset::{Column, Value};
 
 pub enum TBProtocol {
     Text,
@@ -12,6 +26,8 @@ pub struct ExplainPlanContext<'a> {
     sql: &'a str,
     statement: &'a Statement,
     protocol: TBProtocol,
+    /// The first key the proxy generated for an INSERT, returned as `last_insert_id`.
+    generated_key: Option<u64>,
 }
 
 impl<'a> ExplainPlanContext<'a> {
@@ -22,6 +38,7 @@ impl<'a> ExplainPlanContext<'a> {
             sql,
             statement,
             protocol,
+            generated_key: None,
         }
     }
 
@@ -32,17 +49,87 @@ impl<'a> ExplainPlanContext<'a> {
     pub fn get_statement(&self) -> &'a Statement {
         self.statement
     }
+
+    pub fn set_generated_key(&mut self, generated_key: Option<u64>) {
+        self.generated_key = generated_key;
+    }
+
+    pub fn get_generated_key(&self) -> Option<u64> {
+        self.generated_key
+    }
 }
 
+#[async_trait]
 pub trait Executor {
-    fn execute(&self) -> Option<Vec<Bytes>>;
+    async fn execute(&self) -> Option<PayloadStream>;
 }
 
-pub struct PlanTask {}
+/// The SQL sent to one data segment, with the values of its `?` placeholders.
+#[derive(Clone)]
+pub struct PlanTask {
+    segment_id: u32,
+    sql: String,
+    parameters: Vec<PrepareParamValue>,
+    /// A plain read, which a mirror of the segment may answer.
+    mirror_read: bool,
+}
+
+impl PlanTask {
+    pub fn new(segment_id: u32, sql: String, parameters: Vec<PrepareParamValue>) -> Self {
+        PlanTask {
+            segment_id,
+            sql,
+            parameters,
+            mirror_read: false,
+        }
+    }
+
+    pub fn get_segment_id(&self) -> u32 {
+        self.segment_id
+    }
+
+    pub fn get_sql(&self) -> &str {
+        self.sql.as_str()
+    }
+
+    pub fn get_parameters(&self) -> &[PrepareParamValue] {
+        self.parameters.as_slice()
+    }
+
+    pub fn is_mirror_read(&self) -> bool {
+        self.mirror_read
+    }
+
+    pub fn set_mirror_read(&mut self, mirror_read: bool) {
+        self.mirror_read = mirror_read;
+    }
+}
 
 pub struct ExplainPlan<'a> {
     ctx: &'a ExplainPlanContext<'a>,
     tasks: Vec<PlanTask>,
+    merge_ctx: MergeContext,
+    /// A write to several segments, which has to succeed on all of them or on none.
+    atomic: bool,
+    /// A join of tables on different segments, executed in the proxy instead of the tasks.
+    federated: Option<FederatedPlan>,
+    /// An `UPDATE` of dis keys, which moves the rows instead of executing the tasks.
+    relocate: Option<RelocatePlan>,
+    /// `SHOW TABLES` or `SHOW COLUMNS` of the logical tables, answered by the mesh.
+    metadata: Option<MetadataRows>,
+    /// A DDL statement, executed on every task even when some of them fail.
+    ddl: bool,
+    /// Answer with the steps of the plan instead of executing it.
+    describe: bool,
+    /// Tables of the next period of partitioned tables, created before the statement runs.
+    create_ahead: Vec<PlanTask>,
+    /// The client session the statement came from.
+    session_id: u64,
+    /// The session is in a transaction, the tasks run on the connections it holds.
+    in_transaction: bool,
+    /// Commit (`true`) or roll back the transaction of the session on the segments it holds
+    /// connections to, instead of executing tasks.
+    end_transaction: Option<bool>,
 }
 
 impl<'a> ExplainPlan<'a> {
@@ -50,21 +137,216 @@ impl<'a> ExplainPlan<'a> {
         ExplainPlan {
             ctx: ctx,
             tasks: vec![],
+            merge_ctx: MergeContext::default(),
+            atomic: false,
+            federated: None,
+            relocate: None,
+            metadata: None,
+            ddl: false,
+            describe: false,
+            create_ahead: vec![],
+            session_id: 0,
+            in_transaction: false,
+            end_transaction: None,
+        }
+    }
+
+    /// Plan the statement. `SHOW` and `information_schema` queries of the logical tables read
+    /// the metadata of the data segments, `EXPLAIN VERBOSE` plans the explained statement.
+    pub fn gen<'b>(&'b mut self, route_ctx: &'b RouteContext<'_>, stmt_ctx: &'b SQLStatementContext) -> BoxFuture<'b, data_panel_common::common::Result<()>> {
+        async move { self.gen_statement(route_ctx, stmt_ctx).await }.boxed()
+    }
+
+    async fn gen_statement(&mut self, route_ctx: &RouteContext<'_>, stmt_ctx: &SQLStatementContext) -> data_panel_common::common::Result<()> {
+        self.session_id = route_ctx.get_session_ctx().get_thread_id();
+        self.in_transaction = route_ctx.get_session_ctx().has_transaction();
+        if let Statement::Explain { verbose, statement, .. } = route_ctx.get_statement() {
+            let mut explained_ctx = RouteContext::new(route_ctx.get_cluster(), statement, route_ctx.get_session_ctx(), route_ctx.get_parameters());
+            explained_ctx.set_hint(route_ctx.get_hint());
+            if *verbose {
+                // EXPLAIN VERBOSE shows how the mesh runs the statement.
+                self.describe = true;
+                return self.gen(&explained_ctx, stmt_ctx).await;
+            }
+            // Only the mesh knows how it joins tables on different segments.
+            self.federated = FederatedPlan::new(&explained_ctx, stmt_ctx)?;
+            self.describe = self.federated.is_some();
+            if !self.describe {
+                // The segments explain their part of the statement, their rows are returned as they are.
+                self.tasks = route(route_ctx, stmt_ctx)?;
+            }
+            return Ok(());
         }
+        self.end_transaction = transaction_end(route_ctx.get_statement());
+        if self.end_transaction.is_some() {
+            return Ok(());
+        }
+        self.relocate = RelocatePlan::new(route_ctx, stmt_ctx)?;
+        if self.relocate.is_some() {
+            return Ok(());
+        }
+        self.metadata = metadata::show(route_ctx, self.ctx.get_sql()).await?;
+        if self.metadata.is_some() {
+            return Ok(());
+        }
+        if let Some(task) = metadata::information_schema_task(route_ctx).await? {
+            self.tasks = vec![task];
+            self.read_from_mirrors(route_ctx);
+            return Ok(());
+        }
+        self.federated = FederatedPlan::new(route_ctx, stmt_ctx)?;
+        if self.federated.is_some() {
+            return Ok(());
+        }
+        self.tasks = route(route_ctx, stmt_ctx)?;
+        self.merge_ctx = MergeContext::new(stmt_ctx);
+        self.ddl = matches!(stmt_ctx, SQLStatementContext::Ddl(_));
+        self.atomic = self.tasks.len() > 1 && is_write_statement(route_ctx.get_statement()) && !self.ddl;
+        if is_write_statement(route_ctx.get_statement()) && !self.ddl {
+            self.create_ahead = partition::create_ahead_tasks(route_ctx.get_cluster(), &stmt_ctx.get_table_names(), Local::today().naive_local())?;
+        }
+        if self.tasks.len() > 1 {
+            let cluster = route_ctx.get_cluster();
+            self.merge_ctx.set_pagination(pagination(route_ctx, stmt_ctx)?);
+            self.merge_ctx.set_aggregation(AggregationPlan::new(stmt_ctx, cluster.get_dis_rules())?);
+            if self.merge_ctx.get_aggregation().is_none() {
+                self.merge_ctx.set_derived_columns(order_by_derived_columns(stmt_ctx));
+            }
+            self.merge_ctx.set_distinct(stmt_ctx.is_distinct() && !is_partitioned_projection(stmt_ctx, cluster.get_dis_rules()));
+            self.merge_ctx.set_max_distinct_rows(cluster.get_props().get_max_distinct_rows());
+        }
+        self.read_from_mirrors(route_ctx);
+        Ok(())
     }
 
-    pub fn gen(&self) {}
+    /// Let the mirrors answer the tasks of a plain read.
+    fn read_from_mirrors(&mut self, route_ctx: &RouteContext<'_>) {
+        if !is_primary_statement(route_ctx, self.ctx.get_sql()) {
+            for task in self.tasks.iter_mut() {
+                task.set_mirror_read(true);
+            }
+        }
+    }
 
     pub fn ctx(&self) -> &'a ExplainPlanContext<'a> {
         self.ctx
     }
+
+    pub fn get_tasks(&self) -> &Vec<PlanTask> {
+        &self.tasks
+    }
+
+    pub fn get_merge_ctx(&self) -> &MergeContext {
+        &self.merge_ctx
+    }
+
+    pub fn is_atomic(&self) -> bool {
+        self.atomic
+    }
+
+    pub fn get_federated(&self) -> Option<&FederatedPlan> {
+        self.federated.as_ref()
+    }
+
+    pub fn is_ddl(&self) -> bool {
+        self.ddl
+    }
+
+    pub fn get_relocate(&self) -> Option<&RelocatePlan> {
+        self.relocate.as_ref()
+    }
+
+    pub fn get_metadata(&self) -> Option<&MetadataRows> {
+        self.metadata.as_ref()
+    }
+
+    pub fn get_create_ahead(&self) -> &[PlanTask] {
+        self.create_ahead.as_slice()
+    }
+
+    pub fn get_session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub fn is_in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    pub fn get_end_transaction(&self) -> Option<bool> {
+        self.end_transaction
+    }
+
+    pub fn is_describe(&self) -> bool {
+        self.describe
+    }
+
+    /// One task whose response the mesh has nothing to change in, its packets go to the
+    /// client as the segment sent them. In a transaction the task runs on the connection of
+    /// the session instead.
+    pub fn is_passthrough(&self) -> bool {
+        self.tasks.len() == 1
+            && !self.in_transaction
+            && !self.describe
+            && self.federated.is_none()
+            && self.relocate.is_none()
+            && self.metadata.is_none()
+            && !self.ddl
+            && self.create_ahead.is_empty()
+            && self.ctx.get_generated_key().is_none()
+    }
+
+    /// The steps of the plan: the SQL of each segment and what the mesh does with their rows.
+    pub fn describe(&self) -> ShardResultSet {
+        let rows = match (&self.federated, &self.relocate) {
+            (Some(federated), _) => federated.describe(),
+            (None, Some(relocate)) => relocate.describe(),
+            (None, None) if self.metadata.is_some() => vec![explain_row("METADATA", None, "answered by the mesh from the logical tables")],
+            (None, None) => {
+                let mut rows: Vec<Vec<Value>> = self.tasks.iter()
+                    .map(|task| explain_row(if task.is_mirror_read() { "ROUTE MIRROR" } else { "ROUTE" }, Some(task.get_segment_id()), task.get_sql()))
+                    .collect();
+                if self.merge_ctx.get_aggregation().is_some() {
+                    rows.push(explain_row("AGGREGATE", None, "regroup the partial aggregates of the segments"));
+                }
+                if self.merge_ctx.is_distinct() {
+                    rows.push(explain_row("DISTINCT", None, format!("max_distinct_rows {}", self.merge_ctx.get_max_distinct_rows()).as_str()));
+                }
+                if self.tasks.len() > 1 && !self.merge_ctx.get_order_by().is_empty() {
+                    let order_by: Vec<String> = self.merge_ctx.get_order_by().iter().map(|o| o.to_string()).collect();
+                    rows.push(explain_row("MERGE", None, order_by.join(", ").as_str()));
+                }
+                if let Some(pagination) = self.merge_ctx.get_pagination() {
+                    let limit = pagination.get_limit().map(|l| l.to_string()).unwrap_or_else(|| String::from("ALL"));
+                    rows.push(explain_row("LIMIT", None, format!("{} OFFSET {}", limit, pagination.get_offset()).as_str()));
+                }
+                rows
+            }
+        };
+        ShardResultSet::Rows { columns: explain_columns(), rows: Box::new(rows.into_iter().map(Ok)) }
+    }
+}
+
+/// The columns of the rows `EXPLAIN` answers with when the mesh describes a plan.
+pub fn explain_columns() -> Vec<Column> {
+    ["step", "segment_id", "detail"].iter()
+        .map(|name| Column::new(MySQLColumnType::MysqlTypeVarString).with_name(name))
+        .collect()
+}
+
+pub fn explain_row(step: &str, segment_id: Option<u32>, detail: &str) -> Vec<Value> {
+    vec![
+        Value::Bytes(step.as_bytes().to_vec()),
+        segment_id.map(|id| Value::Bytes(id.to_string().into_bytes())).unwrap_or(Value::NULL),
+        Value::Bytes(detail.as_bytes().to_vec()),
+    ]
 }
 
+#[async_trait]
 impl<'a> Executor for ExplainPlan<'a> {
-    fn execute(&self) -> Option<Vec<Bytes>> {
+    async fn execute(&self) -> Option<PayloadStream> {
         match self.ctx.protocol {
-            TBProtocol::Text => { text_query(&self) }
-            TBProtocol::Binary => { bin_query(&self) }
+            TBProtocol::Text => { text_query(&self).await }
+            TBProtocol::Binary => { bin_query(&self).await }
         }
     }
 }
\ No newline at end of file
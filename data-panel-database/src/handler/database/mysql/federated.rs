@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use sqlparser::ast::{BinaryOperator, Expr, Ident, JoinConstraint, JoinOperator, OrderByExpr, Query, SelectItem, SetExpr, Statement, TableFactor};
+
+use data_panel_common::common::Error;
+
+use crate::handler::database::mysql::aggregate::group_key;
+use crate::handler::database::mysql::explainplan::{explain_row, PlanTask};
+use crate::handler::database::mysql::merge::{compare_rows, paginate, RowStream, ShardResultSet, sort_keys, SortKind};
+use crate::handler::database::mysql::rdbc::{ExecuteError, ExecuteResult, fits_pools, in_session, scan_tasks};
+use crate::handler::database::parser::sql::analyse::SQLAnalyse;
+use crate::handler::database::parser::sql::mysql::parser;
+use crate::handler::database::parser::sql::route::{is_co_located, Pagination, pagination, route, route_units, RouteContext};
+use crate::handler::database::parser::sql::SQLStatementContext;
+use crate::protocol::database::mysql::resultset::{Column, Value};
+
+pub type FederatedResult<T> = data_panel_common::common::Result<T>;
+
+/// How the rows of the two sides are joined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    /// `LEFT JOIN`, a left row without a match gets NULLs for the right side.
+    Left,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// One side of a federated join: the rows of a table filtered by the conditions on it
+/// alone, read from the data segments which may have them.
+pub struct FederatedInput {
+    table: String,
+    alias: Ident,
+    conditions: Vec<Expr>,
+    tasks: Vec<PlanTask>,
+    /// The session in a transaction whose connections the scans run on, to see its writes.
+    session_id: Option<u64>,
+}
+
+impl FederatedInput {
+    fn new(table_factor: &TableFactor) -> FederatedResult<Self> {
+        match table_factor {
+            TableFactor::Table { name, alias, args, .. } if args.is_empty() => {
+                let alias = match alias {
+                    Some(alias) => alias.name.clone(),
+                    None => name.0.last().cloned().unwrap_or_else(|| Ident::new(name.to_string())),
+                };
+                Ok(FederatedInput {
+                    table: name.to_string(),
+                    alias,
+                    conditions: vec![],
+                    tasks: vec![],
+                    session_id: None,
+                })
+            }
+            _ => Err(unsupported("derived tables and table functions")),
+        }
+    }
+
+    pub fn get_tasks(&self) -> &[PlanTask] {
+        self.tasks.as_slice()
+    }
+
+    /// `SELECT *` of the table with the conditions on it, in logical names.
+    fn scan_sql(&self) -> String {
+        let mut sql = format!("SELECT * FROM {} AS {}", self.table, self.alias);
+        for (index, condition) in self.conditions.iter().enumerate() {
+            sql.push_str(if index == 0 { " WHERE " } else { " AND " });
+            match condition {
+                Expr::BinaryOp { op: BinaryOperator::Or, .. } => sql.push_str(format!("({})", condition).as_str()),
+                _ => sql.push_str(condition.to_string().as_str()),
+            }
+        }
+        sql
+    }
+
+    fn route(&mut self, route_ctx: &RouteContext<'_>) -> FederatedResult<()> {
+        let statement = match parser(self.scan_sql()).pop() {
+            Some(statement) => statement,
+            None => return Err(Error::General(format!("can not read table {}", self.table))),
+        };
+        let mut stmt_ctx = SQLStatementContext::new(&statement);
+        statement.analyse(&mut stmt_ctx)?;
+        let scan_ctx = RouteContext::new(route_ctx.get_cluster(), &statement, route_ctx.get_session_ctx(), &[]);
+        self.tasks = route(&scan_ctx, &stmt_ctx)?;
+        let session_ctx = route_ctx.get_session_ctx();
+        if session_ctx.has_transaction() {
+            self.session_id = Some(session_ctx.get_thread_id());
+        }
+        Ok(())
+    }
+
+    /// Read the rows of the table, each charged to the budget as it arrives. Going over
+    /// the budget stops the scans without reading the rest of their rows. In a transaction
+    /// the scans are read on the session's connections, one after the other, and their
+    /// rows after the budget was exceeded are read and dropped.
+    async fn fetch(&self, binary: bool, budget: &mut Budget) -> ExecuteResult<(Vec<Column>, Vec<Vec<Value>>)> {
+        let mut columns = None;
+        let mut rows = Vec::new();
+        let mut charge = |row: Vec<Value>| -> ExecuteResult<()> {
+            budget.charge(&row)?;
+            rows.push(row);
+            Ok(())
+        };
+        match self.session_id {
+            Some(session_id) => {
+                let tasks = self.tasks.as_slice();
+                let charge = &mut charge;
+                columns = in_session(session_id, true, |mut transaction| async move {
+                    let columns = transaction.scan_all(tasks, binary, charge).await;
+                    (transaction, columns)
+                }).await?;
+            }
+            None => {
+                // Scans which do not get a connection each at once run one after the other.
+                let chunk_size = if fits_pools(&self.tasks) { self.tasks.len().max(1) } else { 1 };
+                for tasks in self.tasks.chunks(chunk_size) {
+                    if let Some(shard_columns) = scan_tasks(tasks, binary, &mut charge).await? {
+                        columns.get_or_insert(shard_columns);
+                    }
+                }
+            }
+        }
+        match columns {
+            Some(columns) => Ok((columns, rows)),
+            None => Err((10000, String::from("HY000"), format!("no rows of {} were returned", self.table))),
+        }
+    }
+}
+
+/// A query joining distributed tables which are not co-located: both sides are read from
+/// their data segments and joined in the proxy, by a hash join on the equalities between
+/// them or else by a nested loop, within the `max_federated_rows` and `max_federated_bytes`
+/// of the cluster.
+pub struct FederatedPlan {
+    left: FederatedInput,
+    right: FederatedInput,
+    kind: JoinKind,
+    /// Columns of the `left.x = right.y` the rows are joined on.
+    join_keys: Vec<(String, String)>,
+    projection: Vec<SelectItem>,
+    order_by: Vec<OrderByExpr>,
+    pagination: Option<Pagination>,
+    max_rows: u64,
+    max_bytes: u64,
+}
+
+impl FederatedPlan {
+    /// The plan for a query whose distributed tables are not co-located and have rows on
+    /// several data segments, `None` for statements the segments can answer themselves.
+    pub fn new(route_ctx: &RouteContext<'_>, stmt_ctx: &SQLStatementContext) -> FederatedResult<Option<Self>> {
+        let query = match route_ctx.get_statement() {
+            Statement::Query(query) => query,
+            _ => return Ok(None),
+        };
+        let cluster = route_ctx.get_cluster();
+        let dis_rules = cluster.get_dis_rules();
+        let table_names = stmt_ctx.get_table_names();
+        let distributed_tables: Vec<&String> = table_names.iter()
+            .filter(|t| dis_rules.is_distributed_table(t.as_str()))
+            .collect();
+        if distributed_tables.len() < 2 || is_co_located(&distributed_tables, &stmt_ctx.get_table_bindings(dis_rules)) {
+            return Ok(None);
+        }
+        if route_units(route_ctx, stmt_ctx).is_ok() {
+            // The conditions leave one segment with all the rows.
+            return Ok(None);
+        }
+        if !route_ctx.get_parameters().is_empty() || stmt_ctx.get_parameter_count() > 0 {
+            return Err(unsupported("parameters"));
+        }
+
+        let mut plan = Self::from_query(query)?;
+        plan.pagination = pagination(route_ctx, stmt_ctx)?;
+        plan.max_rows = cluster.get_props().get_max_federated_rows();
+        plan.max_bytes = cluster.get_props().get_max_federated_bytes();
+        plan.left.route(route_ctx)?;
+        plan.right.route(route_ctx)?;
+        Ok(Some(plan))
+    }
+
+    fn from_query(query: &Query) -> FederatedResult<Self> {
+        if query.with.is_some() || query.fetch.is_some() {
+            return Err(unsupported("WITH and FETCH"));
+        }
+        let select = match &query.body {
+            SetExpr::Select(select) => select,
+            _ => return Err(unsupported("set operations")),
+        };
+        if select.distinct || select.top.is_some() || !select.group_by.is_empty() || select.having.is_some() {
+            return Err(unsupported("DISTINCT, TOP, GROUP BY and HAVING"));
+        }
+        for item in select.projection.iter() {
+            match item {
+                SelectItem::UnnamedExpr(Expr::Identifier(_)) | SelectItem::UnnamedExpr(Expr::CompoundIdentifier(_)) => {}
+                SelectItem::ExprWithAlias { expr: Expr::Identifier(_), .. } | SelectItem::ExprWithAlias { expr: Expr::CompoundIdentifier(_), .. } => {}
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => {}
+                _ => return Err(unsupported("expressions and aggregates in the select list")),
+            }
+        }
+
+        let (left, right, kind, on) = match select.from.as_slice() {
+            [from] if from.joins.len() == 1 => {
+                let join = &from.joins[0];
+                let (kind, constraint) = match &join.join_operator {
+                    JoinOperator::Inner(constraint) => (JoinKind::Inner, Some(constraint)),
+                    JoinOperator::LeftOuter(constraint) => (JoinKind::Left, Some(constraint)),
+                    JoinOperator::CrossJoin => (JoinKind::Inner, None),
+                    _ => return Err(unsupported("RIGHT, FULL and APPLY joins")),
+                };
+                let on = match constraint {
+                    Some(JoinConstraint::On(expr)) => Some(expr),
+                    Some(JoinConstraint::Using(_)) | Some(JoinConstraint::Natural) => {
+                        return Err(unsupported("NATURAL and USING joins"));
+                    }
+                    _ => None,
+                };
+                (FederatedInput::new(&from.relation)?, FederatedInput::new(&join.relation)?, kind, on)
+            }
+            [left, right] if left.joins.is_empty() && right.joins.is_empty() => {
+                (FederatedInput::new(&left.relation)?, FederatedInput::new(&right.relation)?, JoinKind::Inner, None)
+            }
+            _ => return Err(unsupported("joins of more than two tables")),
+        };
+        if left.alias.value.eq_ignore_ascii_case(right.alias.value.as_str()) {
+            return Err(unsupported("joins of a table with itself without aliases"));
+        }
+
+        let mut plan = FederatedPlan {
+            left,
+            right,
+            kind,
+            join_keys: vec![],
+            projection: select.projection.clone(),
+            order_by: query.order_by.clone(),
+            pagination: None,
+            max_rows: 0,
+            max_bytes: 0,
+        };
+        if let Some(on) = on {
+            for condition in conjuncts(on) {
+                plan.add_condition(condition, true)?;
+            }
+        }
+        if let Some(ref selection) = select.selection {
+            for condition in conjuncts(selection) {
+                plan.add_condition(condition, false)?;
+            }
+        }
+        Ok(plan)
+    }
+
+    pub fn get_left(&self) -> &FederatedInput {
+        &self.left
+    }
+
+    pub fn get_right(&self) -> &FederatedInput {
+        &self.right
+    }
+
+    pub fn get_join_keys(&self) -> &[(String, String)] {
+        self.join_keys.as_slice()
+    }
+
+    /// Push a condition down to the side it is about, or join the sides on it. The right side
+    /// of a `LEFT JOIN` is only filtered by `ON`, the left side only by `WHERE`.
+    fn add_condition(&mut self, expr: &Expr, on: bool) -> FederatedResult<()> {
+        if let Expr::BinaryOp { left, op: BinaryOperator::Eq, right } = expr {
+            if let (Some((left_side, left_column)), Some((right_side, right_column))) = (self.column(left)?, self.column(right)?) {
+                if left_side != right_side {
+                    if self.kind == JoinKind::Left && !on {
+                        return Err(unsupported(format!("the condition {} in WHERE of a LEFT JOIN", expr)));
+                    }
+                    self.join_keys.push(if left_side == Side::Left {
+                        (left_column, right_column)
+                    } else {
+                        (right_column, left_column)
+                    });
+                    return Ok(());
+                }
+            }
+        }
+        let mut sides = vec![];
+        self.collect_sides(expr, &mut sides)?;
+        let left = sides.contains(&Side::Left);
+        let right = sides.contains(&Side::Right);
+        match (left, right) {
+            (true, false) if self.kind == JoinKind::Inner || !on => self.left.conditions.push(expr.clone()),
+            (false, true) if self.kind == JoinKind::Inner || on => self.right.conditions.push(expr.clone()),
+            _ => return Err(unsupported(format!("the condition {}", expr))),
+        }
+        Ok(())
+    }
+
+    fn side(&self, qualifier: &str) -> Option<Side> {
+        if self.left.alias.value.eq_ignore_ascii_case(qualifier) {
+            Some(Side::Left)
+        } else if self.right.alias.value.eq_ignore_ascii_case(qualifier) {
+            Some(Side::Right)
+        } else {
+            None
+        }
+    }
+
+    /// The side and name of a qualified column, `None` for other expressions.
+    fn column(&self, expr: &Expr) -> FederatedResult<Option<(Side, String)>> {
+        match expr {
+            Expr::Identifier(ident) if ident.value == "?" => Err(unsupported("parameters")),
+            Expr::Identifier(ident) => Err(unsupported(format!("the column {} without its table", ident))),
+            Expr::CompoundIdentifier(idents) if idents.len() > 1 => {
+                let qualifier = idents[idents.len() - 2].value.as_str();
+                match self.side(qualifier) {
+                    Some(side) => Ok(Some((side, idents[idents.len() - 1].value.clone()))),
+                    None => Err(Error::General(format!("unknown table {} in {}", qualifier, expr))),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn collect_sides(&self, expr: &Expr, sides: &mut Vec<Side>) -> FederatedResult<()> {
+        match expr {
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+                if let Some((side, _)) = self.column(expr)? {
+                    sides.push(side);
+                }
+                Ok(())
+            }
+            Expr::Value(_) => Ok(()),
+            Expr::BinaryOp { left, right, .. } => {
+                self.collect_sides(left, sides)?;
+                self.collect_sides(right, sides)
+            }
+            Expr::UnaryOp { expr, .. } | Expr::Nested(expr) | Expr::IsNull(expr) | Expr::IsNotNull(expr) => {
+                self.collect_sides(expr, sides)
+            }
+            Expr::Between { expr, low, high, .. } => {
+                self.collect_sides(expr, sides)?;
+                self.collect_sides(low, sides)?;
+                self.collect_sides(high, sides)
+            }
+            Expr::InList { expr, list, .. } => {
+                self.collect_sides(expr, sides)?;
+                for item in list {
+                    self.collect_sides(item, sides)?;
+                }
+                Ok(())
+            }
+            _ => Err(unsupported(format!("the condition {}", expr))),
+        }
+    }
+
+    /// Read both sides, join them and shape the joined rows like the query asks for.
+    pub async fn execute(&self, binary: bool) -> ExecuteResult<ShardResultSet> {
+        let mut budget = Budget::new(self.max_rows, self.max_bytes);
+        let (left_columns, left_rows) = self.left.fetch(binary, &mut budget).await?;
+        let (right_columns, right_rows) = self.right.fetch(binary, &mut budget).await?;
+
+        let mut keys = Vec::with_capacity(self.join_keys.len());
+        for (left_column, right_column) in self.join_keys.iter() {
+            let left_index = column_index(&left_columns, 0..left_columns.len(), &self.left.alias, left_column)?;
+            let right_index = column_index(&right_columns, 0..right_columns.len(), &self.right.alias, right_column)?;
+            keys.push(JoinKey::new(left_index, right_index, SortKind::of(&left_columns[left_index])));
+        }
+        let joined = join(&left_rows, &right_rows, right_columns.len(), &keys, self.kind, &mut budget)?;
+
+        let left_count = left_columns.len();
+        let mut columns = left_columns;
+        columns.extend(right_columns);
+        let (columns, indexes) = self.projection(&columns, left_count)?;
+        let mut rows: Vec<Vec<Value>> = joined.into_iter()
+            .map(|row| indexes.iter().map(|index| row[*index].clone()).collect())
+            .collect();
+        if !self.order_by.is_empty() {
+            let sort_keys = sort_keys(&self.order_by, &columns, &[])?;
+            rows.sort_by(|a, b| compare_rows(a, b, &sort_keys));
+        }
+        let mut rows: RowStream = Box::new(rows.into_iter().map(Ok));
+        if let Some(ref pagination) = self.pagination {
+            rows = paginate(rows, pagination);
+        }
+        Ok(ShardResultSet::Rows { columns, rows })
+    }
+
+    /// The columns of the select list, named by their aliases, and where they are in the joined rows.
+    fn projection(&self, columns: &[Column], left_count: usize) -> ExecuteResult<(Vec<Column>, Vec<usize>)> {
+        let range = |side: Side| match side {
+            Side::Left => 0..left_count,
+            Side::Right => left_count..columns.len(),
+        };
+        let mut projected = Vec::new();
+        let mut indexes = Vec::new();
+        for item in self.projection.iter() {
+            let (expr, alias) = match item {
+                SelectItem::Wildcard => {
+                    indexes.extend(0..columns.len());
+                    projected.extend(columns.iter().cloned());
+                    continue;
+                }
+                SelectItem::QualifiedWildcard(name) => {
+                    let qualifier = name.0.last().map(|ident| ident.value.as_str()).unwrap_or("");
+                    let side = self.side(qualifier).ok_or_else(|| unknown_column(&item.to_string()))?;
+                    indexes.extend(range(side));
+                    projected.extend(columns[range(side)].iter().cloned());
+                    continue;
+                }
+                SelectItem::UnnamedExpr(expr) => (expr, None),
+                SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias)),
+            };
+            let index = match expr {
+                Expr::CompoundIdentifier(idents) if idents.len() > 1 => {
+                    let qualifier = &idents[idents.len() - 2];
+                    let side = self.side(qualifier.value.as_str()).ok_or_else(|| unknown_column(&expr.to_string()))?;
+                    column_index(columns, range(side), qualifier, idents[idents.len() - 1].value.as_str())?
+                }
+                Expr::Identifier(ident) => {
+                    let left = find_column(columns, range(Side::Left), ident.value.as_str());
+                    let right = find_column(columns, range(Side::Right), ident.value.as_str());
+                    match (left, right) {
+                        (Some(_), Some(_)) => {
+                            return Err((1052, String::from("23000"), format!("Column '{}' in field list is ambiguous", ident.value)));
+                        }
+                        (Some(index), None) | (None, Some(index)) => index,
+                        (None, None) => return Err(unknown_column(&ident.value)),
+                    }
+                }
+                _ => return Err(unknown_column(&expr.to_string())),
+            };
+            indexes.push(index);
+            projected.push(match alias {
+                Some(alias) => columns[index].clone().with_name(alias.value.as_str()),
+                None => columns[index].clone(),
+            });
+        }
+        Ok((projected, indexes))
+    }
+
+    /// The steps of the plan, for `EXPLAIN`.
+    pub fn describe(&self) -> Vec<Vec<Value>> {
+        let mut rows = Vec::new();
+        for input in vec![&self.left, &self.right] {
+            let step = format!("SCAN {}", input.alias.value);
+            for task in input.tasks.iter() {
+                rows.push(explain_row(step.as_str(), Some(task.get_segment_id()), task.get_sql()));
+            }
+        }
+        let kind = match self.kind {
+            JoinKind::Inner => "INNER",
+            JoinKind::Left => "LEFT",
+        };
+        if self.join_keys.is_empty() {
+            rows.push(explain_row(format!("{} NESTED LOOP JOIN", kind).as_str(), None, "every pair of rows"));
+        } else {
+            let keys: Vec<String> = self.join_keys.iter()
+                .map(|(left, right)| format!("{}.{} = {}.{}", self.left.alias, left, self.right.alias, right))
+                .collect();
+            rows.push(explain_row(format!("{} HASH JOIN", kind).as_str(), None, keys.join(" AND ").as_str()));
+        }
+        if !self.order_by.is_empty() {
+            let order_by: Vec<String> = self.order_by.iter().map(|o| o.to_string()).collect();
+            rows.push(explain_row("SORT", None, order_by.join(", ").as_str()));
+        }
+        if let Some(ref pagination) = self.pagination {
+            let limit = pagination.get_limit().map(|l| l.to_string()).unwrap_or_else(|| String::from("ALL"));
+            rows.push(explain_row("LIMIT", None, format!("{} OFFSET {}", limit, pagination.get_offset()).as_str()));
+        }
+        rows.push(explain_row("BUDGET", None, format!("max_federated_rows {}, max_federated_bytes {}", self.max_rows, self.max_bytes).as_str()));
+        rows
+    }
+}
+
+/// A `left.x = right.y` of the join, by column index, compared like the left column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JoinKey {
+    left: usize,
+    right: usize,
+    kind: SortKind,
+}
+
+impl JoinKey {
+    pub fn new(left: usize, right: usize, kind: SortKind) -> Self {
+        JoinKey {
+            left,
+            right,
+            kind,
+        }
+    }
+}
+
+/// Join the rows of both sides: a hash join on the keys, built from the right rows, or
+/// every pair of rows without keys. NULL never equals anything.
+pub fn join(left_rows: &[Vec<Value>],
+            right_rows: &[Vec<Value>],
+            right_width: usize,
+            keys: &[JoinKey],
+            kind: JoinKind,
+            budget: &mut Budget) -> ExecuteResult<Vec<Vec<Value>>> {
+    let null_row = vec![Value::NULL; right_width];
+    let mut joined = Vec::new();
+    let mut push = |left_row: &Vec<Value>, right_row: &Vec<Value>, joined: &mut Vec<Vec<Value>>| -> ExecuteResult<()> {
+        let mut row = Vec::with_capacity(left_row.len() + right_row.len());
+        row.extend(left_row.iter().cloned());
+        row.extend(right_row.iter().cloned());
+        budget.charge(&row)?;
+        joined.push(row);
+        Ok(())
+    };
+
+    if keys.is_empty() {
+        for left_row in left_rows {
+            for right_row in right_rows {
+                push(left_row, right_row, &mut joined)?;
+            }
+            if right_rows.is_empty() && kind == JoinKind::Left {
+                push(left_row, &null_row, &mut joined)?;
+            }
+        }
+        return Ok(joined);
+    }
+
+    let mut hash_table: HashMap<Vec<Vec<u8>>, Vec<usize>> = HashMap::new();
+    for (index, right_row) in right_rows.iter().enumerate() {
+        if let Some(key) = join_key(right_row, keys.iter().map(|k| (k.right, k.kind))) {
+            hash_table.entry(key).or_insert_with(Vec::new).push(index);
+        }
+    }
+    for left_row in left_rows {
+        let matches = join_key(left_row, keys.iter().map(|k| (k.left, k.kind)))
+            .and_then(|key| hash_table.get(&key));
+        match matches {
+            Some(matches) => {
+                for index in matches {
+                    push(left_row, &right_rows[*index], &mut joined)?;
+                }
+            }
+            None if kind == JoinKind::Left => push(left_row, &null_row, &mut joined)?,
+            None => {}
+        }
+    }
+    Ok(joined)
+}
+
+fn join_key<I: Iterator<Item = (usize, SortKind)>>(row: &[Value], columns: I) -> Option<Vec<Vec<u8>>> {
+    columns.map(|(index, kind)| row.get(index).and_then(|value| group_key(value, kind))).collect()
+}
+
+/// Rows and bytes of values a federated join holds, failing the statement beyond its limits.
+pub struct Budget {
+    max_rows: u64,
+    max_bytes: u64,
+    rows: u64,
+    bytes: u64,
+}
+
+impl Budget {
+    pub fn new(max_rows: u64, max_bytes: u64) -> Self {
+        Budget {
+            max_rows,
+            max_bytes,
+            rows: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn charge(&mut self, row: &[Value]) -> ExecuteResult<()> {
+        self.rows += 1;
+        for value in row {
+            self.bytes += std::mem::size_of::<Value>() as u64;
+            if let Value::Bytes(bytes) = value {
+                self.bytes += bytes.len() as u64;
+            }
+        }
+        if self.max_rows > 0 && self.rows > self.max_rows {
+            return Err(budget_exceeded(format!("max_federated_rows {} rows", self.max_rows)));
+        }
+        if self.max_bytes > 0 && self.bytes > self.max_bytes {
+            return Err(budget_exceeded(format!("max_federated_bytes {} bytes", self.max_bytes)));
+        }
+        Ok(())
+    }
+}
+
+fn budget_exceeded(limit: String) -> ExecuteError {
+    (1104, String::from("42000"),
+     format!("the join of tables on different data segments needs more than {}, narrow it down with conditions on the tables", limit))
+}
+
+fn unknown_column(column: &str) -> ExecuteError {
+    (1054, String::from("42S22"), format!("Unknown column '{}' in field list", column))
+}
+
+fn unsupported<S: AsRef<str>>(what: S) -> Error {
+    Error::General(format!("{} not supported in joins of tables on different data segments", what.as_ref()))
+}
+
+fn find_column(columns: &[Column], range: Range<usize>, name: &str) -> Option<usize> {
+    range.into_iter().find(|index| columns[*index].get_name().eq_ignore_ascii_case(name))
+}
+
+fn column_index(columns: &[Column], range: Range<usize>, qualifier: &Ident, name: &str) -> ExecuteResult<usize> {
+    find_column(columns, range, name).ok_or_else(|| unknown_column(format!("{}.{}", qualifier, name).as_str()))
+}
+
+/// The operands of the top level `AND`s.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp { left, op: BinaryOperator::And, right } => {
+            let mut operands = conjuncts(left);
+            operands.extend(conjuncts(right));
+            operands
+        }
+        Expr::Nested(nested) => match nested.as_ref() {
+            Expr::BinaryOp { op: BinaryOperator::And, .. } => conjuncts(nested),
+            _ => vec![expr],
+        },
+        _ => vec![expr],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::federated::{Budget, FederatedPlan, join, JoinKey, JoinKind};
+    use crate::handler::database::mysql::merge::SortKind;
+    use crate::handler::database::parser::sql::route::tests::with_route_ctx;
+    use crate::protocol::database::mysql::resultset::Value;
+
+    fn federated_plan(sql: &str) -> data_panel_common::common::Result<Option<FederatedPlan>> {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        with_route_ctx(&cluster, sql, &[], FederatedPlan::new)
+    }
+
+    #[test]
+    fn test_federated_plan() {
+        let plan = federated_plan("SELECT o.order_id, l.content FROM t_order AS o JOIN t_order_log AS l ON o.order_id = l.order_id \
+                         WHERE o.user_id = 10 AND l.content IS NOT NULL ORDER BY o.order_id LIMIT 10").unwrap().unwrap();
+        assert_eq!(&[(String::from("order_id"), String::from("order_id"))], plan.get_join_keys());
+        let left: Vec<(u32, &str)> = plan.get_left().get_tasks().iter().map(|t| (t.get_segment_id(), t.get_sql())).collect();
+        assert_eq!(vec![(200, "SELECT * FROM t_order_1 AS o WHERE o.user_id = 10")], left);
+        let right: Vec<u32> = plan.get_right().get_tasks().iter().map(|t| t.get_segment_id()).collect();
+        assert_eq!(vec![100, 200, 300], right);
+        assert_eq!("SELECT * FROM t_order_log AS l WHERE l.content IS NOT NULL", plan.get_right().get_tasks()[0].get_sql());
+
+        // Relatives are joined by the segments.
+        assert!(federated_plan("SELECT * FROM t_order AS o JOIN t_order_item AS i ON o.user_id = i.user_id").unwrap().is_none());
+        assert!(federated_plan("SELECT COUNT(*) FROM t_order AS o JOIN t_order_log AS l ON o.order_id = l.order_id").is_err());
+        assert!(federated_plan("SELECT * FROM t_order AS o LEFT JOIN t_order_log AS l ON o.order_id = l.order_id WHERE l.order_id = 1").is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let int = |i: i64| Value::Int(i);
+        let left = vec![vec![int(1), int(10)], vec![int(2), int(20)], vec![Value::NULL, int(30)]];
+        let right = vec![vec![int(1), int(100)], vec![int(1), int(101)], vec![Value::NULL, int(300)]];
+        let keys = vec![JoinKey::new(0, 0, SortKind::Numeric)];
+
+        let inner = join(&left, &right, 2, &keys, JoinKind::Inner, &mut Budget::new(0, 0)).unwrap();
+        assert_eq!(vec![vec![int(1), int(10), int(1), int(100)], vec![int(1), int(10), int(1), int(101)]], inner);
+
+        let outer = join(&left, &right, 2, &keys, JoinKind::Left, &mut Budget::new(0, 0)).unwrap();
+        assert_eq!(4, outer.len());
+        assert_eq!(vec![int(2), int(20), Value::NULL, Value::NULL], outer[2]);
+
+        let cross = join(&left, &right, 2, &[], JoinKind::Inner, &mut Budget::new(0, 0)).unwrap();
+        assert_eq!(9, cross.len());
+
+        let err = join(&left, &right, 2, &[], JoinKind::Inner, &mut Budget::new(5, 0)).unwrap_err();
+        assert_eq!(1104, err.0);
+    }
+}
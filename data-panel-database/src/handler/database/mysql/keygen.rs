@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlparser::ast::{Expr, Ident, SetExpr, Statement, Value};
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{Cluster, KeyGenerator, KeyType};
+use crate::handler::database::mysql::client::{is_connection_error, MySQLClient};
+use crate::handler::database::mysql::merge::ShardResultSet;
+use crate::handler::database::mysql::pool;
+use crate::handler::database::mysql::rdbc::ExecuteResult;
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+
+pub type KeyGenResult<T> = data_panel_common::common::Result<T>;
+
+/// 2021-01-01T00:00:00Z, the snowflake timestamps count milliseconds from it.
+const SNOWFLAKE_EPOCH: u64 = 1609459200000;
+const WORKER_ID_BITS: u64 = 10;
+const SEQUENCE_BITS: u64 = 12;
+const MAX_WORKER_ID: u16 = (1 << WORKER_ID_BITS) - 1;
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+
+lazy_static! {
+    static ref SNOWFLAKE: Mutex<Snowflake> = Mutex::new(Snowflake::default());
+    static ref KEY_RANGES: Mutex<HashMap<String, Arc<tokio::sync::Mutex<KeyRange>>>> = Mutex::new(HashMap::new());
+}
+
+/// Add the keys an INSERT into a table with a `key_generator` leaves out: the key column is
+/// appended to the columns and a new key to every row, NULL values of a listed key column
+/// are replaced. Returns the first key, which the client gets as `last_insert_id`.
+pub async fn generate_keys(statement: &mut Statement, cluster: &Cluster) -> KeyGenResult<Option<u64>> {
+    let (table_name, columns, source) = match statement {
+        Statement::Insert { table_name, columns, source, .. } => (table_name, columns, source),
+        _ => return Ok(None),
+    };
+    let table = table_name.to_string();
+    let key_generator = match cluster.get_dis_rules().get_distributed_table(table.as_str()).and_then(|t| t.get_key_generator()) {
+        Some(key_generator) => key_generator,
+        None => return Ok(None),
+    };
+    let rows = match source.body {
+        SetExpr::Values(ref mut values) => &mut values.0,
+        _ => return Ok(None),
+    };
+    if columns.is_empty() {
+        // Values follow the table's own column order, which the proxy does not know.
+        return Ok(None);
+    }
+
+    let key_column = key_generator.get_key_column();
+    let position = columns.iter().position(|column| column.value.eq_ignore_ascii_case(key_column));
+    if position.is_none() {
+        columns.push(Ident::new(key_column.clone()));
+    }
+    let mut first_key = None;
+    for row in rows.iter_mut() {
+        let slot = match position {
+            Some(position) => match row.get_mut(position) {
+                Some(value @ Expr::Value(Value::Null)) => value,
+                _ => continue,
+            },
+            None => {
+                row.push(Expr::Value(Value::Null));
+                row.last_mut().unwrap()
+            }
+        };
+        let key = next_key(table.as_str(), key_generator, cluster).await?;
+        *slot = Expr::Value(Value::Number(key.to_string(), false));
+        first_key = first_key.or(Some(key));
+    }
+    Ok(first_key)
+}
+
+/// A `worker_id` which does not fit the bits of a snowflake key fails when the config loads.
+pub fn check_key_generator(table: &str, key_generator: &KeyGenerator) -> KeyGenResult<()> {
+    match key_generator.get_key_type() {
+        KeyType::SNOWFLAKE if key_generator.get_worker_id() > MAX_WORKER_ID => Err(Error::General(format!(
+            "{}: worker_id {} is greater than {}", table, key_generator.get_worker_id(), MAX_WORKER_ID))),
+        _ => Ok(()),
+    }
+}
+
+async fn next_key(table: &str, key_generator: &KeyGenerator, cluster: &Cluster) -> KeyGenResult<u64> {
+    match key_generator.get_key_type() {
+        KeyType::SNOWFLAKE => {
+            Ok(SNOWFLAKE.lock().unwrap().next(current_millis(), key_generator.get_worker_id()))
+        }
+        KeyType::SEGMENT => {
+            // Only the table's own range stays locked while its next range is allocated.
+            let key_range = KEY_RANGES.lock().unwrap().entry(table.to_string()).or_default().clone();
+            let mut key_range = key_range.lock().await;
+            if key_range.is_exhausted() {
+                *key_range = allocate_key_range(table, key_generator.get_step().max(1), cluster).await?;
+            }
+            Ok(key_range.next())
+        }
+    }
+}
+
+fn current_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Milliseconds since the epoch, worker id and a sequence within the millisecond. When the
+/// sequence of a millisecond runs out or the clock goes back, the keys borrow the following
+/// milliseconds, so they keep increasing.
+#[derive(Debug, Default)]
+pub struct Snowflake {
+    last_timestamp: u64,
+    sequence: u64,
+}
+
+impl Snowflake {
+    pub fn next(&mut self, now_millis: u64, worker_id: u16) -> u64 {
+        let timestamp = now_millis.saturating_sub(SNOWFLAKE_EPOCH);
+        if timestamp > self.last_timestamp {
+            self.last_timestamp = timestamp;
+            self.sequence = 0;
+        } else {
+            self.sequence = (self.sequence + 1) & SEQUENCE_MASK;
+            if self.sequence == 0 {
+                self.last_timestamp += 1;
+            }
+        }
+        (self.last_timestamp << (WORKER_ID_BITS + SEQUENCE_BITS)) | ((worker_id as u64) << SEQUENCE_BITS) | self.sequence
+    }
+}
+
+/// Keys `next..end` a `SEGMENT` generator took from the meta segment and has not used yet.
+#[derive(Debug, Default)]
+struct KeyRange {
+    next: u64,
+    end: u64,
+}
+
+impl KeyRange {
+    fn is_exhausted(&self) -> bool {
+        self.next >= self.end
+    }
+
+    fn next(&mut self) -> u64 {
+        let key = self.next;
+        self.next += 1;
+        key
+    }
+}
+
+/// Take the next `step` keys of the table from the meta segment. `LAST_INSERT_ID(expr)`
+/// hands the new maximum back to this connection alone, so proxies never share a range.
+async fn allocate_key_range(table: &str, step: u64, cluster: &Cluster) -> KeyGenResult<KeyRange> {
+    let meta_segment = cluster.get_segments().get_meta_segment().get_primary();
+    let pool = pool::pool(meta_segment, cluster.get_props().get_pool());
+    let mut conn = pool.get().await.map_err(|(_, _, message)| {
+        Error::General(format!("can not allocate keys of {} in the meta segment: {}", table, message))
+    })?;
+    match allocate(&mut conn, table, step).await {
+        Ok(max_key) => Ok(KeyRange { next: max_key - step + 1, end: max_key + 1 }),
+        Err((code, _, message)) => {
+            if is_connection_error(code) {
+                conn.discard();
+            }
+            Err(Error::General(format!("can not allocate keys of {} in the meta segment: {} {}", table, code, message)))
+        }
+    }
+}
+
+async fn allocate(conn: &mut MySQLClient, table: &str, step: u64) -> ExecuteResult<u64> {
+    conn.query_drop("CREATE TABLE IF NOT EXISTS dbmesh_key_range (\
+                     table_name VARCHAR(192) NOT NULL PRIMARY KEY, \
+                     max_key BIGINT UNSIGNED NOT NULL)").await?;
+    let result_sets = conn.execute("INSERT INTO dbmesh_key_range (table_name, max_key) VALUES (?, ?) \
+                                    ON DUPLICATE KEY UPDATE max_key = LAST_INSERT_ID(max_key + VALUES(max_key))",
+                                   &[PrepareParamValue::Bytes(table.as_bytes().to_vec()), PrepareParamValue::UInt(step)]).await?;
+    // Zero when the row was just created.
+    Ok(match result_sets.first() {
+        Some(ShardResultSet::Update { last_insert_id, .. }) if *last_insert_id != 0 => *last_insert_id,
+        _ => step,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::keygen::{generate_keys, Snowflake};
+    use crate::handler::database::mysql::rdbc::block_on;
+    use crate::handler::database::parser::sql::mysql::parser;
+
+    #[test]
+    fn test_snowflake() {
+        let mut snowflake = Snowflake::default();
+        let now = 1609459200000 + 1000;
+        let first = snowflake.next(now, 1);
+        assert_eq!((1000 << 22) | (1 << 12), first);
+        assert_eq!(first + 1, snowflake.next(now, 1));
+        // The clock went back.
+        assert_eq!(first + 2, snowflake.next(now - 5, 1));
+
+        let mut keys: Vec<u64> = (0..10000).map(|_| snowflake.next(now, 1)).collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        keys.dedup();
+        assert_eq!(10000, keys.len());
+    }
+
+    #[test]
+    fn test_generate_keys() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+
+        let mut statement = parser("INSERT INTO t_order (user_id, status) VALUES (10, 'NEW'), (11, 'NEW')".to_string()).pop().unwrap();
+        let first_key = block_on(generate_keys(&mut statement, &cluster)).unwrap().unwrap();
+        let sql = statement.to_string();
+        let prefix = format!("INSERT INTO t_order (user_id, status, order_id) VALUES (10, 'NEW', {}), (11, 'NEW', ", first_key);
+        assert!(sql.starts_with(prefix.as_str()));
+        let second_key: u64 = sql[prefix.len()..sql.len() - 1].parse().unwrap();
+        assert!(second_key > first_key);
+
+        let mut statement = parser("INSERT INTO t_order (order_id, user_id) VALUES (7, 10), (NULL, 11)".to_string()).pop().unwrap();
+        let key = block_on(generate_keys(&mut statement, &cluster)).unwrap().unwrap();
+        assert_eq!(format!("INSERT INTO t_order (order_id, user_id) VALUES (7, 10), ({}, 11)", key), statement.to_string());
+
+        let mut statement = parser("INSERT INTO t_order_item (user_id) VALUES (10)".to_string()).pop().unwrap();
+        assert_eq!(None, block_on(generate_keys(&mut statement, &cluster)).unwrap());
+    }
+}
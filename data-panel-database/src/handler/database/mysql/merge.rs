@@ -0,0 +1,731 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::sync::Arc;
+
+use sqlparser::ast::{Expr, OrderByExpr, SelectItem};
+
+use crate::handler::database::mysql::aggregate::{AggregationPlan, group_key, same_column, too_many_distinct_rows};
+use crate::handler::database::mysql::rdbc::{ExecuteError, ExecuteResult};
+use crate::handler::database::parser::sql::route::Pagination;
+use crate::handler::database::parser::sql::{DerivedColumn, SQLStatementContext};
+use crate::protocol::database::mysql::constant::MySQLColumnType;
+use crate::protocol::database::mysql::resultset::{Column, Value};
+
+/// Rows of a result set, pulled one at a time. An error ends them.
+pub type RowStream = Box<dyn Iterator<Item = ExecuteResult<Vec<Value>>> + Send>;
+
+/// The result sets a data segment answered with, the next one read once the rows of the
+/// one before were. An error ends them.
+pub type ResultSetStream = Box<dyn Iterator<Item = ExecuteResult<ShardResultSet>> + Send>;
+
+/// One result set a data segment answered with.
+pub enum ShardResultSet {
+    Rows {
+        columns: Vec<Column>,
+        rows: RowStream,
+    },
+    Update {
+        affected_rows: u64,
+        last_insert_id: u64,
+    },
+}
+
+/// What the merge stage needs to know about the statement.
+#[derive(Default, Clone)]
+pub struct MergeContext {
+    order_by: Vec<OrderByExpr>,
+    pagination: Option<Pagination>,
+    aggregation: Option<AggregationPlan>,
+    distinct: bool,
+    max_distinct_rows: u64,
+    /// `ORDER BY` items the segments return after the select list, see `order_by_derived_columns`.
+    derived_columns: Vec<DerivedColumn>,
+}
+
+impl MergeContext {
+    pub fn new(stmt_ctx: &SQLStatementContext) -> Self {
+        MergeContext {
+            order_by: stmt_ctx.get_order_by().to_vec(),
+            pagination: None,
+            aggregation: None,
+            distinct: false,
+            max_distinct_rows: 0,
+            derived_columns: vec![],
+        }
+    }
+
+    pub fn get_order_by(&self) -> &[OrderByExpr] {
+        self.order_by.as_slice()
+    }
+
+    /// Set when the segments were asked for the first `offset + limit` rows each.
+    pub fn set_pagination(&mut self, pagination: Option<Pagination>) {
+        self.pagination = pagination;
+    }
+
+    pub fn get_pagination(&self) -> Option<&Pagination> {
+        self.pagination.as_ref()
+    }
+
+    /// Set when the segments return partial aggregates which have to be regrouped.
+    pub fn set_aggregation(&mut self, aggregation: Option<AggregationPlan>) {
+        self.aggregation = aggregation;
+    }
+
+    pub fn get_aggregation(&self) -> Option<&AggregationPlan> {
+        self.aggregation.as_ref()
+    }
+
+    /// Set when equal rows of different segments have to be removed for `SELECT DISTINCT`.
+    pub fn set_distinct(&mut self, distinct: bool) {
+        self.distinct = distinct;
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// Most distinct rows or values the merge keeps in memory, 0 for no limit.
+    pub fn set_max_distinct_rows(&mut self, max_distinct_rows: u64) {
+        self.max_distinct_rows = max_distinct_rows;
+    }
+
+    pub fn get_max_distinct_rows(&self) -> u64 {
+        self.max_distinct_rows
+    }
+
+    /// Set when the rows are sorted on columns the client did not select.
+    pub fn set_derived_columns(&mut self, derived_columns: Vec<DerivedColumn>) {
+        self.derived_columns = derived_columns;
+    }
+
+    pub fn get_derived_columns(&self) -> &[DerivedColumn] {
+        self.derived_columns.as_slice()
+    }
+}
+
+/// The `ORDER BY` items of a query which are not in its select list, added to it for the
+/// merge to sort on and dropped afterwards. Positions refer to the select list and a
+/// wildcard selects every column. `SELECT DISTINCT` can not sort on them, the segments
+/// report that.
+pub fn order_by_derived_columns(stmt_ctx: &SQLStatementContext) -> Vec<DerivedColumn> {
+    let projection = stmt_ctx.get_projection();
+    if projection.is_empty() || stmt_ctx.is_distinct() {
+        return vec![];
+    }
+    let wildcard = projection.iter().any(|item| matches!(item, SelectItem::Wildcard | SelectItem::QualifiedWildcard(_)));
+    let mut derived_columns: Vec<DerivedColumn> = vec![];
+    for order_by_expr in stmt_ctx.get_order_by() {
+        let expr = &order_by_expr.expr;
+        let selected = match expr {
+            Expr::Value(sqlparser::ast::Value::Number(_, _)) => true,
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) if wildcard => true,
+            _ => projection.iter().any(|item| match item {
+                SelectItem::UnnamedExpr(item_expr) => same_column(item_expr, expr),
+                SelectItem::ExprWithAlias { expr: item_expr, alias } => {
+                    same_column(item_expr, expr) || matches!(expr, Expr::Identifier(ident) if ident.value.eq_ignore_ascii_case(alias.value.as_str()))
+                }
+                _ => false,
+            }),
+        };
+        if !selected && !derived_columns.iter().any(|d| d.get_expr() == expr) {
+            let alias = format!("DP_DERIVED_ORDER_BY_{}", derived_columns.len());
+            derived_columns.push(DerivedColumn::new(expr.clone(), alias));
+        }
+    }
+    derived_columns
+}
+
+/// Combine the result sets of the data segments, in task order, into the result sets
+/// of the statement: the n-th result set of every segment goes into the n-th one. Each is
+/// merged when the client gets to it and its rows are pulled from the segments as the
+/// client takes them.
+pub fn merge(responses: Vec<ResultSetStream>, merge_ctx: &MergeContext) -> MergedResultSets {
+    MergedResultSets {
+        responses,
+        merge_ctx: merge_ctx.clone(),
+    }
+}
+
+/// The merged result sets of the statement, see `merge`.
+pub struct MergedResultSets {
+    responses: Vec<ResultSetStream>,
+    merge_ctx: MergeContext,
+}
+
+impl Iterator for MergedResultSets {
+    type Item = ExecuteResult<ShardResultSet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut group = Vec::with_capacity(self.responses.len());
+        for response in self.responses.iter_mut() {
+            match response.next() {
+                Some(Ok(result_set)) => group.push(result_set),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {}
+            }
+        }
+        if group.is_empty() {
+            return None;
+        }
+        Some(merge_group(group, &self.merge_ctx))
+    }
+}
+
+fn merge_group(group: Vec<ShardResultSet>, merge_ctx: &MergeContext) -> ExecuteResult<ShardResultSet> {
+    let mut merged_columns = None;
+    let mut streams = Vec::new();
+    let mut total_affected_rows = 0;
+    let mut max_last_insert_id = 0;
+    for result_set in group {
+        match result_set {
+            ShardResultSet::Rows { columns, rows } => {
+                if merged_columns.is_none() {
+                    merged_columns = Some(columns);
+                }
+                streams.push(rows);
+            }
+            ShardResultSet::Update { affected_rows, last_insert_id } => {
+                total_affected_rows += affected_rows;
+                max_last_insert_id = max_last_insert_id.max(last_insert_id);
+            }
+        }
+    }
+
+    let mut columns = match merged_columns {
+        Some(columns) => columns,
+        None => {
+            return Ok(ShardResultSet::Update {
+                affected_rows: total_affected_rows,
+                last_insert_id: max_last_insert_id,
+            });
+        }
+    };
+    let mut rows: RowStream = if let Some(aggregation) = merge_ctx.get_aggregation() {
+        let (visible_columns, groups) = aggregation.merge(columns, streams, merge_ctx.get_max_distinct_rows())?;
+        columns = visible_columns;
+        Box::new(groups.into_iter().map(Ok))
+    } else if streams.len() > 1 && !merge_ctx.get_order_by().is_empty() {
+        let sort_keys = sort_keys(merge_ctx.get_order_by(), &columns, merge_ctx.get_derived_columns())?;
+        Box::new(OrderByMerge::new(streams, sort_keys))
+    } else {
+        Box::new(streams.into_iter().flatten())
+    };
+    let derived_count = merge_ctx.get_derived_columns().len();
+    if merge_ctx.get_aggregation().is_none() && derived_count > 0 {
+        let visible_count = columns.len().saturating_sub(derived_count);
+        columns.truncate(visible_count);
+        rows = Box::new(rows.map(move |row| row.map(|mut row| {
+            row.truncate(visible_count);
+            row
+        })));
+    }
+    if merge_ctx.is_distinct() {
+        let kinds: Vec<SortKind> = columns.iter().map(SortKind::of).collect();
+        rows = Box::new(distinct_rows(rows, kinds, merge_ctx.get_max_distinct_rows()));
+    }
+    if let Some(pagination) = merge_ctx.get_pagination() {
+        rows = paginate(rows, pagination);
+    }
+    Ok(ShardResultSet::Rows { columns, rows })
+}
+
+/// Skip the `offset` first merged rows and stop after `limit`, without reading further.
+/// An error is not skipped with the rows.
+pub fn paginate<T, I>(rows: I, pagination: &Pagination) -> Box<dyn Iterator<Item = ExecuteResult<T>> + Send>
+    where T: 'static, I: Iterator<Item = ExecuteResult<T>> + Send + 'static {
+    let mut offset = pagination.get_offset();
+    let rows = rows.filter(move |row| {
+        if offset > 0 && row.is_ok() {
+            offset -= 1;
+            false
+        } else {
+            true
+        }
+    });
+    match pagination.get_limit() {
+        Some(limit) => Box::new(rows.take(limit as usize)),
+        None => Box::new(rows),
+    }
+}
+
+/// Keep the first of equal rows, in merged order. Rows are equal when every column
+/// compares equal, NULLs included, as for `SELECT DISTINCT`. Only the keys of the rows
+/// already returned are kept.
+pub fn distinct_rows<I>(rows: I, kinds: Vec<SortKind>, max_distinct_rows: u64) -> impl Iterator<Item = ExecuteResult<Vec<Value>>>
+    where I: Iterator<Item = ExecuteResult<Vec<Value>>> {
+    let mut seen = HashSet::new();
+    rows.filter_map(move |row| {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        let key: Vec<Option<Vec<u8>>> = row.iter().zip(kinds.iter()).map(|(value, kind)| group_key(value, *kind)).collect();
+        if !seen.insert(key) {
+            return None;
+        }
+        if max_distinct_rows > 0 && seen.len() as u64 > max_distinct_rows {
+            return Some(Err(too_many_distinct_rows(max_distinct_rows)));
+        }
+        Some(Ok(row))
+    })
+}
+
+/// How the values of a column compare.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKind {
+    Numeric,
+    /// Byte by byte, for binary strings and temporal values.
+    Binary,
+    /// Like MySQL's default `_general_ci` collations: case insensitive, trailing spaces ignored.
+    Collated,
+}
+
+impl SortKind {
+    pub fn of(column: &Column) -> Self {
+        match column.get_column_type() {
+            MySQLColumnType::MysqlTypeDecimal
+            | MySQLColumnType::MysqlTypeNewDecimal
+            | MySQLColumnType::MysqlTypeTiny
+            | MySQLColumnType::MysqlTypeShort
+            | MySQLColumnType::MysqlTypeLong
+            | MySQLColumnType::MysqlTypeInt24
+            | MySQLColumnType::MysqlTypeLonglong
+            | MySQLColumnType::MysqlTypeFloat
+            | MySQLColumnType::MysqlTypeDouble
+            | MySQLColumnType::MysqlTypeYear
+            | MySQLColumnType::MysqlTypeBit => SortKind::Numeric,
+            MySQLColumnType::MysqlTypeVarchar
+            | MySQLColumnType::MysqlTypeVarString
+            | MySQLColumnType::MysqlTypeString
+            | MySQLColumnType::MysqlTypeTinyBlob
+            | MySQLColumnType::MysqlTypeMediumBlob
+            | MySQLColumnType::MysqlTypeLongBlob
+            | MySQLColumnType::MysqlTypeBlob
+            | MySQLColumnType::MysqlTypeEnum
+            | MySQLColumnType::MysqlTypeSet
+            | MySQLColumnType::MysqlTypeJson if column.get_character_set() != BINARY_CHARSET => SortKind::Collated,
+            _ => SortKind::Binary,
+        }
+    }
+}
+
+/// Character set id of `binary` strings.
+const BINARY_CHARSET: u16 = 63;
+
+/// A resolved `ORDER BY` item: the column of the result set it sorts on and the direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    index: usize,
+    kind: SortKind,
+    asc: bool,
+    nulls_first: Option<bool>,
+}
+
+impl SortKey {
+    pub fn new(index: usize, kind: SortKind, asc: bool, nulls_first: Option<bool>) -> Self {
+        SortKey {
+            index,
+            kind,
+            asc,
+            nulls_first,
+        }
+    }
+}
+
+/// Find the result set column of every `ORDER BY` item, by position, name, alias or
+/// by the expression text the server uses as name of a computed column. The derived
+/// columns come last, after those the client selected.
+pub fn sort_keys(order_by: &[OrderByExpr], columns: &[Column], derived_columns: &[DerivedColumn]) -> ExecuteResult<Vec<SortKey>> {
+    let visible_count = columns.len().saturating_sub(derived_columns.len());
+    let visible_columns = &columns[..visible_count];
+    let mut sort_keys = Vec::with_capacity(order_by.len());
+    for order_by_expr in order_by {
+        let index = match &order_by_expr.expr {
+            Expr::Value(sqlparser::ast::Value::Number(n, _)) => {
+                n.parse::<usize>().ok().filter(|n| *n >= 1 && *n <= visible_count).map(|n| n - 1)
+            }
+            expr => match derived_columns.iter().position(|d| d.get_expr() == expr) {
+                Some(index) => Some(visible_count + index),
+                None => match expr {
+                    Expr::Identifier(ident) => find_column(visible_columns, None, ident.value.as_str()),
+                    Expr::CompoundIdentifier(idents) if !idents.is_empty() => {
+                        let qualifier = if idents.len() > 1 { Some(idents[idents.len() - 2].value.as_str()) } else { None };
+                        find_column(visible_columns, qualifier, idents[idents.len() - 1].value.as_str())
+                    }
+                    expr => find_column(visible_columns, None, expr.to_string().as_str()),
+                },
+            },
+        };
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let err_message = format!("Unknown column '{}' in 'order clause'", order_by_expr.expr);
+                return Err((1054, String::from("42S22"), err_message));
+            }
+        };
+        sort_keys.push(SortKey::new(index, SortKind::of(&columns[index]), order_by_expr.asc.unwrap_or(true), order_by_expr.nulls_first));
+    }
+    Ok(sort_keys)
+}
+
+fn find_column(columns: &[Column], qualifier: Option<&str>, name: &str) -> Option<usize> {
+    let mut matches = columns.iter().enumerate()
+        .filter(|(_, c)| c.get_name().eq_ignore_ascii_case(name));
+    match qualifier {
+        Some(qualifier) => {
+            let matched: Vec<(usize, &Column)> = matches.collect();
+            matched.iter()
+                .find(|(_, c)| c.get_table().eq_ignore_ascii_case(qualifier) || c.get_org_table().eq_ignore_ascii_case(qualifier))
+                .or_else(|| matched.first())
+                .map(|(index, _)| *index)
+        }
+        None => matches.next().map(|(index, _)| index),
+    }
+}
+
+/// Access to the column values of a row.
+pub trait RowValues {
+    fn get_value(&self, index: usize) -> Option<&Value>;
+}
+
+impl RowValues for Vec<Value> {
+    fn get_value(&self, index: usize) -> Option<&Value> {
+        self.get(index)
+    }
+}
+
+pub fn compare_rows<R: RowValues>(a: &R, b: &R, sort_keys: &[SortKey]) -> Ordering {
+    for sort_key in sort_keys {
+        let a = a.get_value(sort_key.index).unwrap_or(&Value::NULL);
+        let b = b.get_value(sort_key.index).unwrap_or(&Value::NULL);
+        let ordering = compare_sort_values(a, b, sort_key);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+fn compare_sort_values(a: &Value, b: &Value, sort_key: &SortKey) -> Ordering {
+    let ordering = match (a, b) {
+        (Value::NULL, Value::NULL) => return Ordering::Equal,
+        // MySQL sorts NULL as the smallest value unless told otherwise.
+        (Value::NULL, _) => return null_ordering(sort_key),
+        (_, Value::NULL) => return null_ordering(sort_key).reverse(),
+        (a, b) => compare_values(a, b, sort_key.kind),
+    };
+    if sort_key.asc { ordering } else { ordering.reverse() }
+}
+
+fn null_ordering(sort_key: &SortKey) -> Ordering {
+    match sort_key.nulls_first {
+        Some(true) => Ordering::Less,
+        Some(false) => Ordering::Greater,
+        None if sort_key.asc => Ordering::Less,
+        None => Ordering::Greater,
+    }
+}
+
+/// Compare two non NULL values of a column.
+pub fn compare_values(a: &Value, b: &Value, kind: SortKind) -> Ordering {
+    match (a, b) {
+        (Value::Bytes(a), Value::Bytes(b)) => match kind {
+            SortKind::Numeric => compare_numeric_text(a, b),
+            SortKind::Binary => a.cmp(b),
+            SortKind::Collated => collate(a, b),
+        },
+        (Value::Date(..), Value::Date(..)) | (Value::Time(..), Value::Time(..)) => temporal_key(a).cmp(&temporal_key(b)),
+        _ => match (numeric(a), numeric(b)) {
+            (Some(Number::Int(a)), Some(Number::Int(b))) => a.cmp(&b),
+            (Some(a), Some(b)) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+            _ => a.to_sql().cmp(&b.to_sql()),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(&self) -> f64 {
+        match self {
+            Number::Int(i) => *i as f64,
+            Number::Float(f) => *f,
+        }
+    }
+}
+
+fn numeric(value: &Value) -> Option<Number> {
+    match value {
+        Value::Int(i) => Some(Number::Int(*i as i128)),
+        Value::UInt(u) => Some(Number::Int(*u as i128)),
+        Value::Float(f) => Some(Number::Float(*f as f64)),
+        Value::Double(f) => Some(Number::Float(*f)),
+        Value::Bytes(bytes) => parse_number(bytes),
+        _ => None,
+    }
+}
+
+fn parse_number(bytes: &[u8]) -> Option<Number> {
+    let text = std::str::from_utf8(bytes).ok()?.trim();
+    match text.parse::<i128>() {
+        Ok(i) => Some(Number::Int(i)),
+        Err(_) => text.parse::<f64>().ok().map(Number::Float),
+    }
+}
+
+fn compare_numeric_text(a: &[u8], b: &[u8]) -> Ordering {
+    match (parse_number(a), parse_number(b)) {
+        (Some(Number::Int(a)), Some(Number::Int(b))) => a.cmp(&b),
+        (Some(a), Some(b)) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+/// Microseconds since year 0 for dates, signed microseconds for times.
+fn temporal_key(value: &Value) -> i128 {
+    match value {
+        Value::Date(year, month, day, hour, minutes, seconds, micro_seconds) => {
+            let days = (*year as i128 * 12 + *month as i128) * 31 + *day as i128;
+            ((days * 24 + *hour as i128) * 60 + *minutes as i128) * 60_000_000 + *seconds as i128 * 1_000_000 + *micro_seconds as i128
+        }
+        Value::Time(is_negative, days, hours, minutes, seconds, micro_seconds) => {
+            let micros = (((*days as i128 * 24 + *hours as i128) * 60 + *minutes as i128) * 60 + *seconds as i128) * 1_000_000 + *micro_seconds as i128;
+            if *is_negative { -micros } else { micros }
+        }
+        _ => 0,
+    }
+}
+
+fn collate(a: &[u8], b: &[u8]) -> Ordering {
+    let a = String::from_utf8_lossy(a);
+    let b = String::from_utf8_lossy(b);
+    let a = a.trim_end_matches(' ').chars().flat_map(char::to_lowercase);
+    let b = b.trim_end_matches(' ').chars().flat_map(char::to_lowercase);
+    a.cmp(b)
+}
+
+/// The head row of one segment's stream, ordered so that `BinaryHeap` pops the smallest.
+struct MergeEntry<R> {
+    row: R,
+    shard: usize,
+    sort_keys: Arc<Vec<SortKey>>,
+}
+
+impl<R: RowValues> Ord for MergeEntry<R> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_rows(&other.row, &self.row, &self.sort_keys)
+            .then_with(|| other.shard.cmp(&self.shard))
+    }
+}
+
+impl<R: RowValues> PartialOrd for MergeEntry<R> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<R: RowValues> PartialEq for MergeEntry<R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<R: RowValues> Eq for MergeEntry<R> {}
+
+/// K-way merge of streams which are each sorted by the same keys; only the head row
+/// of every stream is held in memory. The error of a stream ends the merge.
+pub struct OrderByMerge<R, I> {
+    streams: Vec<I>,
+    heap: BinaryHeap<MergeEntry<R>>,
+    sort_keys: Arc<Vec<SortKey>>,
+    error: Option<ExecuteError>,
+}
+
+impl<R: RowValues, I: Iterator<Item = ExecuteResult<R>>> OrderByMerge<R, I> {
+    pub fn new(streams: Vec<I>, sort_keys: Vec<SortKey>) -> Self {
+        let heap = BinaryHeap::with_capacity(streams.len());
+        let mut merge = OrderByMerge {
+            streams,
+            heap,
+            sort_keys: Arc::new(sort_keys),
+            error: None,
+        };
+        for shard in 0..merge.streams.len() {
+            merge.pull(shard);
+        }
+        merge
+    }
+
+    /// Put the next row of a stream on the heap.
+    fn pull(&mut self, shard: usize) {
+        match self.streams[shard].next() {
+            Some(Ok(row)) => self.heap.push(MergeEntry { row, shard, sort_keys: self.sort_keys.clone() }),
+            Some(Err(e)) => {
+                self.error.get_or_insert(e);
+            }
+            None => {}
+        }
+    }
+}
+
+impl<R: RowValues, I: Iterator<Item = ExecuteResult<R>>> Iterator for OrderByMerge<R, I> {
+    type Item = ExecuteResult<R>;
+
+    fn next(&mut self) -> Option<ExecuteResult<R>> {
+        if let Some(e) = self.error.take() {
+            self.heap.clear();
+            return Some(Err(e));
+        }
+        let entry = self.heap.pop()?;
+        self.pull(entry.shard);
+        Some(Ok(entry.row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use sqlparser::ast::{Expr, Ident, OrderByExpr};
+
+    use crate::handler::database::mysql::merge::{distinct_rows, merge, MergeContext, OrderByMerge, paginate, ResultSetStream, ShardResultSet, sort_keys, SortKey, SortKind};
+    use crate::handler::database::mysql::rdbc::ExecuteResult;
+    use crate::handler::database::parser::sql::DerivedColumn;
+    use crate::handler::database::parser::sql::route::Pagination;
+    use crate::protocol::database::mysql::constant::MySQLColumnType;
+    use crate::protocol::database::mysql::resultset::{Column, Value};
+
+    fn text(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    fn stream(rows: Vec<Vec<Value>>) -> impl Iterator<Item = ExecuteResult<Vec<Value>>> {
+        rows.into_iter().map(Ok)
+    }
+
+    #[test]
+    fn test_merge_update() {
+        let responses: Vec<ResultSetStream> = vec![
+            Box::new(vec![Ok(ShardResultSet::Update { affected_rows: 2, last_insert_id: 7 })].into_iter()),
+            Box::new(vec![Ok(ShardResultSet::Update { affected_rows: 3, last_insert_id: 5 })].into_iter()),
+        ];
+        let merged: Vec<ShardResultSet> = merge(responses, &MergeContext::default()).collect::<ExecuteResult<_>>().unwrap();
+        assert_eq!(1, merged.len());
+        match merged[0] {
+            ShardResultSet::Update { affected_rows, last_insert_id } => {
+                assert_eq!(5, affected_rows);
+                assert_eq!(7, last_insert_id);
+            }
+            ShardResultSet::Rows { .. } => panic!("expected an update result"),
+        }
+    }
+
+    #[test]
+    fn test_order_by_merge() {
+        // ORDER BY 1 DESC, 2
+        let streams = vec![
+            stream(vec![vec![text("10"), text("b")], vec![text("9"), text("A")], vec![Value::NULL, text("x")]]),
+            stream(vec![vec![text("10"), text("a")], vec![text("2"), text("c")]]),
+            stream(vec![]),
+            stream(vec![vec![text("11"), text("z")], vec![text("9"), text("a ")]]),
+        ];
+        let sort_keys = vec![SortKey::new(0, SortKind::Numeric, false, None), SortKey::new(1, SortKind::Collated, true, None)];
+        let rows: Vec<Vec<Value>> = OrderByMerge::new(streams, sort_keys).collect::<ExecuteResult<_>>().unwrap();
+        let expected = vec![
+            vec![text("11"), text("z")],
+            vec![text("10"), text("a")],
+            vec![text("10"), text("b")],
+            vec![text("9"), text("A")],
+            vec![text("9"), text("a ")],
+            vec![text("2"), text("c")],
+            vec![Value::NULL, text("x")],
+        ];
+        assert_eq!(expected, rows);
+    }
+
+    #[test]
+    fn test_order_by_merge_nulls_and_binary() {
+        let streams = vec![
+            stream(vec![vec![Value::NULL], vec![text("B")]]),
+            stream(vec![vec![text("a")]]),
+        ];
+        let rows: Vec<Vec<Value>> = OrderByMerge::new(streams, vec![SortKey::new(0, SortKind::Binary, true, None)]).collect::<ExecuteResult<_>>().unwrap();
+        assert_eq!(vec![vec![Value::NULL], vec![text("B")], vec![text("a")]], rows);
+
+        let streams = vec![
+            stream(vec![vec![Value::Int(1)], vec![Value::NULL]]),
+            stream(vec![vec![Value::Int(-3)]]),
+        ];
+        let rows: Vec<Vec<Value>> = OrderByMerge::new(streams, vec![SortKey::new(0, SortKind::Numeric, true, Some(false))]).collect::<ExecuteResult<_>>().unwrap();
+        assert_eq!(vec![vec![Value::Int(-3)], vec![Value::Int(1)], vec![Value::NULL]], rows);
+    }
+
+    #[test]
+    fn test_paginate_merged_rows() {
+        let streams = vec![
+            stream(vec![vec![Value::Int(1)], vec![Value::Int(4)], vec![Value::Int(5)]]),
+            stream(vec![vec![Value::Int(2)], vec![Value::Int(3)], vec![Value::Int(6)]]),
+        ];
+        let merged = OrderByMerge::new(streams, vec![SortKey::new(0, SortKind::Numeric, true, None)]);
+        // LIMIT 2 OFFSET 2, each segment returned its first 4 rows
+        let rows: Vec<Vec<Value>> = paginate(merged, &Pagination::new(2, Some(2))).collect::<ExecuteResult<_>>().unwrap();
+        assert_eq!(vec![vec![Value::Int(3)], vec![Value::Int(4)]], rows);
+    }
+
+    #[test]
+    fn test_distinct_rows() {
+        let text = |s: &str| Value::Bytes(s.as_bytes().to_vec());
+        let rows = vec![
+            vec![text("a"), Value::Int(1)],
+            vec![text("A "), Value::Int(1)],
+            vec![text("a"), Value::NULL],
+            vec![text("a"), Value::NULL],
+            vec![text("b"), Value::Int(1)],
+        ];
+        let kinds = vec![SortKind::Collated, SortKind::Numeric];
+        let distinct: Vec<Vec<Value>> = distinct_rows(stream(rows.clone()), kinds.clone(), 0).collect::<ExecuteResult<_>>().unwrap();
+        assert_eq!(vec![vec![text("a"), Value::Int(1)], vec![text("a"), Value::NULL], vec![text("b"), Value::Int(1)]], distinct);
+
+        let err = distinct_rows(stream(rows), kinds, 2).collect::<ExecuteResult<Vec<_>>>().unwrap_err();
+        assert_eq!(1038, err.0);
+    }
+
+    #[test]
+    fn test_merge_stops_at_error() {
+        let failed = (1146, String::from("42S02"), String::from("Table 't_order_1' doesn't exist"));
+        let streams = vec![
+            vec![Ok(vec![Value::Int(1)]), Ok(vec![Value::Int(3)])].into_iter(),
+            vec![Ok(vec![Value::Int(2)]), Err(failed.clone())].into_iter(),
+        ];
+        let rows: Vec<ExecuteResult<Vec<Value>>> = OrderByMerge::new(streams, vec![SortKey::new(0, SortKind::Numeric, true, None)]).collect();
+        assert_eq!(vec![Ok(vec![Value::Int(1)]), Ok(vec![Value::Int(2)]), Err(failed.clone())], rows);
+
+        // The error is not skipped as one of the offset rows.
+        let rows = vec![Ok(vec![Value::Int(1)]), Err(failed.clone()), Ok(vec![Value::Int(2)])];
+        let rows: Vec<ExecuteResult<Vec<Value>>> = paginate(rows.into_iter(), &Pagination::new(2, Some(1))).collect();
+        assert_eq!(vec![Err(failed)], rows);
+    }
+
+    #[test]
+    fn test_sort_keys_of_derived_columns() {
+        let columns = vec![
+            Column::new(MySQLColumnType::MysqlTypeLonglong).with_name("order_id"),
+            Column::new(MySQLColumnType::MysqlTypeDatetime).with_name("DP_DERIVED_ORDER_BY_0"),
+        ];
+        let create_time = Expr::Identifier(Ident::new("create_time"));
+        let derived_columns = vec![DerivedColumn::new(create_time.clone(), String::from("DP_DERIVED_ORDER_BY_0"))];
+        let order_by = vec![
+            OrderByExpr { expr: create_time, asc: Some(false), nulls_first: None },
+            OrderByExpr { expr: Expr::Identifier(Ident::new("order_id")), asc: None, nulls_first: None },
+        ];
+        assert_eq!(vec![SortKey::new(1, SortKind::Binary, false, None), SortKey::new(0, SortKind::Numeric, true, None)],
+                   sort_keys(&order_by, &columns, &derived_columns).unwrap());
+
+        let err = sort_keys(&order_by, &columns[..1], &[]).unwrap_err();
+        assert_eq!(1054, err.0);
+    }
+}
@@ -0,0 +1,706 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use sqlparser::ast::{Ident, Query, SetExpr, ShowStatementFilter, Statement, TableAlias, TableFactor, TableWithJoins};
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{Cluster, DisPartition};
+use crate::handler::database::mysql::explainplan::PlanTask;
+use crate::handler::database::mysql::merge::ShardResultSet;
+use crate::handler::database::mysql::pool::{self, PoolStats};
+use crate::handler::database::mysql::rdbc::{execute_tasks, ExecuteError};
+use crate::handler::database::parser::sql::mysql::parser;
+use crate::handler::database::parser::sql::route::{partition, RouteContext};
+use crate::protocol::database::mysql::constant::MySQLColumnType;
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+use crate::protocol::database::mysql::resultset::{Column, Value};
+
+pub type MetadataResult<T> = data_panel_common::common::Result<T>;
+
+lazy_static! {
+    static ref METADATA_CACHE: DashMap<SchemaTable, Arc<MetadataRows>> = DashMap::new();
+}
+
+/// The tables of `information_schema` the mesh answers with the logical tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SchemaTable {
+    Tables,
+    Columns,
+}
+
+impl SchemaTable {
+    fn of(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("TABLES") {
+            Some(SchemaTable::Tables)
+        } else if name.eq_ignore_ascii_case("COLUMNS") {
+            Some(SchemaTable::Columns)
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SchemaTable::Tables => "TABLES",
+            SchemaTable::Columns => "COLUMNS",
+        }
+    }
+}
+
+/// Rows the mesh answers with itself instead of asking the data segments.
+#[derive(Clone)]
+pub struct MetadataRows {
+    columns: Vec<Column>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl MetadataRows {
+    pub fn new(columns: Vec<Column>, rows: Vec<Vec<Value>>) -> Self {
+        MetadataRows {
+            columns,
+            rows,
+        }
+    }
+
+    pub fn get_columns(&self) -> &[Column] {
+        self.columns.as_slice()
+    }
+
+    pub fn get_rows(&self) -> &[Vec<Value>] {
+        self.rows.as_slice()
+    }
+
+    pub fn to_result_set(&self) -> ShardResultSet {
+        ShardResultSet::Rows { columns: self.columns.clone(), rows: Box::new(self.rows.clone().into_iter().map(Ok)) }
+    }
+
+    fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.get_name().eq_ignore_ascii_case(name))
+    }
+
+    fn value(&self, row: &[Value], name: &str) -> Value {
+        self.column_index(name).and_then(|index| row.get(index).cloned()).unwrap_or(Value::NULL)
+    }
+}
+
+/// Forget the metadata read from the data segments, after a DDL statement changed it.
+pub fn clear_cache() {
+    METADATA_CACHE.clear();
+}
+
+/// The answer to `SHOW TABLES` and to `SHOW COLUMNS` of a logical table, which the data
+/// segments would answer with their physical tables, and to `SHOW DBMESH POOL`. `None` for other statements, for the
+/// tables of another database, and when a `shard` hint asks for the physical tables of a segment.
+pub async fn show(route_ctx: &RouteContext<'_>, sql: &str) -> MetadataResult<Option<MetadataRows>> {
+    if route_ctx.get_hint().and_then(|hint| hint.get_shard()).is_some() {
+        return Ok(None);
+    }
+    let cluster = route_ctx.get_cluster();
+    match route_ctx.get_statement() {
+        Statement::ShowVariable { variable } => {
+            let words: Vec<String> = variable.iter().map(|ident| ident.value.to_uppercase()).collect();
+            if words == ["DBMESH", "POOL"] {
+                return Ok(Some(show_pool(&pool::stats(cluster))));
+            }
+            let full = words.first().map(|w| w == "FULL").unwrap_or(false);
+            if words.get(full as usize).map(|w| w != "TABLES").unwrap_or(true) {
+                return Ok(None);
+            }
+            if words.iter().any(|w| w == "WHERE") {
+                return Err(Error::General(String::from("SHOW TABLES ... WHERE is not supported, use LIKE or information_schema.TABLES")));
+            }
+            // The mesh answers for its own database only, the default segment those of others.
+            let database = database_name(cluster);
+            if let Some(index) = words.iter().position(|w| w == "FROM" || w == "IN") {
+                if variable.get(index + 1).map(|ident| ident.value != database).unwrap_or(true) {
+                    return Ok(None);
+                }
+            }
+            let pattern = if words.iter().any(|w| w == "LIKE") { like_pattern(sql) } else { None };
+            let tables = logical_tables(cluster, &default_tables(cluster).await?);
+            Ok(Some(show_tables(database.as_str(), &tables, full, pattern.as_deref())))
+        }
+        Statement::ShowColumns { full, table_name, filter, .. } => {
+            let table = match table_name.0.last() {
+                Some(ident) if is_logical_table(cluster, ident.value.as_str()) => ident.value.clone(),
+                _ => return Ok(None),
+            };
+            let pattern = match filter {
+                Some(ShowStatementFilter::Like(pattern)) => Some(pattern.as_str()),
+                Some(ShowStatementFilter::Where(_)) => {
+                    return Err(Error::General(String::from("SHOW COLUMNS ... WHERE is not supported, use LIKE or information_schema.COLUMNS")));
+                }
+                None => None,
+            };
+            let columns = logical_rows(cluster, SchemaTable::Columns).await?;
+            Ok(Some(show_columns(&columns, table.as_str(), *full, pattern)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A query of `information_schema.TABLES` or `COLUMNS`, run on the default segment with
+/// those tables replaced by the rows of the logical tables.
+pub async fn information_schema_task(route_ctx: &RouteContext<'_>) -> MetadataResult<Option<PlanTask>> {
+    if route_ctx.get_hint().and_then(|hint| hint.get_shard()).is_some() {
+        return Ok(None);
+    }
+    let query = match route_ctx.get_statement() {
+        Statement::Query(query) => query,
+        _ => return Ok(None),
+    };
+    let cluster = route_ctx.get_cluster();
+    let mut schema_rows = vec![];
+    for schema_table in schema_tables(query) {
+        schema_rows.push((schema_table, logical_rows(cluster, schema_table).await?));
+    }
+    let rows_of = |schema_table: SchemaTable| -> MetadataResult<Arc<MetadataRows>> {
+        schema_rows.iter()
+            .find(|(t, _)| *t == schema_table)
+            .map(|(_, rows)| rows.clone())
+            .ok_or_else(|| Error::General(format!("information_schema.{} was not read", schema_table.name())))
+    };
+    match rewrite_schema_query(query, rows_of)? {
+        Some(query) => {
+            let default_segment_id = cluster.get_segments().get_data_segment_ids()[0];
+            let sql = Statement::Query(Box::new(query)).to_string();
+            Ok(Some(PlanTask::new(default_segment_id, sql, route_ctx.get_parameters().to_vec())))
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_logical_table(cluster: &Cluster, table: &str) -> bool {
+    let dis_rules = cluster.get_dis_rules();
+    dis_rules.is_distributed_table(table) || dis_rules.is_replicated_table(table)
+}
+
+/// The distributed and replicated tables, and the tables of the default segment which are
+/// none of their physical tables, with their type in name order.
+fn logical_tables(cluster: &Cluster, default_tables: &[(String, String)]) -> Vec<(String, String)> {
+    let dis_rules = cluster.get_dis_rules();
+    let base_table = || String::from("BASE TABLE");
+    let mut tables: Vec<(String, String)> = dis_rules.get_distributed_tables().keys()
+        .map(|t| (t.clone(), base_table()))
+        .chain(dis_rules.get_replicated_tables().iter().map(|t| (t.split('[').next().unwrap_or("").to_string(), base_table())))
+        .collect();
+    let database = database_name(cluster);
+    for (table, table_type) in default_tables {
+        let sharded = dis_rules.get_distributed_tables().values().any(|dis_table| {
+            dis_table.get_dis_physical_tables().values()
+                .any(|physical| *physical == *table || *physical == format!("{}.{}", database, table))
+                || dis_table.get_dis_partition().map(|p| is_partition_table(p, table)).unwrap_or(false)
+        });
+        if !sharded && !tables.iter().any(|(t, _)| t == table) {
+            tables.push((table.clone(), table_type.clone()));
+        }
+    }
+    tables.sort();
+    tables
+}
+
+/// Whether the table is named by the `table_format` of the partitions for some period.
+fn is_partition_table(dis_partition: &DisPartition, table: &str) -> bool {
+    let format = dis_partition.get_table_format();
+    let prefix = format.split('%').next().unwrap_or("");
+    let separators: Vec<char> = format[prefix.len()..].chars().filter(|c| *c != '%' && !c.is_ascii_alphabetic()).collect();
+    let length = partition::partition_table(dis_partition, chrono::NaiveDate::from_ymd(2000, 1, 1)).len();
+    table.len() == length && table.starts_with(prefix)
+        && table[prefix.len()..].chars().all(|c| c.is_ascii_digit() || separators.contains(&c))
+}
+
+/// The tables of the database of the default segment with their type, as `SHOW FULL TABLES` lists them.
+async fn default_tables(cluster: &Cluster) -> MetadataResult<Vec<(String, String)>> {
+    let default_segment_id = cluster.get_segments().get_data_segment_ids()[0];
+    let sql = String::from("SELECT TABLE_NAME, TABLE_TYPE FROM information_schema.TABLES WHERE TABLE_SCHEMA = DATABASE()");
+    let read_error = |(code, _, message): ExecuteError| {
+        Error::General(format!("can not read the tables of segment {}: {} {}", default_segment_id, code, message))
+    };
+    let shard_results = execute_tasks(&[PlanTask::new(default_segment_id, sql, vec![])], true).await.map_err(read_error)?;
+    let text = |value: &Value| match value {
+        Value::Bytes(bytes) => String::from_utf8_lossy(bytes).to_string(),
+        _ => String::new(),
+    };
+    let mut tables = vec![];
+    for result_set in shard_results.into_iter().flatten() {
+        if let ShardResultSet::Rows { rows, .. } = result_set {
+            for row in rows {
+                let row = row.map_err(read_error)?;
+                tables.push((text(&row[0]), text(&row[1])));
+            }
+        }
+    }
+    Ok(tables)
+}
+
+fn database_name(cluster: &Cluster) -> String {
+    let default_segment_id = cluster.get_segments().get_data_segment_ids()[0];
+    cluster.get_segments().get_data_segment(default_segment_id)
+        .map(|segment| segment.get_primary().get_database_name().to_string())
+        .unwrap_or_default()
+}
+
+fn string_column(name: &str) -> Column {
+    Column::new(MySQLColumnType::MysqlTypeVarString).with_name(name)
+}
+
+fn show_tables(database: &str, tables: &[(String, String)], full: bool, pattern: Option<&str>) -> MetadataRows {
+    let mut columns = vec![string_column(format!("Tables_in_{}", database).as_str())];
+    if full {
+        columns.push(string_column("Table_type"));
+    }
+    let rows = tables.iter()
+        .filter(|(table, _)| pattern.map(|p| like(p, table)).unwrap_or(true))
+        .map(|(table, table_type)| {
+            let mut row = vec![Value::Bytes(table.clone().into_bytes())];
+            if full {
+                row.push(Value::Bytes(table_type.clone().into_bytes()));
+            }
+            row
+        })
+        .collect();
+    MetadataRows::new(columns, rows)
+}
+
+/// The connections of the pool of every data segment which borrowed one.
+fn show_pool(stats: &[(u32, PoolStats)]) -> MetadataRows {
+    let columns = ["Segment", "Total", "Idle", "In_use", "Waiting", "Created", "Closed", "Timeouts"].iter()
+        .map(|name| Column::new(MySQLColumnType::MysqlTypeLonglong).with_name(name))
+        .collect();
+    let rows = stats.iter()
+        .map(|(segment_id, stats)| {
+            vec![Value::UInt(*segment_id as u64), Value::UInt(stats.get_total() as u64), Value::UInt(stats.get_idle() as u64),
+                 Value::UInt(stats.get_in_use() as u64), Value::UInt(stats.get_waiting() as u64), Value::UInt(stats.get_created()),
+                 Value::UInt(stats.get_closed()), Value::UInt(stats.get_timeouts())]
+        })
+        .collect();
+    MetadataRows::new(columns, rows)
+}
+
+/// The rows of `SHOW [FULL] COLUMNS` from those of `information_schema.COLUMNS`.
+fn show_columns(schema_columns: &MetadataRows, table: &str, full: bool, pattern: Option<&str>) -> MetadataRows {
+    let fields: Vec<(&str, &str)> = if full {
+        vec![("Field", "COLUMN_NAME"), ("Type", "COLUMN_TYPE"), ("Collation", "COLLATION_NAME"), ("Null", "IS_NULLABLE"),
+             ("Key", "COLUMN_KEY"), ("Default", "COLUMN_DEFAULT"), ("Extra", "EXTRA"), ("Privileges", "PRIVILEGES"),
+             ("Comment", "COLUMN_COMMENT")]
+    } else {
+        vec![("Field", "COLUMN_NAME"), ("Type", "COLUMN_TYPE"), ("Null", "IS_NULLABLE"), ("Key", "COLUMN_KEY"),
+             ("Default", "COLUMN_DEFAULT"), ("Extra", "EXTRA")]
+    };
+    let columns = fields.iter().map(|(name, _)| string_column(name)).collect();
+    let rows = schema_columns.get_rows().iter()
+        .filter(|row| schema_columns.value(row, "TABLE_NAME") == Value::Bytes(table.as_bytes().to_vec()))
+        .filter(|row| match (pattern, schema_columns.value(row, "COLUMN_NAME")) {
+            (Some(pattern), Value::Bytes(name)) => like(pattern, String::from_utf8_lossy(&name).as_ref()),
+            _ => true,
+        })
+        .map(|row| fields.iter().map(|(_, schema_column)| schema_columns.value(row, schema_column)).collect())
+        .collect();
+    MetadataRows::new(columns, rows)
+}
+
+/// The rows of an `information_schema` table about the logical tables, read once from the
+/// segment of the first physical table of every logical table and renamed after it.
+async fn logical_rows(cluster: &Cluster, schema_table: SchemaTable) -> MetadataResult<Arc<MetadataRows>> {
+    if let Some(rows) = METADATA_CACHE.get(&schema_table) {
+        return Ok(rows.value().clone());
+    }
+    let tables: Vec<String> = logical_tables(cluster, &default_tables(cluster).await?).into_iter().map(|(table, _)| table).collect();
+    let mut tasks = Vec::with_capacity(tables.len());
+    for table in tables.iter() {
+        let (segment_id, physical_table) = representative(cluster, table)?;
+        let (schema, physical_table) = match physical_table.find('.') {
+            Some(index) => (Some(physical_table[..index].to_string()), physical_table[index + 1..].to_string()),
+            None => (None, physical_table),
+        };
+        let mut parameters = vec![];
+        let mut sql = format!("SELECT * FROM information_schema.{} WHERE ", schema_table.name());
+        match schema {
+            Some(schema) => {
+                sql.push_str("TABLE_SCHEMA = ?");
+                parameters.push(PrepareParamValue::Bytes(schema.into_bytes()));
+            }
+            None => sql.push_str("TABLE_SCHEMA = DATABASE()"),
+        }
+        sql.push_str(" AND TABLE_NAME = ?");
+        parameters.push(PrepareParamValue::Bytes(physical_table.into_bytes()));
+        if schema_table == SchemaTable::Columns {
+            sql.push_str(" ORDER BY ORDINAL_POSITION");
+        }
+        tasks.push(PlanTask::new(segment_id, sql, parameters));
+    }
+    let read_error = |(code, _, message): ExecuteError| {
+        Error::General(format!("can not read information_schema.{}: {} {}", schema_table.name(), code, message))
+    };
+    let shard_results = execute_tasks(&tasks, true).await.map_err(read_error)?;
+
+    let database = Value::Bytes(database_name(cluster).into_bytes());
+    let mut columns = vec![];
+    let mut rows = vec![];
+    for (table, result_sets) in tables.iter().zip(shard_results) {
+        for result_set in result_sets {
+            if let ShardResultSet::Rows { columns: shard_columns, rows: shard_rows } = result_set {
+                if columns.is_empty() {
+                    columns = shard_columns;
+                }
+                let renamed = MetadataRows::new(columns.clone(), vec![]);
+                let schema_index = renamed.column_index("TABLE_SCHEMA");
+                let table_index = renamed.column_index("TABLE_NAME");
+                for row in shard_rows {
+                    let mut row = row.map_err(read_error)?;
+                    if let Some(index) = schema_index {
+                        row[index] = database.clone();
+                    }
+                    if let Some(index) = table_index {
+                        row[index] = Value::Bytes(table.clone().into_bytes());
+                    }
+                    rows.push(row);
+                }
+            }
+        }
+    }
+    let rows = Arc::new(MetadataRows::new(columns, rows));
+    METADATA_CACHE.insert(schema_table, rows.clone());
+    Ok(rows)
+}
+
+/// The data segment and the physical table a logical table is read from.
+fn representative(cluster: &Cluster, table: &str) -> MetadataResult<(u32, String)> {
+    let segment_ids = cluster.get_segments().get_data_segment_ids();
+    let default_segment_id = segment_ids[0];
+    let dis_table = match cluster.get_dis_rules().get_distributed_table(table) {
+        Some(dis_table) => dis_table,
+        None => return Ok((default_segment_id, table.to_string())),
+    };
+    if let Some(dis_partition) = dis_table.get_dis_partition() {
+        let today = chrono::Local::today().naive_local();
+        let units = partition::partition_units(dis_table, dis_partition, &Default::default(), &[], &segment_ids, today)?;
+        if let Some(unit) = units.into_iter().next() {
+            return Ok(unit);
+        }
+        return Ok((default_segment_id, partition::first_partition_table(dis_partition)?));
+    }
+    let physical_table = dis_table.get_dis_physical_table(default_segment_id).cloned().unwrap_or_else(|| table.to_string());
+    Ok((default_segment_id, physical_table))
+}
+
+/// The query with its `information_schema.TABLES` and `COLUMNS` replaced by derived tables
+/// of the given rows, `None` when it reads neither.
+fn rewrite_schema_query<F>(query: &Query, schema_rows: F) -> MetadataResult<Option<Query>>
+    where F: Fn(SchemaTable) -> MetadataResult<Arc<MetadataRows>> {
+    let schema_tables = schema_tables(query);
+    if schema_tables.is_empty() {
+        return Ok(None);
+    }
+    let mut subqueries = Vec::with_capacity(schema_tables.len());
+    for schema_table in schema_tables {
+        subqueries.push((schema_table, rows_query(&schema_rows(schema_table)?)?));
+    }
+    let mut query = query.clone();
+    visit_table_factors(&mut query, &mut |table_factor| {
+        if let Some((schema_table, alias)) = schema_table_factor(table_factor) {
+            if let Some((_, subquery)) = subqueries.iter().find(|(t, _)| *t == schema_table) {
+                *table_factor = TableFactor::Derived {
+                    lateral: false,
+                    subquery: Box::new(subquery.clone()),
+                    alias: Some(alias),
+                };
+            }
+        }
+    });
+    Ok(Some(query))
+}
+
+/// The `information_schema` tables the query reads which the mesh answers for.
+fn schema_tables(query: &Query) -> Vec<SchemaTable> {
+    let mut query = query.clone();
+    let mut schema_tables = vec![];
+    visit_table_factors(&mut query, &mut |table_factor| {
+        if let Some((schema_table, _)) = schema_table_factor(table_factor) {
+            if !schema_tables.contains(&schema_table) {
+                schema_tables.push(schema_table);
+            }
+        }
+    });
+    schema_tables
+}
+
+/// The `information_schema` table a table factor reads, with the alias its rows go by.
+fn schema_table_factor(table_factor: &TableFactor) -> Option<(SchemaTable, TableAlias)> {
+    match table_factor {
+        TableFactor::Table { name, alias, args, .. } if args.is_empty() && name.0.len() == 2 => {
+            if !name.0[0].value.eq_ignore_ascii_case("information_schema") {
+                return None;
+            }
+            let schema_table = SchemaTable::of(name.0[1].value.as_str())?;
+            let alias = alias.clone().unwrap_or_else(|| TableAlias { name: Ident::new(schema_table.name()), columns: vec![] });
+            Some((schema_table, alias))
+        }
+        _ => None,
+    }
+}
+
+fn visit_table_factors(query: &mut Query, visit: &mut dyn FnMut(&mut TableFactor)) {
+    if let Some(with) = query.with.as_mut() {
+        for cte in with.cte_tables.iter_mut() {
+            visit_table_factors(&mut cte.query, visit);
+        }
+    }
+    visit_set_expr(&mut query.body, visit);
+}
+
+fn visit_set_expr(set_expr: &mut SetExpr, visit: &mut dyn FnMut(&mut TableFactor)) {
+    match set_expr {
+        SetExpr::Select(select) => {
+            for table_with_joins in select.from.iter_mut() {
+                visit_table_with_joins(table_with_joins, visit);
+            }
+        }
+        SetExpr::Query(query) => visit_table_factors(query, visit),
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, visit);
+            visit_set_expr(right, visit);
+        }
+        _ => {}
+    }
+}
+
+fn visit_table_with_joins(table_with_joins: &mut TableWithJoins, visit: &mut dyn FnMut(&mut TableFactor)) {
+    visit_table_factor(&mut table_with_joins.relation, visit);
+    for join in table_with_joins.joins.iter_mut() {
+        visit_table_factor(&mut join.relation, visit);
+    }
+}
+
+fn visit_table_factor(table_factor: &mut TableFactor, visit: &mut dyn FnMut(&mut TableFactor)) {
+    match table_factor {
+        TableFactor::Derived { subquery, .. } => visit_table_factors(subquery, visit),
+        TableFactor::NestedJoin(table_with_joins) => visit_table_with_joins(table_with_joins, visit),
+        _ => visit(table_factor),
+    }
+}
+
+/// A query of the rows as literals: `SELECT ... UNION ALL SELECT ...`, or a query with
+/// the columns and no rows.
+fn rows_query(rows: &MetadataRows) -> MetadataResult<Query> {
+    let names: Vec<String> = rows.get_columns().iter().map(|c| c.get_name().replace('`', "``")).collect();
+    let sql = if rows.get_rows().is_empty() {
+        let columns: Vec<String> = names.iter().map(|name| format!("NULL AS `{}`", name)).collect();
+        format!("SELECT {} FROM DUAL WHERE 1 = 0", columns.join(", "))
+    } else {
+        let selects: Vec<String> = rows.get_rows().iter().enumerate()
+            .map(|(index, row)| {
+                let values: Vec<String> = row.iter().zip(names.iter())
+                    .map(|(value, name)| if index == 0 { format!("{} AS `{}`", literal(value), name) } else { literal(value) })
+                    .collect();
+                format!("SELECT {}", values.join(", "))
+            })
+            .collect();
+        selects.join(" UNION ALL ")
+    };
+    match parser(sql.clone()).pop() {
+        Some(Statement::Query(query)) => Ok(*query),
+        _ => Err(Error::General(format!("can not parse {}", sql))),
+    }
+}
+
+fn literal(value: &Value) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''"));
+    match value {
+        Value::NULL => String::from("NULL"),
+        Value::Bytes(bytes) => quote(String::from_utf8_lossy(bytes).as_ref()),
+        Value::Int(i) => i.to_string(),
+        Value::UInt(u) => u.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Double(d) => d.to_string(),
+        Value::Date(year, month, day, hour, minute, second, micros) => {
+            let mut date = format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second);
+            if *micros > 0 {
+                date.push_str(format!(".{:06}", micros).as_str());
+            }
+            quote(date.as_str())
+        }
+        Value::Time(negative, days, hours, minutes, seconds, micros) => {
+            let mut time = format!("{}{:02}:{:02}:{:02}", if *negative { "-" } else { "" },
+                                   *days as u64 * 24 + *hours as u64, minutes, seconds);
+            if *micros > 0 {
+                time.push_str(format!(".{:06}", micros).as_str());
+            }
+            quote(time.as_str())
+        }
+    }
+}
+
+/// The quoted pattern after `LIKE`, which the parser drops from `SHOW TABLES`.
+fn like_pattern(sql: &str) -> Option<String> {
+    let index = sql.to_ascii_uppercase().find(" LIKE ")?;
+    let rest = sql[index + " LIKE ".len()..].trim_start();
+    let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let mut pattern = String::new();
+    let mut chars = rest[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                // Keep the escape, `\_` and `\%` are literal in the pattern.
+                pattern.push(c);
+                pattern.extend(chars.next());
+            }
+            c if c == quote => return Some(pattern),
+            c => pattern.push(c),
+        }
+    }
+    None
+}
+
+/// Whether the text matches the `LIKE` pattern, ignoring case as MySQL does for names.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    like_chars(&pattern, &text)
+}
+
+fn like_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('%') => (0..=text.len()).any(|skip| like_chars(&pattern[1..], &text[skip..])),
+        Some('_') => !text.is_empty() && like_chars(&pattern[1..], &text[1..]),
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && like_chars(&pattern[2..], &text[1..]),
+        Some(c) => text.first() == Some(c) && like_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlparser::ast::Statement;
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::metadata::{like, like_pattern, logical_tables, MetadataRows, rewrite_schema_query, show, show_columns, show_pool, show_tables, string_column};
+    use crate::handler::database::mysql::pool::{self, PoolStats};
+    use crate::handler::database::mysql::rdbc::block_on;
+    use crate::handler::database::parser::sql::mysql::parser;
+    use crate::handler::database::parser::sql::route::RouteContext;
+    use crate::protocol::database::mysql::resultset::Value;
+    use crate::session::mysql::SessionContext;
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("t\\_order%", "t_order_event"));
+        assert!(!like("t\\_order%", "tXorder"));
+        assert!(like("T_ORDER", "t_order"));
+        assert!(like("%dept", "t_dept"));
+        assert!(!like("%dept", "t_dept_1"));
+        assert_eq!(Some(String::from("t\\_order%")), like_pattern("SHOW TABLES LIKE 't\\_order%'"));
+        assert_eq!(None, like_pattern("SHOW TABLES"));
+        assert_eq!(Some(String::from("t_%")), like_pattern("SHOW TABLES FROM `straße` LIKE 't_%'"));
+    }
+
+    #[test]
+    fn test_show_tables() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let default_tables: Vec<(String, String)> = vec![("t_order_0", "BASE TABLE"), ("t_order_event_202001", "BASE TABLE"),
+                                                         ("t_order_event_2020", "BASE TABLE"), ("t_config", "BASE TABLE"),
+                                                         ("t_dept", "BASE TABLE"), ("v_order", "VIEW")].into_iter()
+            .map(|(table, table_type)| (table.to_string(), table_type.to_string()))
+            .collect();
+        let tables = logical_tables(&cluster, &default_tables);
+        let all = vec!["t_config", "t_dept", "t_order", "t_order_event", "t_order_event_2020", "t_order_item", "t_order_log", "t_root", "v_order"];
+        for (sql, expected) in vec![("SHOW TABLES", all),
+                                    ("SHOW TABLES LIKE 't\\_order\\_%'", vec!["t_order_event", "t_order_event_2020", "t_order_item", "t_order_log"]),
+                                    ("SHOW TABLES FROM martlet LIKE 't\\_c%'", vec!["t_config"])] {
+            let rows = show_tables("martlet", &tables, false, like_pattern(sql).as_deref());
+            assert_eq!("Tables_in_martlet", rows.get_columns()[0].get_name());
+            let tables: Vec<Value> = rows.get_rows().iter().map(|row| row[0].clone()).collect();
+            let expected: Vec<Value> = expected.into_iter().map(bytes).collect();
+            assert_eq!(expected, tables);
+        }
+
+        let rows = show_tables("martlet", &tables, true, Some("v%"));
+        assert_eq!(vec![vec![bytes("v_order"), bytes("VIEW")]], rows.get_rows().to_vec());
+    }
+
+    #[test]
+    fn test_show_tables_from() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let session_ctx = SessionContext::new(1);
+        for sql in vec!["SHOW TABLES FROM other", "SHOW FULL TABLES IN other LIKE 't%'", "SHOW COLUMNS FROM t_config"] {
+            let statement = parser(sql.to_string()).pop().unwrap();
+            let route_ctx = RouteContext::new(&cluster, &statement, &session_ctx, &[]);
+            assert!(block_on(show(&route_ctx, sql)).unwrap().is_none(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_show_pool() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let rows = show_pool(&[(100, PoolStats::default())]);
+        assert_eq!(vec!["Segment", "Total", "Idle", "In_use", "Waiting", "Created", "Closed", "Timeouts"],
+                   rows.get_columns().iter().map(|c| c.get_name().to_string()).collect::<Vec<String>>());
+        assert_eq!(vec![vec![Value::UInt(100), Value::UInt(0), Value::UInt(0), Value::UInt(0), Value::UInt(0), Value::UInt(0),
+                             Value::UInt(0), Value::UInt(0)]],
+                   rows.get_rows().to_vec());
+
+        // The segments share one url and so one pool, created without connecting.
+        let segment = cluster.get_segments().get_data_segment(100).unwrap();
+        pool::pool(segment.get_primary(), cluster.get_props().get_pool());
+        let session_ctx = SessionContext::new(1);
+        let sql = "SHOW DBMESH POOL";
+        let statement = parser(sql.to_string()).pop().unwrap();
+        let route_ctx = RouteContext::new(&cluster, &statement, &session_ctx, &[]);
+        let rows = block_on(show(&route_ctx, sql)).unwrap().unwrap();
+        assert_eq!(vec![Value::UInt(100), Value::UInt(200), Value::UInt(300)],
+                   rows.get_rows().iter().map(|row| row[0].clone()).collect::<Vec<Value>>());
+    }
+
+    #[test]
+    fn test_show_columns() {
+        let schema_columns = MetadataRows::new(
+            ["TABLE_NAME", "COLUMN_NAME", "COLUMN_TYPE", "IS_NULLABLE", "COLUMN_KEY", "COLUMN_DEFAULT", "EXTRA"].iter()
+                .map(|name| string_column(name))
+                .collect(),
+            vec![vec![bytes("t_order"), bytes("order_id"), bytes("bigint"), bytes("NO"), bytes("PRI"), Value::NULL, bytes("")],
+                 vec![bytes("t_order"), bytes("user_id"), bytes("int"), bytes("NO"), bytes(""), Value::NULL, bytes("")],
+                 vec![bytes("t_dept"), bytes("dept_id"), bytes("int"), bytes("NO"), bytes("PRI"), Value::NULL, bytes("")]]);
+        let rows = show_columns(&schema_columns, "t_order", false, None);
+        assert_eq!(vec!["Field", "Type", "Null", "Key", "Default", "Extra"],
+                   rows.get_columns().iter().map(|c| c.get_name().to_string()).collect::<Vec<String>>());
+        assert_eq!(vec![vec![bytes("order_id"), bytes("bigint"), bytes("NO"), bytes("PRI"), Value::NULL, bytes("")],
+                        vec![bytes("user_id"), bytes("int"), bytes("NO"), bytes(""), Value::NULL, bytes("")]],
+                   rows.get_rows().to_vec());
+
+        let rows = show_columns(&schema_columns, "t_order", true, Some("user%"));
+        assert_eq!(9, rows.get_columns().len());
+        assert_eq!(1, rows.get_rows().len());
+        assert_eq!(Value::NULL, rows.get_rows()[0][2]);
+    }
+
+    #[test]
+    fn test_rewrite_schema_query() {
+        let tables = Arc::new(MetadataRows::new(
+            vec![string_column("TABLE_NAME"), string_column("TABLE_ROWS")],
+            vec![vec![bytes("t_order"), Value::UInt(3)], vec![bytes("it's"), Value::NULL]]));
+        let query = |sql: &str| match parser(sql.to_string()).pop().unwrap() {
+            Statement::Query(query) => query,
+            _ => unreachable!(),
+        };
+
+        let rewritten = rewrite_schema_query(&query("SELECT TABLE_NAME FROM information_schema.TABLES WHERE TABLE_NAME LIKE ?"),
+                                             |_| Ok(tables.clone())).unwrap().unwrap();
+        assert_eq!("SELECT TABLE_NAME FROM (SELECT 't_order' AS `TABLE_NAME`, 3 AS `TABLE_ROWS` UNION ALL SELECT 'it''s', NULL) AS TABLES \
+                    WHERE TABLE_NAME LIKE ?", rewritten.to_string());
+
+        let empty = Arc::new(MetadataRows::new(vec![string_column("COLUMN_NAME")], vec![]));
+        let rewritten = rewrite_schema_query(&query("SELECT c.COLUMN_NAME FROM information_schema.columns c"),
+                                             |_| Ok(empty.clone())).unwrap().unwrap();
+        assert_eq!("SELECT c.COLUMN_NAME FROM (SELECT NULL AS `COLUMN_NAME` FROM DUAL WHERE 1 = 0) AS c", rewritten.to_string());
+
+        assert!(rewrite_schema_query(&query("SELECT * FROM t_order"), |_| Ok(empty.clone())).unwrap().is_none());
+        assert!(rewrite_schema_query(&query("SELECT * FROM information_schema.STATISTICS"), |_| Ok(empty.clone())).unwrap().is_none());
+    }
+}
@@ -1,4 +1,8 @@
+use std::pin::Pin;
+
+use async_trait::async_trait;
 use bytes::Bytes;
+use futures::{stream, Stream};
 
 use crate::handler::database::mysql::binary::{ComStmtCloseHandler, ComStmtExecuteHandler, ComStmtPrepareHandler, ComStmtResetHandler};
 use crate::handler::database::mysql::text::ComQueryHandler;
@@ -10,40 +14,60 @@ use crate::session::mysql::SessionContext;
 pub mod text;
 pub mod binary;
 pub mod explainplan;
+pub mod merge;
+pub mod aggregate;
+pub mod federated;
+pub mod keygen;
+pub mod metadata;
+pub mod client;
 pub mod rdbc;
+pub mod pool;
+pub mod relocate;
+pub mod rebalance;
+
+/// The packets of a response, encoded and read from the segments as the client socket takes
+/// them.
+pub type PayloadStream = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+
+/// A response whose packets are all encoded already.
+pub fn payload_stream(payloads: Vec<Bytes>) -> PayloadStream {
+    Box::pin(stream::iter(payloads))
+}
 
+#[async_trait]
 pub trait CommandHandler<P, Session> {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<P>, session_ctx: &mut Session) -> Option<Vec<Bytes>>;
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<P>, session_ctx: &mut Session) -> Option<PayloadStream>;
 }
 
 pub struct CommandRootHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for CommandRootHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let command_packet_header = command_packet_header.unwrap();
         let command_packet = command_packet.unwrap();
         let command_packet_type = command_packet_header.get_command_packet_type();
         match MySQLCommandPacketType::value_of(command_packet_type) {
             MySQLCommandPacketType::ComQuery => {
-                ComQueryHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx)
+                ComQueryHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx).await
             }
             MySQLCommandPacketType::ComStmtPrepare => {
-                ComStmtPrepareHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx)
+                ComStmtPrepareHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx).await
             }
             MySQLCommandPacketType::ComStmtExecute => {
-                ComStmtExecuteHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx)
+                ComStmtExecuteHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx).await
             }
             MySQLCommandPacketType::ComStmtClose => {
-                ComStmtCloseHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx)
+                ComStmtCloseHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx).await
             }
             MySQLCommandPacketType::ComStmtReset => {
-                ComStmtResetHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx)
+                ComStmtResetHandler::handle(Some(command_packet_header), Some(command_packet), session_ctx).await
             }
             MySQLCommandPacketType::ComQuit => {
-                ComQuitHandler::handle(Some(command_packet_header), None, session_ctx)
+                ComQuitHandler::handle(Some(command_packet_header), None, session_ctx).await
             }
             MySQLCommandPacketType::ComPing => {
-                ComPingHandler::handle(Some(command_packet_header), None, session_ctx)
+                ComPingHandler::handle(Some(command_packet_header), None, session_ctx).await
             }
             _ => {
                 None
@@ -54,19 +78,21 @@ impl CommandHandler<MySQLPacketPayload, SessionContext> for CommandRootHandler {
 
 pub struct HandshakeHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for HandshakeHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let mut handshake_packet = MySQLHandshakePacket::new(session_ctx.get_thread_id() as u32, session_ctx.get_auth_plugin_data1(), session_ctx.get_auth_plugin_data2());
         let mut handshake_payload = MySQLPacketPayload::new();
         let handshake_payload = DatabasePacket::encode(&mut handshake_packet, &mut handshake_payload);
-        Some(vec![handshake_payload.get_payload()])
+        Some(payload_stream(vec![handshake_payload.get_payload()]))
     }
 }
 
 pub struct AuthPhaseFastPathHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for AuthPhaseFastPathHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let command_packet_header = command_packet_header.unwrap();
         let mut handshake_response41_payload = payload.unwrap();
         let mut handshake_response41_packet = MySQLHandshakeResponse41Packet::new();
@@ -95,14 +121,15 @@ impl CommandHandler<MySQLPacketPayload, SessionContext> for AuthPhaseFastPathHan
         session_ctx.set_auth_response(handshake_response41_packet.get_auth_response());
         session_ctx.set_database(handshake_response41_packet.get_database());
 
-        Some(payloads)
+        Some(payload_stream(payloads))
     }
 }
 
 pub struct AuthMethodMismatchHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for AuthMethodMismatchHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let command_packet_header = command_packet_header.unwrap();
         let mut auth_switch_response_payload = payload.unwrap();
         let mut auth_switch_response_packet = MySQLAuthSwitchResponsePacket::new();
@@ -116,23 +143,25 @@ impl CommandHandler<MySQLPacketPayload, SessionContext> for AuthMethodMismatchHa
 
 pub struct ComQuitHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for ComQuitHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let mut ok_packet = MySQLOKPacket::new(1, 0, 0);
         let mut ok_payload = MySQLPacketPayload::new();
         let ok_payload = DatabasePacket::encode(&mut ok_packet, &mut ok_payload);
-        Some(vec![ok_payload.get_payload()])
+        Some(payload_stream(vec![ok_payload.get_payload()]))
     }
 }
 
 pub struct ComPingHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for ComPingHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         let mut ok_packet = MySQLOKPacket::new(1, 0, 0);
         let mut ok_payload = MySQLPacketPayload::new();
         let ok_payload = DatabasePacket::encode(&mut ok_packet, &mut ok_payload);
-        Some(vec![ok_payload.get_payload()])
+        Some(payload_stream(vec![ok_payload.get_payload()]))
     }
 }
 
@@ -142,15 +171,13 @@ mod tests {
     use std::fs::File;
     use std::io::Read;
 
-    use mysql::Conn;
-    use mysql::prelude::Queryable;
     use sqlparser::parser::Parser;
 
     use crate::discovery::database::Cluster;
     use crate::handler::database::parser::sql::analyse::SQLAnalyse;
     use crate::handler::database::parser::sql::mysql::parser;
     use crate::handler::database::parser::sql::rewrite::SQLReWrite;
-    use crate::handler::database::parser::sql::SQLStatementContext;
+    use crate::handler::database::parser::sql::{SQLRewriteContext, SQLStatementContext};
 
     #[test]
     fn test_route() {
@@ -171,7 +198,7 @@ mod tests {
         sql_stmt.analyse(&mut sql_stmt_ctx).unwrap();
 
         let mut rewrite_sql = String::new();
-        let ctx: HashMap<String, String> = HashMap::new();
+        let ctx = SQLRewriteContext::new(HashMap::new());
         sql_stmt.rewrite(&mut rewrite_sql, &ctx).unwrap();
 
         assert_eq!(sql.to_uppercase(), rewrite_sql.to_uppercase());
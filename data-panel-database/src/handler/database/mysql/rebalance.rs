@@ -0,0 +1,205 @@
+use rhai::Dynamic;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::Cluster;
+use crate::handler::database::mysql::explainplan::PlanTask;
+use crate::handler::database::mysql::merge::ShardResultSet;
+use crate::handler::database::mysql::rdbc::{execute_tasks, ExecuteError};
+use crate::handler::database::parser::sql::route::algorithm::{eval_expression, param_to_dynamic};
+use crate::handler::database::parser::sql::route::ring::{MovedRange, ring_position, table_moved_ranges};
+use crate::protocol::database::mysql::resultset::Value;
+
+pub type RebalanceResult<T> = data_panel_common::common::Result<T>;
+
+/// A row of a consistent-hash table which belongs to another data segment once the
+/// segments change, by the values of its dis keys.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedRow {
+    table: String,
+    dis_values: Vec<(String, Value)>,
+    from: u32,
+    to: u32,
+}
+
+impl MovedRow {
+    pub fn get_table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    pub fn get_dis_values(&self) -> &[(String, Value)] {
+        self.dis_values.as_slice()
+    }
+
+    pub fn get_from(&self) -> u32 {
+        self.from
+    }
+
+    pub fn get_to(&self) -> u32 {
+        self.to
+    }
+}
+
+/// The rows of the consistent-hash tables of `before` which move when its data segments
+/// become those of `after`, read from the segments of `before` they move away from.
+pub async fn moved_rows(before: &Cluster, after: &Cluster) -> RebalanceResult<Vec<MovedRow>> {
+    let mut tables: Vec<&String> = before.get_dis_rules().get_distributed_tables().iter()
+        .filter(|(_, dis_table)| dis_table.get_dis_algorithm().get_dis_ring().is_some())
+        .map(|(table, _)| table)
+        .collect();
+    tables.sort();
+
+    let mut moved = vec![];
+    for table in tables {
+        let ranges = table_moved_ranges(table, before, after)?;
+        let tasks = scan_tasks(table, before, &ranges)?;
+        if tasks.is_empty() {
+            continue;
+        }
+        let read_error = |(code, _, message): ExecuteError| {
+            Error::General(format!("can not read the dis keys of {}: {} {}", table, code, message))
+        };
+        let shard_results = execute_tasks(&tasks, true).await.map_err(read_error)?;
+        for (task, result_sets) in tasks.iter().zip(shard_results) {
+            for result_set in result_sets {
+                if let ShardResultSet::Rows { rows, .. } = result_set {
+                    let rows = rows.collect::<Result<Vec<Vec<Value>>, ExecuteError>>().map_err(read_error)?;
+                    moved.extend(table_moved_rows(table, before, &ranges, task.get_segment_id(), &rows)?);
+                }
+            }
+        }
+    }
+    Ok(moved)
+}
+
+/// A scan of the distinct dis key values of the table on every segment rows move away from.
+fn scan_tasks(table: &str, cluster: &Cluster, ranges: &[MovedRange]) -> RebalanceResult<Vec<PlanTask>> {
+    let dis_table = cluster.get_dis_rules().get_distributed_table(table)
+        .ok_or_else(|| Error::General(format!("{} is not a distributed table", table)))?;
+    if dis_table.get_dis_partition().is_some() {
+        return Err(Error::General(format!("{}: moved rows of a partitioned table are not supported", table)));
+    }
+    let quote = |name: &str| name.split('.').map(|part| format!("`{}`", part.replace('`', "``"))).collect::<Vec<String>>().join(".");
+    let dis_keys: Vec<String> = dis_table.get_dis_keys().iter().map(|key| quote(key)).collect();
+
+    let mut segment_ids: Vec<u32> = ranges.iter().map(|range| range.get_from()).collect();
+    segment_ids.sort();
+    segment_ids.dedup();
+    Ok(segment_ids.into_iter()
+        .map(|segment_id| {
+            let physical_table = dis_table.get_dis_physical_table(segment_id).map(|t| t.as_str()).unwrap_or(table);
+            let sql = format!("SELECT DISTINCT {} FROM {}", dis_keys.join(", "), quote(physical_table));
+            PlanTask::new(segment_id, sql, vec![])
+        })
+        .collect())
+}
+
+/// The rows of the table on the segment, as values of its dis keys, which are in one of the
+/// moved ranges. Rows with a NULL dis key are on no place of the ring and stay.
+fn table_moved_rows(table: &str,
+                    cluster: &Cluster,
+                    ranges: &[MovedRange],
+                    segment_id: u32,
+                    rows: &[Vec<Value>]) -> RebalanceResult<Vec<MovedRow>> {
+    let dis_table = cluster.get_dis_rules().get_distributed_table(table)
+        .ok_or_else(|| Error::General(format!("{} is not a distributed table", table)))?;
+    let dis_keys = dis_table.get_dis_keys();
+    let dis_expression = dis_table.get_dis_algorithm().get_dis_expression();
+
+    let mut moved = vec![];
+    for row in rows {
+        let values: Vec<(&str, Dynamic)> = dis_keys.iter().zip(row.iter())
+            .filter_map(|(key, value)| param_to_dynamic(value).map(|value| (key.as_str(), value)))
+            .collect();
+        if values.len() < dis_keys.len() {
+            continue;
+        }
+        let position = ring_position(eval_expression(dis_expression, &values)?);
+        if let Some(range) = ranges.iter().find(|range| range.get_from() == segment_id && range.contains(position)) {
+            moved.push(MovedRow {
+                table: table.to_string(),
+                dis_values: dis_keys.iter().cloned().zip(row.iter().cloned()).collect(),
+                from: range.get_from(),
+                to: range.get_to(),
+            });
+        }
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use rhai::{Dynamic, INT};
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::rebalance::{scan_tasks, table_moved_rows};
+    use crate::handler::database::parser::sql::route::algorithm::eval_segment;
+    use crate::handler::database::parser::sql::route::ring::table_moved_ranges;
+    use crate::protocol::database::mysql::resultset::Value;
+
+    /// A cluster of the data segments with `t_user` on a ring.
+    fn ring_cluster(segment_ids: &[u32]) -> Cluster {
+        let segments: String = segment_ids.iter()
+            .map(|id| format!(r#"
+    {}:
+      primary: {{ id: 0, url: "jdbc:mysql://localhost:3306/martlet", username: root, password: root }}
+      mirrors: [ ]"#, id))
+            .collect();
+        Cluster::from_str(&format!(r#"
+name: test
+segments:
+  meta_segment:
+    primary: {{ id: 0, url: "jdbc:mysql://localhost:3306/martlet", username: root, password: root }}
+    mirrors: [ ]
+  data_segments:{}
+dis_rules:
+  distributed_tables:
+    t_user:
+      dis_keys: [ user_id ]
+      dis_algorithm: {{ dis_type: HASH, dis_expression: "user_id", dis_ring: {{ virtual_nodes: 40 }} }}
+      dis_relatives: [ ]
+      dis_physical_tables: {{ 100: t_user_0 }}
+  replicated_tables: [ ]
+"#, segments)).unwrap()
+    }
+
+    #[test]
+    fn test_table_moved_rows() {
+        let before = ring_cluster(&[100, 200, 300]);
+        let after = ring_cluster(&[100, 200, 300, 400]);
+        let ranges = table_moved_ranges("t_user", &before, &after).unwrap();
+
+        let tasks = scan_tasks("t_user", &before, &ranges).unwrap();
+        assert_eq!(vec![(100, "SELECT DISTINCT `user_id` FROM `t_user_0`"), (200, "SELECT DISTINCT `user_id` FROM `t_user`"),
+                        (300, "SELECT DISTINCT `user_id` FROM `t_user`")],
+                   tasks.iter().map(|task| (task.get_segment_id(), task.get_sql())).collect::<Vec<(u32, &str)>>());
+
+        let segment = |cluster: &Cluster, user_id: INT| {
+            let dis_algorithm = cluster.get_dis_rules().get_distributed_table("t_user").unwrap().get_dis_algorithm();
+            eval_segment(dis_algorithm, &[("user_id", Dynamic::from(user_id))], &cluster.get_segments().get_data_segment_ids()).unwrap()
+        };
+        let mut reported = vec![];
+        for segment_id in vec![100, 200, 300] {
+            let mut rows: Vec<Vec<Value>> = (0..2000 as INT)
+                .filter(|user_id| segment(&before, *user_id) == segment_id)
+                .map(|user_id| vec![Value::Int(user_id as i64)])
+                .collect();
+            rows.push(vec![Value::NULL]);
+            for moved_row in table_moved_rows("t_user", &before, &ranges, segment_id, &rows).unwrap() {
+                assert_eq!(("t_user", segment_id, 400), (moved_row.get_table(), moved_row.get_from(), moved_row.get_to()));
+                assert_eq!("user_id", moved_row.get_dis_values()[0].0);
+                reported.push(moved_row.get_dis_values()[0].1.clone());
+            }
+        }
+        let expected: Vec<Value> = (0..2000 as INT)
+            .filter(|user_id| segment(&before, *user_id) != segment(&after, *user_id))
+            .map(|user_id| Value::Int(user_id as i64))
+            .collect();
+        reported.sort_by_key(|value| match value {
+            Value::Int(user_id) => *user_id,
+            _ => -1,
+        });
+        assert!(!expected.is_empty());
+        assert_eq!(expected, reported);
+    }
+}
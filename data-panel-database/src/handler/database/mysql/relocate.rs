@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use sqlparser::ast::Statement;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::DisKeyUpdate;
+use crate::handler::database::mysql::explainplan::{explain_row, PlanTask};
+use crate::handler::database::mysql::merge::ShardResultSet;
+use crate::handler::database::mysql::rdbc::{DistributedTransaction, ExecuteResult};
+use crate::handler::database::parser::sql::analyse::SQLAnalyse;
+use crate::handler::database::parser::sql::mysql::parser;
+use crate::handler::database::parser::sql::route::{algorithm, route, RouteContext};
+use crate::handler::database::parser::sql::{ShardingCondition, ShardingValue, SQLStatementContext};
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+use crate::protocol::database::mysql::resultset::{Column, Value};
+
+pub type RelocateResult<T> = data_panel_common::common::Result<T>;
+
+/// The most `?` a prepared statement may have.
+const MAX_PLACEHOLDERS: usize = 65535;
+
+/// An `UPDATE` setting the dis keys of a distributed table with `dis_key_update: MOVE`: the
+/// rows are read and deleted on the segments they are on and inserted with the new values
+/// on the segment of the new dis key values, all in one distributed transaction.
+pub struct RelocatePlan {
+    table: String,
+    target_segment_id: u32,
+    /// The physical table on the target segment.
+    target_table: String,
+    /// The new value of every assigned column.
+    assignments: Vec<(String, PrepareParamValue)>,
+    select_tasks: Vec<PlanTask>,
+    delete_tasks: Vec<PlanTask>,
+}
+
+impl RelocatePlan {
+    /// `None` unless the statement is an `UPDATE` setting a dis key, which fails unless
+    /// the cluster moves such rows.
+    pub fn new(route_ctx: &RouteContext<'_>, stmt_ctx: &SQLStatementContext) -> RelocateResult<Option<Self>> {
+        let (table_name, selection, limit) = match route_ctx.get_statement() {
+            Statement::Update { table_name, selection, limit, .. } => (table_name, selection, limit),
+            _ => return Ok(None),
+        };
+        let cluster = route_ctx.get_cluster();
+        let dis_rules = cluster.get_dis_rules();
+        let dis_keys = stmt_ctx.get_updated_dis_keys(dis_rules);
+        if dis_keys.is_empty() {
+            return Ok(None);
+        }
+        let table = table_name.to_string();
+        if cluster.get_props().get_dis_key_update() == DisKeyUpdate::REJECT {
+            return Err(Error::General(format!(
+                "UPDATE of {} sets the dis key {}, the rows would stay on the data segment of the old value; \
+                 delete and insert them instead or set dis_key_update: MOVE",
+                table, dis_keys.join(", "))));
+        }
+        let dis_table = match dis_rules.get_distributed_table(table.as_str()) {
+            Some(dis_table) => dis_table,
+            None => return Ok(None),
+        };
+        if dis_table.get_dis_partition().is_some() {
+            return Err(unsupported(table.as_str(), "partitioned tables"));
+        }
+        if limit.is_some() {
+            return Err(unsupported(table.as_str(), "LIMIT"));
+        }
+
+        let parameters = route_ctx.get_parameters();
+        let mut assignments = Vec::new();
+        let mut assigned_parameters = 0;
+        let mut conditions = HashMap::new();
+        for assignment in stmt_ctx.get_update_assignments() {
+            let value = assignment.get_value()
+                .ok_or_else(|| unsupported(table.as_str(), "values other than literals and ?"))?;
+            if let ShardingValue::Parameter(_) = value {
+                assigned_parameters += 1;
+            }
+            assignments.push((assignment.get_column().clone(), assignment_value(value, parameters)?));
+            if let Some(dis_key) = dis_keys.iter().find(|k| k.eq_ignore_ascii_case(assignment.get_column())) {
+                conditions.insert(dis_key.clone(), vec![ShardingCondition::Equal(vec![value.clone()])]);
+            }
+        }
+        if dis_keys.len() != dis_table.get_dis_keys().len() {
+            // The segment would depend on the dis keys each row keeps.
+            return Err(unsupported(table.as_str(), "setting only some of the dis keys"));
+        }
+        let segment_ids = cluster.get_segments().get_data_segment_ids();
+        let target_segment_id = match algorithm::sharding_segments(dis_table, &conditions, parameters, &segment_ids)? {
+            Some(target_segment_ids) if target_segment_ids.len() == 1 => target_segment_ids[0],
+            _ => return Err(Error::General(format!("can not find the data segment of the new {} of {}", dis_keys.join(", "), table))),
+        };
+        let target_table = dis_table.get_dis_physical_table(target_segment_id).cloned().unwrap_or_else(|| table.clone());
+
+        let selection = match selection {
+            Some(selection) => format!(" WHERE {}", selection),
+            None => String::new(),
+        };
+        // The `?` of the SET come before those of the WHERE.
+        let where_parameters = &parameters[assigned_parameters.min(parameters.len())..];
+        let mut select_tasks = source_tasks(route_ctx, format!("SELECT * FROM {}{}", table, selection), where_parameters)?;
+        for task in select_tasks.iter_mut() {
+            *task = PlanTask::new(task.get_segment_id(), format!("{} FOR UPDATE", task.get_sql()), task.get_parameters().to_vec());
+        }
+        let delete_tasks = source_tasks(route_ctx, format!("DELETE FROM {}{}", table, selection), where_parameters)?;
+        Ok(Some(RelocatePlan {
+            table,
+            target_segment_id,
+            target_table,
+            assignments,
+            select_tasks,
+            delete_tasks,
+        }))
+    }
+
+    pub fn get_target_segment_id(&self) -> u32 {
+        self.target_segment_id
+    }
+
+    pub fn get_select_tasks(&self) -> &[PlanTask] {
+        self.select_tasks.as_slice()
+    }
+
+    pub fn get_delete_tasks(&self) -> &[PlanTask] {
+        self.delete_tasks.as_slice()
+    }
+
+    /// Move the rows and commit on the target segment first: should a source segment fail to
+    /// commit after it, the rows are on both segments rather than on none, and the error
+    /// says so.
+    pub async fn execute(&self, binary: bool) -> ExecuteResult<ShardResultSet> {
+        let mut transaction = DistributedTransaction::new();
+        match self.relocate(&mut transaction, binary).await {
+            Ok(moved_rows) => {
+                transaction.commit_segment(self.target_segment_id).await?;
+                transaction.commit().await.map_err(|(code, state, message)| (code, state, format!(
+                    "the rows were inserted into {} on segment {} but may not be deleted from their source segments: {}",
+                    self.target_table, self.target_segment_id, message)))?;
+                Ok(ShardResultSet::Update { affected_rows: moved_rows, last_insert_id: 0 })
+            }
+            Err(e) => {
+                transaction.rollback().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Move the rows in a transaction, left to commit. The number of rows moved.
+    pub async fn relocate(&self, transaction: &mut DistributedTransaction, binary: bool) -> ExecuteResult<u64> {
+        let mut columns: Option<Vec<Column>> = None;
+        let mut rows = Vec::new();
+        for task in self.select_tasks.iter() {
+            for result_set in transaction.execute(task, binary).await? {
+                if let ShardResultSet::Rows { columns: shard_columns, rows: shard_rows } = result_set {
+                    columns.get_or_insert(shard_columns);
+                    for row in shard_rows {
+                        rows.push(row?);
+                    }
+                }
+            }
+        }
+        for task in self.delete_tasks.iter() {
+            transaction.execute(task, binary).await?;
+        }
+        let columns = match columns {
+            Some(ref columns) if !rows.is_empty() => columns,
+            _ => return Ok(0),
+        };
+        let mut moved_rows = 0;
+        for insert in self.insert_tasks(columns, rows)? {
+            for result_set in transaction.execute(&insert, true).await? {
+                if let ShardResultSet::Update { affected_rows, .. } = result_set {
+                    moved_rows += affected_rows;
+                }
+            }
+        }
+        Ok(moved_rows)
+    }
+
+    /// The rows with the new values, inserted into the target table by as many rows at a
+    /// time as stay within `MAX_PLACEHOLDERS`.
+    fn insert_tasks(&self, columns: &[Column], rows: Vec<Vec<Value>>) -> ExecuteResult<Vec<PlanTask>> {
+        let names: Vec<String> = columns.iter().map(|c| c.get_name().to_string()).collect();
+        let mut assigned = Vec::with_capacity(self.assignments.len());
+        for (column, value) in self.assignments.iter() {
+            match names.iter().position(|name| name.eq_ignore_ascii_case(column)) {
+                Some(index) => assigned.push((index, value)),
+                None => return Err((1054, String::from("42S22"), format!("Unknown column '{}' in '{}'", column, self.table))),
+            }
+        }
+        let placeholders = format!("({})", vec!["?"; names.len()].join(", "));
+        let column_list = names.iter().map(|name| format!("`{}`", name)).collect::<Vec<String>>().join(", ");
+        let batch_size = (MAX_PLACEHOLDERS / names.len().max(1)).max(1);
+        let mut tasks = Vec::with_capacity(rows.len() / batch_size + 1);
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let mut parameters = Vec::with_capacity(names.len() * batch_size);
+            let mut row_count = 0;
+            for mut row in rows.by_ref().take(batch_size) {
+                for (index, value) in assigned.iter() {
+                    row[*index] = (*value).clone();
+                }
+                parameters.extend(row);
+                row_count += 1;
+            }
+            let sql = format!("INSERT INTO {} ({}) VALUES {}", self.target_table, column_list, vec![placeholders.as_str(); row_count].join(", "));
+            tasks.push(PlanTask::new(self.target_segment_id, sql, parameters));
+        }
+        Ok(tasks)
+    }
+
+    pub fn describe(&self) -> Vec<Vec<Value>> {
+        let mut rows: Vec<Vec<Value>> = self.select_tasks.iter()
+            .chain(self.delete_tasks.iter())
+            .map(|task| explain_row("RELOCATE", Some(task.get_segment_id()), task.get_sql()))
+            .collect();
+        let detail = format!("INSERT INTO {} the rows read, with the new values", self.target_table);
+        rows.push(explain_row("RELOCATE", Some(self.target_segment_id), detail.as_str()));
+        rows
+    }
+}
+
+/// The tasks of a statement on the segments of the rows the `UPDATE` matches.
+fn source_tasks(route_ctx: &RouteContext<'_>, sql: String, parameters: &[PrepareParamValue]) -> RelocateResult<Vec<PlanTask>> {
+    let statement = match parser(sql.clone()).pop() {
+        Some(statement) => statement,
+        None => return Err(Error::General(format!("can not parse {}", sql))),
+    };
+    let mut stmt_ctx = SQLStatementContext::new(&statement);
+    statement.analyse(&mut stmt_ctx)?;
+    let mut source_ctx = RouteContext::new(route_ctx.get_cluster(), &statement, route_ctx.get_session_ctx(), parameters);
+    source_ctx.set_hint(route_ctx.get_hint());
+    route(&source_ctx, &stmt_ctx)
+}
+
+fn assignment_value(value: &ShardingValue, parameters: &[PrepareParamValue]) -> RelocateResult<PrepareParamValue> {
+    match value {
+        ShardingValue::Parameter(index) => parameters.get(*index).cloned()
+            .ok_or_else(|| Error::General(format!("no value for parameter {}", index))),
+        ShardingValue::Literal(sqlparser::ast::Value::Null) => Ok(PrepareParamValue::NULL),
+        ShardingValue::Literal(sqlparser::ast::Value::Boolean(b)) => Ok(PrepareParamValue::Int(*b as i64)),
+        ShardingValue::Literal(sqlparser::ast::Value::Number(n, _)) => Ok(PrepareParamValue::Bytes(n.clone().into_bytes())),
+        ShardingValue::Literal(sqlparser::ast::Value::SingleQuotedString(s)) => Ok(PrepareParamValue::Bytes(s.clone().into_bytes())),
+        ShardingValue::Literal(value) => Err(Error::General(format!("can not move rows to the value {}", value))),
+    }
+}
+
+fn unsupported(table: &str, what: &str) -> Error {
+    Error::General(format!("UPDATE of the dis key of {} does not support {} yet", table, what))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::relocate::RelocatePlan;
+    use crate::handler::database::parser::sql::route::tests::with_route_ctx;
+    use crate::protocol::database::mysql::constant::MySQLColumnType;
+    use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+    use crate::protocol::database::mysql::resultset::{Column, Value};
+
+    fn relocate_plan(config: &str, sql: &str, parameters: &[PrepareParamValue]) -> data_panel_common::common::Result<Option<RelocatePlan>> {
+        let cluster = Cluster::from_str(config).unwrap();
+        with_route_ctx(&cluster, sql, parameters, RelocatePlan::new)
+    }
+
+    #[test]
+    fn test_relocate_plan() {
+        let config = std::fs::read_to_string("./etc/dbmesh.yaml").unwrap();
+        assert!(relocate_plan(config.as_str(), "UPDATE t_order SET status = 'PAID' WHERE user_id = 10", &[]).unwrap().is_none());
+        assert!(relocate_plan(config.as_str(), "UPDATE t_order SET user_id = 11 WHERE user_id = 10", &[]).is_err());
+
+        let config = config.replace("dis_key_update: REJECT", "dis_key_update: MOVE");
+        let plan = relocate_plan(config.as_str(), "UPDATE t_order SET status = ?, user_id = ? WHERE user_id = ?",
+                                 &[PrepareParamValue::Bytes(b"NEW".to_vec()), PrepareParamValue::Int(11), PrepareParamValue::Int(10)])
+            .unwrap().unwrap();
+        assert_eq!(300, plan.get_target_segment_id());
+        let tasks: Vec<(u32, &str)> = plan.get_select_tasks().iter().chain(plan.get_delete_tasks().iter())
+            .map(|t| (t.get_segment_id(), t.get_sql()))
+            .collect();
+        assert_eq!(vec![(200, "SELECT * FROM t_order_1 WHERE user_id = ? FOR UPDATE"), (200, "DELETE FROM t_order_1 WHERE user_id = ?")], tasks);
+        assert!(vec![PrepareParamValue::Int(10)] == plan.get_select_tasks()[0].get_parameters().to_vec());
+
+        let columns: Vec<Column> = ["order_id", "user_id", "status"].iter()
+            .map(|name| Column::new(MySQLColumnType::MysqlTypeVarString).with_name(name))
+            .collect();
+        let rows = vec![vec![Value::Int(1), Value::Int(10), Value::Bytes(b"PAID".to_vec())]];
+        let inserts = plan.insert_tasks(&columns, rows).unwrap();
+        assert_eq!(1, inserts.len());
+        assert_eq!((300, "INSERT INTO t_order_2 (`order_id`, `user_id`, `status`) VALUES (?, ?, ?)"), (inserts[0].get_segment_id(), inserts[0].get_sql()));
+        assert!(vec![PrepareParamValue::Int(1), PrepareParamValue::Int(11), PrepareParamValue::Bytes(b"NEW".to_vec())]
+            == inserts[0].get_parameters().to_vec());
+
+        // 65535 placeholders take 21845 rows of 3 columns.
+        let rows = vec![vec![Value::Int(1), Value::Int(10), Value::Bytes(b"PAID".to_vec())]; 21846];
+        let inserts = plan.insert_tasks(&columns, rows).unwrap();
+        let parameter_counts: Vec<usize> = inserts.iter().map(|insert| insert.get_parameters().len()).collect();
+        assert_eq!(vec![65535, 3], parameter_counts);
+        assert_eq!("INSERT INTO t_order_2 (`order_id`, `user_id`, `status`) VALUES (?, ?, ?)", inserts[1].get_sql());
+
+        assert!(relocate_plan(config.as_str(), "UPDATE t_order SET user_id = user_id + 1 WHERE user_id = 10", &[]).is_err());
+        assert!(relocate_plan(config.as_str(), "UPDATE t_order SET user_id = 11 WHERE user_id = 10 LIMIT 1", &[]).is_err());
+    }
+}
@@ -1,8 +1,16 @@
-use bytes::Bytes;
+use async_trait::async_trait;
 
-use crate::handler::database::mysql::CommandHandler;
+use crate::discovery::database::Cluster;
+use crate::handler::database::mysql::{CommandHandler, payload_stream, PayloadStream};
 use crate::handler::database::mysql::explainplan::{Executor, ExplainPlan, ExplainPlanContext, TBProtocol};
+use crate::handler::database::mysql::keygen::generate_keys;
+use crate::handler::database::mysql::rdbc::{err_payload, ok_payload};
 use crate::handler::database::parser;
+use crate::handler::database::parser::sql::analyse::SQLAnalyse;
+use crate::handler::database::parser::sql::hint::extract_hint;
+use crate::handler::database::parser::sql::route::RouteContext;
+use crate::handler::database::parser::sql::route::readwrite::{set_session_option, track_session};
+use crate::handler::database::parser::sql::SQLStatementContext;
 use crate::protocol::database::DatabasePacket;
 use crate::protocol::database::mysql::packet::{MySQLPacketHeader, MySQLPacketPayload};
 use crate::protocol::database::mysql::packet::text::MySQLComQueryPacket;
@@ -10,8 +18,9 @@ use crate::session::mysql::SessionContext;
 
 pub struct ComQueryHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for ComQueryHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet_payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet_payload: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         // 1 解析報文
         // 2 解析SQL
         // 3 服務發現
@@ -28,23 +37,58 @@ impl CommandHandler<MySQLPacketPayload, SessionContext> for ComQueryHandler {
 
         let command_sql = command_packet.get_sql();
         let cow_sql = String::from_utf8_lossy(command_sql.as_slice());
-        let sql = cow_sql.to_string();
+        let (hint, sql) = match extract_hint(cow_sql.as_ref()) {
+            Ok(extracted) => extracted,
+            Err(data_panel_common::common::Error::General(err_message)) => {
+                return Some(payload_stream(vec![err_payload((10000, String::from("HY000"), err_message))]));
+            }
+        };
         println!("SQL = {}", sql);
-        let mut statement = parser::sql::mysql::parser(sql);
-        let statement = statement.pop().unwrap();
+        let mut statement = parser::sql::mysql::parser(sql.clone());
+        let mut statement = statement.pop().unwrap();
+        match set_session_option(&statement, session_ctx) {
+            Ok(true) => return Some(payload_stream(vec![ok_payload(1, 0, 0)])),
+            Ok(false) => {}
+            Err(data_panel_common::common::Error::General(err_message)) => {
+                return Some(payload_stream(vec![err_payload((10000, String::from("HY000"), err_message))]));
+            }
+        }
+        let cluster = Cluster::current();
+        let generated_key = match generate_keys(&mut statement, &cluster).await {
+            Ok(generated_key) => generated_key,
+            Err(data_panel_common::common::Error::General(err_message)) => {
+                return Some(payload_stream(vec![err_payload((10000, String::from("HY000"), err_message))]));
+            }
+        };
 
-        let x_query_context = ExplainPlanContext::new(cow_sql.as_ref(),
-                                                      &statement, TBProtocol::Text);
-        let plan = ExplainPlan::new(&x_query_context);
+        let mut x_query_context = ExplainPlanContext::new(sql.as_str(),
+                                                          &statement, TBProtocol::Text);
+        x_query_context.set_generated_key(generated_key);
+        let mut stmt_ctx = SQLStatementContext::new(&statement);
+        let mut route_ctx = RouteContext::new(&cluster, &statement, session_ctx, &[]);
+        route_ctx.set_hint(hint);
+        let mut plan = ExplainPlan::new(&x_query_context);
+        let plan_result = match statement.analyse(&mut stmt_ctx) {
+            Ok(_) => plan.gen(&route_ctx, &stmt_ctx).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = plan_result {
+            let err_message = match e {
+                data_panel_common::common::Error::General(message) => message,
+            };
+            return Some(payload_stream(vec![err_payload((10000, String::from("HY000"), err_message))]));
+        }
+        track_session(&statement, session_ctx);
 
-        plan.execute()
+        plan.execute().await
     }
 }
 
 pub struct SetVariableHandler {}
 
+#[async_trait]
 impl CommandHandler<MySQLPacketPayload, SessionContext> for SetVariableHandler {
-    fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<Vec<Bytes>> {
+    async fn handle(command_packet_header: Option<MySQLPacketHeader>, command_packet: Option<MySQLPacketPayload>, session_ctx: &mut SessionContext) -> Option<PayloadStream> {
         unimplemented!()
     }
 }
\ No newline at end of file
@@ -12,11 +12,11 @@
 
 //! SQL Abstract Syntax Tree (AST) types
 
-use sqlparser::ast::{AddDropSync, Assignment, Expr, FileFormat, Function, FunctionArg, HiveDistributionStyle, HiveFormat, HiveIOFormat, HiveRowFormat, Ident, ListAgg, ListAggOnOverflow, ObjectName, ObjectType, SetVariableValue, ShowStatementFilter, SqliteOnConflict, SqlOption, Statement, TransactionAccessMode, TransactionIsolationLevel, TransactionMode, UnaryOperator, WindowFrameBound, WindowFrameUnits, WindowSpec};
+use sqlparser::ast::{AddDropSync, Assignment, BinaryOperator, Expr, FileFormat, Function, FunctionArg, HiveDistributionStyle, HiveFormat, HiveIOFormat, HiveRowFormat, Ident, ListAgg, ListAggOnOverflow, ObjectName, ObjectType, SetVariableValue, ShowStatementFilter, SqliteOnConflict, SqlOption, Statement, TransactionAccessMode, TransactionIsolationLevel, TransactionMode, UnaryOperator, Value, WindowFrameBound, WindowFrameUnits, WindowSpec};
 use sqlparser::tokenizer::{Token, Whitespace, Word};
 
 // use std::fmt::Write;
-use crate::handler::database::parser::sql::SQLStatementContext;
+use crate::handler::database::parser::sql::{ColumnCondition, ColumnEquality, logical_table_name, ShardingCondition, ShardingValue, SQLStatementContext, UpdateAssignment};
 
 mod data_type;
 mod ddl;
@@ -100,8 +100,15 @@ impl SQLAnalyse for String {
 /// inappropriate type, like `WHERE 1` or `SELECT 1=1`, as necessary.
 impl SQLAnalyse for Expr {
     fn analyse(&self, ctx: &mut SQLStatementContext) -> SAResult {
+        let predicate = ctx.take_predicate();
+        if predicate {
+            analyse_condition(self, ctx);
+        }
         match self {
             Expr::Identifier(s) => {
+                if s.value == "?" {
+                    ctx.add_parameter();
+                }
                 s.analyse(ctx)?;
             }
             Expr::MapAccess { column, key } => {
@@ -180,10 +187,13 @@ impl SQLAnalyse for Expr {
                 high.analyse(ctx)?;
             }
             Expr::BinaryOp { left, op, right } => {
+                let conjunction = predicate && *op == BinaryOperator::And;
+                ctx.set_predicate(conjunction);
                 left.analyse(ctx)?;
                 // write!(f, " ")?;
                 op.analyse(ctx)?;
                 // write!(f, " ")?;
+                ctx.set_predicate(conjunction);
                 right.analyse(ctx)?;
             }
             Expr::UnaryOp { op, expr } => {
@@ -217,6 +227,7 @@ impl SQLAnalyse for Expr {
             }
             Expr::Nested(ast) => {
                 // write!(f, "(")?;
+                ctx.set_predicate(predicate);
                 ast.analyse(ctx)?;
                 // write!(f, ")")?;
             }
@@ -292,6 +303,114 @@ impl SQLAnalyse for Expr {
     }
 }
 
+/// Record `col = v`, `col IN (v, ...)`, `col BETWEEN v AND v` and comparisons like
+/// `col >= v`, where every value is a literal or a `?` placeholder, as a condition on the column.
+fn analyse_condition(expr: &Expr, ctx: &mut SQLStatementContext) {
+    let parameter_index = ctx.get_parameter_count();
+    analyse_column_equality(expr, ctx);
+    let column_condition = match expr {
+        Expr::BinaryOp { left, op: BinaryOperator::Eq, right } => {
+            condition_column(left).map(|c| (c, right))
+                .or_else(|| condition_column(right).map(|c| (c, left)))
+                .and_then(|((qualifier, column), value)| {
+                    let values = condition_values(vec![value.as_ref()], parameter_index)?;
+                    Some(ColumnCondition::new(qualifier, column, ShardingCondition::Equal(values)))
+                })
+        }
+        Expr::InList { expr, list, negated: false } => {
+            condition_column(expr).and_then(|(qualifier, column)| {
+                let values = condition_values(list.iter().collect(), parameter_index)?;
+                Some(ColumnCondition::new(qualifier, column, ShardingCondition::Equal(values)))
+            })
+        }
+        Expr::Between { expr, negated: false, low, high } => {
+            condition_column(expr).and_then(|(qualifier, column)| {
+                let mut values = condition_values(vec![low.as_ref(), high.as_ref()], parameter_index)?;
+                let high = values.pop()?;
+                let low = values.pop()?;
+                Some(ColumnCondition::new(qualifier, column, ShardingCondition::Range(low, high)))
+            })
+        }
+        Expr::BinaryOp { left, op, right } => comparison_condition(left, op, right, parameter_index),
+        _ => None,
+    };
+    if let Some(column_condition) = column_condition {
+        ctx.add_column_condition(column_condition);
+    }
+}
+
+/// Record the `a.x = b.y` of a join constraint, which has to hold for the joined rows.
+pub fn analyse_join_condition(expr: &Expr, ctx: &mut SQLStatementContext) {
+    match expr {
+        Expr::BinaryOp { left, op: BinaryOperator::And, right } => {
+            analyse_join_condition(left, ctx);
+            analyse_join_condition(right, ctx);
+        }
+        Expr::Nested(expr) => analyse_join_condition(expr, ctx),
+        _ => analyse_column_equality(expr, ctx),
+    }
+}
+
+fn analyse_column_equality(expr: &Expr, ctx: &mut SQLStatementContext) {
+    if let Expr::BinaryOp { left, op: BinaryOperator::Eq, right } = expr {
+        if let (Some(left), Some(right)) = (condition_column(left), condition_column(right)) {
+            ctx.add_column_equality(ColumnEquality::new(left, right));
+        }
+    }
+}
+
+/// `col > v`, `col >= v`, `col < v` and `col <= v`, with the column on either side.
+fn comparison_condition(left: &Expr, op: &BinaryOperator, right: &Expr, parameter_index: usize) -> Option<ColumnCondition> {
+    let ((qualifier, column), value, column_first) = match condition_column(left) {
+        Some(column) => (column, right, true),
+        None => (condition_column(right)?, left, false),
+    };
+    let value = condition_values(vec![value], parameter_index)?.pop()?;
+    let condition = match (op, column_first) {
+        (BinaryOperator::Gt, true) | (BinaryOperator::Lt, false) => ShardingCondition::GreaterThan(value, false),
+        (BinaryOperator::GtEq, true) | (BinaryOperator::LtEq, false) => ShardingCondition::GreaterThan(value, true),
+        (BinaryOperator::Lt, true) | (BinaryOperator::Gt, false) => ShardingCondition::LessThan(value, false),
+        (BinaryOperator::LtEq, true) | (BinaryOperator::GtEq, false) => ShardingCondition::LessThan(value, true),
+        _ => return None,
+    };
+    Some(ColumnCondition::new(qualifier, column, condition))
+}
+
+fn condition_column(expr: &Expr) -> Option<(Option<String>, String)> {
+    match expr {
+        Expr::Identifier(ident) if ident.value != "?" => Some((None, ident.value.clone())),
+        Expr::CompoundIdentifier(idents) if idents.len() > 1 => {
+            let (column, qualifier) = idents.split_last()?;
+            let qualifier = qualifier.iter()
+                .map(|ident| ident.value.as_str())
+                .collect::<Vec<&str>>()
+                .join(".");
+            Some((Some(qualifier), column.value.clone()))
+        }
+        _ => None,
+    }
+}
+
+fn condition_values(exprs: Vec<&Expr>, mut parameter_index: usize) -> Option<Vec<ShardingValue>> {
+    let mut values = Vec::with_capacity(exprs.len());
+    for expr in exprs {
+        let value = match expr {
+            Expr::Identifier(ident) if ident.value == "?" => {
+                parameter_index += 1;
+                ShardingValue::Parameter(parameter_index - 1)
+            }
+            Expr::Value(value) => ShardingValue::Literal(value.clone()),
+            Expr::UnaryOp { op: UnaryOperator::Minus, expr } => match expr.as_ref() {
+                Expr::Value(Value::Number(n, long)) => ShardingValue::Literal(Value::Number(format!("-{}", n), *long)),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        values.push(value);
+    }
+    Some(values)
+}
+
 /// A window specification (i.e. `OVER (PARTITION BY .. ORDER BY .. etc.)`)
 impl SQLAnalyse for WindowSpec {
     fn analyse(&self, ctx: &mut SQLStatementContext) -> SAResult {
@@ -445,6 +564,7 @@ impl SQLAnalyse for Statement {
             } => {
                 // write!(f, "TRUNCATE TABLE ")?;
                 table_name.analyse(ctx)?;
+                ctx.add_table(table_name.to_string(), String::from(""));
                 if let Some(ref parts) = partitions {
                     if !parts.is_empty() {
                         // write!(f, " PARTITION (")?;
@@ -493,6 +613,7 @@ impl SQLAnalyse for Statement {
                     // write!(f, " INTO ")?;
                     table_name.analyse(ctx)?;
                     // write!(f, " ")?;
+                    ctx.add_table(table_name.to_string(), String::from(""));
                 } else {
                     // write!(
                     //     f,
@@ -502,7 +623,9 @@ impl SQLAnalyse for Statement {
                     // )?;
                     table_name.analyse(ctx)?;
                     // write!(f, " ")?;
+                    ctx.add_table(table_name.to_string(), String::from(""));
                 }
+                ctx.set_insert_columns(columns.iter().map(|column| column.value.clone()).collect());
                 if !columns.is_empty() {
                     // write!(f, "(")?;
                     display_comma_separated(columns).analyse(ctx)?;
@@ -557,30 +680,44 @@ impl SQLAnalyse for Statement {
                 limit,
             } => {
                 // write!(f, "UPDATE ")?;
+                ctx.enter_query();
                 table_name.analyse(ctx)?;
+                ctx.add_table(table_name.to_string(), String::from(""));
+                ctx.set_update_table(logical_table_name(table_name.to_string().as_str()));
                 if !assignments.is_empty() {
                     // write!(f, " SET ")?;
+                    for assignment in assignments {
+                        let value = condition_values(vec![&assignment.value], ctx.get_parameter_count())
+                            .and_then(|mut values| values.pop());
+                        ctx.add_update_assignment(UpdateAssignment::new(assignment.id.value.clone(), value));
+                    }
                     display_comma_separated(assignments).analyse(ctx)?;
                 }
                 if let Some(selection) = selection {
                     // write!(f, " WHERE ")?;
+                    ctx.set_predicate(ctx.is_outermost_query());
                     selection.analyse(ctx)?;
                 }
                 if let Some(limit) = limit {
                     // write!(f, " LIMIT ")?;
                     limit.analyse(ctx)?;
                 }
+                ctx.exit_query();
             }
             Statement::Delete {
                 table_name,
                 selection,
             } => {
                 // write!(f, "DELETE FROM ")?;
+                ctx.enter_query();
                 table_name.analyse(ctx)?;
+                ctx.add_table(table_name.to_string(), String::from(""));
                 if let Some(selection) = selection {
                     // write!(f, " WHERE ")?;
+                    ctx.set_predicate(ctx.is_outermost_query());
                     selection.analyse(ctx)?;
                 }
+                ctx.exit_query();
             }
             Statement::CreateDatabase {
                 db_name,
@@ -657,6 +794,7 @@ impl SQLAnalyse for Statement {
                 //     if_not_exists = if *if_not_exists { "IF NOT EXISTS " } else { "" },
                 // )?;
                 name.analyse(ctx)?;
+                ctx.add_table(name.to_string(), String::from(""));
                 if !columns.is_empty() || !constraints.is_empty() {
                     // write!(f, " (")?;
                     display_comma_separated(columns).analyse(ctx)?;
@@ -850,6 +988,7 @@ impl SQLAnalyse for Statement {
                 //     " ON "
                 // )?;
                 table_name.analyse(ctx)?;
+                ctx.add_table(table_name.to_string(), String::from(""));
                 // write!(
                 //     f,
                 //     "("
@@ -863,6 +1002,7 @@ impl SQLAnalyse for Statement {
             Statement::AlterTable { name, operation } => {
                 // write!(f, "ALTER TABLE ")?;
                 name.analyse(ctx)?;
+                ctx.add_table(name.to_string(), String::from(""));
                 // write!(f, " ")?;
                 operation.analyse(ctx)?;
             }
@@ -883,6 +1023,10 @@ impl SQLAnalyse for Statement {
                 //     if *if_exists { " IF EXISTS" } else { "" }
                 // )?;
                 display_comma_separated(names).analyse(ctx)?;
+                for name in names {
+                    // Only a DROP TABLE has a DDL context which keeps them.
+                    ctx.add_table(name.to_string(), String::from(""));
+                }
                 // write!(
                 //     f,
                 //     "{}{}",
@@ -1448,9 +1592,13 @@ impl SQLAnalyse for Whitespace {
 
 #[cfg(test)]
 mod tests {
-    use crate::handler::database::parser::sql::{SelectStatementContext, SQLStatementContext};
+    use sqlparser::ast::Value;
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::parser::sql::{SelectStatementContext, ShardingCondition, ShardingValue, SQLStatementContext, UpdateAssignment};
     use crate::handler::database::parser::sql::analyse::SQLAnalyse;
     use crate::handler::database::parser::sql::mysql::parser;
+    use crate::handler::database::parser::sql::route::tests::with_route_ctx;
 
     #[test]
     fn test_analyse() {
@@ -1470,9 +1618,64 @@ mod tests {
             SQLStatementContext::Select(mut s) => {
                 println!("{:?}", s.common_ctx.tables);
             }
+            SQLStatementContext::Insert(_) => {}
             SQLStatementContext::Update(_) => {}
             SQLStatementContext::Delete(_) => {}
+            SQLStatementContext::Ddl(_) => {}
             SQLStatementContext::Default => {}
         }
     }
+
+    fn sharding_conditions(sql: &str) -> Vec<ShardingCondition> {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let mut sharding_conditions = with_route_ctx(&cluster, sql, &[], |route_ctx, stmt_ctx| {
+            stmt_ctx.get_sharding_conditions(route_ctx.get_cluster().get_dis_rules())
+        });
+        sharding_conditions.remove("t_order")
+            .and_then(|mut c| c.remove("user_id"))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_analyse_sharding_conditions() {
+        let number = |n: &str| ShardingValue::Literal(Value::Number(n.to_string(), false));
+
+        assert_eq!(vec![ShardingCondition::Equal(vec![number("10")])],
+                   sharding_conditions("SELECT * FROM t_order WHERE user_id = 10 AND status = 'PAID'"));
+        assert_eq!(vec![ShardingCondition::Equal(vec![ShardingValue::Parameter(1)])],
+                   sharding_conditions("SELECT * FROM t_order o WHERE o.status = ? AND (o.user_id = ?)"));
+        assert_eq!(vec![ShardingCondition::Equal(vec![number("1"), ShardingValue::Parameter(0)])],
+                   sharding_conditions("UPDATE t_order SET status = 'PAID' WHERE user_id IN (1, ?)"));
+        assert_eq!(vec![ShardingCondition::Range(number("1"), number("9"))],
+                   sharding_conditions("DELETE FROM t_order WHERE user_id BETWEEN 1 AND 9"));
+        assert_eq!(vec![ShardingCondition::GreaterThan(number("1"), true), ShardingCondition::LessThan(number("9"), false)],
+                   sharding_conditions("SELECT * FROM t_order WHERE user_id >= 1 AND 9 > user_id"));
+        assert_eq!(vec![ShardingCondition::Equal(vec![number("7")])],
+                   sharding_conditions("SELECT * FROM t_order o JOIN t_order_item i ON o.order_id = i.order_id WHERE o.user_id = 7"));
+
+        assert!(sharding_conditions("SELECT * FROM t_order WHERE user_id = 1 OR user_id = 2").is_empty());
+        assert!(sharding_conditions("SELECT * FROM t_order WHERE NOT user_id = 1").is_empty());
+        assert!(sharding_conditions("SELECT * FROM t_order WHERE user_id NOT IN (1, 2)").is_empty());
+        assert!(sharding_conditions("SELECT * FROM t_order WHERE order_id IN (SELECT order_id FROM t_order_item WHERE user_id = 1)").is_empty());
+        assert!(sharding_conditions("SELECT * FROM t_order i WHERE t_user.user_id = 1").is_empty());
+    }
+
+    #[test]
+    fn test_analyse_update_assignments() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let stmt = parser("UPDATE t_order SET status = ?, user_id = 11, amount = amount + 1 WHERE user_id = ?".to_string()).pop().unwrap();
+        let mut ctx = SQLStatementContext::new(&stmt);
+        stmt.analyse(&mut ctx).unwrap();
+        assert_eq!(&[UpdateAssignment::new(String::from("status"), Some(ShardingValue::Parameter(0))),
+                     UpdateAssignment::new(String::from("user_id"), Some(ShardingValue::Literal(Value::Number(String::from("11"), false)))),
+                     UpdateAssignment::new(String::from("amount"), None)],
+                   ctx.get_update_assignments());
+        assert_eq!(vec![String::from("user_id")], ctx.get_updated_dis_keys(cluster.get_dis_rules()));
+        assert_eq!(2, ctx.get_parameter_count());
+
+        let stmt = parser("UPDATE t_order SET status = 'PAID' WHERE user_id = 10".to_string()).pop().unwrap();
+        let mut ctx = SQLStatementContext::new(&stmt);
+        stmt.analyse(&mut ctx).unwrap();
+        assert!(ctx.get_updated_dis_keys(cluster.get_dis_rules()).is_empty());
+    }
 }
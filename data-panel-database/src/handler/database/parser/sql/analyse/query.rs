@@ -10,10 +10,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use sqlparser::ast::{Cte, Fetch, Join, JoinConstraint, JoinOperator, Offset, OffsetRows, OrderByExpr, Query, Select, SelectItem, SetExpr, SetOperator, TableAlias, TableFactor, TableWithJoins, Top, Values, With};
+use sqlparser::ast::{Cte, Expr, Fetch, Join, JoinConstraint, JoinOperator, Offset, OffsetRows, OrderByExpr, Query, Select, SelectItem, SetExpr, SetOperator, TableAlias, TableFactor, TableWithJoins, Top, UnaryOperator, Value, Values, With};
 
-use crate::handler::database::parser::sql::analyse::{display_comma_separated, SQLAnalyse};
-use crate::handler::database::parser::sql::SQLStatementContext;
+use crate::handler::database::parser::sql::analyse::{analyse_join_condition, display_comma_separated, SQLAnalyse};
+use crate::handler::database::parser::sql::{InsertRow, ShardingValue, SQLStatementContext};
 
 // use std::fmt::Write;
 
@@ -23,30 +23,70 @@ pub type SAResult = data_panel_common::common::Result<()>;
 /// including `WITH`, `UNION` / other set operations, and `ORDER BY`.
 impl SQLAnalyse for Query {
     fn analyse(&self, ctx: &mut SQLStatementContext) -> SAResult {
+        ctx.enter_query();
         if let Some(ref with) = self.with {
             with.analyse(ctx)?;
         }
+        if let SetExpr::Select(ref select) = self.body {
+            if ctx.is_outermost_query() {
+                ctx.set_select(select);
+            }
+        }
         self.body.analyse(ctx)?;
         if !self.order_by.is_empty() {
             // write!(f, " ORDER BY ")?;
+            if ctx.is_outermost_query() {
+                ctx.set_order_by(self.order_by.clone());
+            }
             display_comma_separated(&self.order_by).analyse(ctx)?;
         }
+        let outermost_query = ctx.is_outermost_query();
+        let mut limit_value = None;
         if let Some(ref limit) = self.limit {
             // write!(f, " LIMIT ")?;
+            limit_value = pagination_value(limit, ctx);
             limit.analyse(ctx)?;
         }
+        let mut offset_value = None;
         if let Some(ref offset) = self.offset {
             // write!(f, " ")?;
+            offset_value = pagination_value(&offset.value, ctx);
             offset.analyse(ctx)?;
         }
+        if outermost_query {
+            ctx.set_pagination(limit_value, offset_value);
+        }
         if let Some(ref fetch) = self.fetch {
             // write!(f, " ")?;
             fetch.analyse(ctx)?;
         }
+        ctx.exit_query();
         Ok(())
     }
 }
 
+/// The count of a `LIMIT` or `OFFSET`, a `?` is the next placeholder of the statement.
+fn pagination_value(expr: &Expr, ctx: &SQLStatementContext) -> Option<ShardingValue> {
+    match expr {
+        Expr::Value(value @ Value::Number(..)) => Some(ShardingValue::Literal(value.clone())),
+        Expr::Identifier(ident) if ident.value == "?" => Some(ShardingValue::Parameter(ctx.get_parameter_count())),
+        _ => None,
+    }
+}
+
+/// The value of an `INSERT ... VALUES` column, the `?` of a row are numbered in the order they appear.
+fn insert_value(expr: &Expr, ctx: &SQLStatementContext) -> Option<ShardingValue> {
+    match expr {
+        Expr::Value(value) => Some(ShardingValue::Literal(value.clone())),
+        Expr::UnaryOp { op: UnaryOperator::Minus, expr } => match expr.as_ref() {
+            Expr::Value(Value::Number(n, long)) => Some(ShardingValue::Literal(Value::Number(format!("-{}", n), *long))),
+            _ => None,
+        },
+        Expr::Identifier(ident) if ident.value == "?" => Some(ShardingValue::Parameter(ctx.get_parameter_count())),
+        _ => None,
+    }
+}
+
 /// A node in a tree, representing a "query body" expression, roughly:
 /// `SELECT ... [ {UNION|EXCEPT|INTERSECT} SELECT ...]`
 impl SQLAnalyse for SetExpr {
@@ -71,12 +111,15 @@ impl SQLAnalyse for SetExpr {
                 all,
             } => {
                 let all_str = if *all { " ALL" } else { "" };
+                // The rows come from either side, so no side's conditions hold for all of them.
+                ctx.enter_query();
                 left.analyse(ctx)?;
                 // write!(f, " ")?;
                 op.analyse(ctx)?;
                 // write!(f, "{}", all_str)?;
                 // write!(f, " ")?;
                 right.analyse(ctx)?;
+                ctx.exit_query();
             }
         };
         Ok(())
@@ -112,6 +155,7 @@ impl SQLAnalyse for Select {
         }
         if let Some(ref selection) = self.selection {
             // write!(f, " WHERE ")?;
+            ctx.set_predicate(ctx.is_outermost_query());
             selection.analyse(ctx)?;
         }
         if !self.group_by.is_empty() {
@@ -280,6 +324,7 @@ impl SQLAnalyse for Join {
                     match self.0 {
                         JoinConstraint::On(expr) => {
                             // write!(f, " ON ")?;
+                            analyse_join_condition(expr, ctx);
                             expr.analyse(ctx)?;
                             Ok(())
                         }
@@ -419,12 +464,21 @@ impl SQLAnalyse for Top {
 impl SQLAnalyse for Values {
     fn analyse(&self, ctx: &mut SQLStatementContext) -> SAResult {
         // write!(f, "VALUES ")?;
+        let outermost_query = ctx.is_outermost_query();
         let mut delim = "";
         for row in &self.0 {
             // write!(f, "{}", delim)?;
             delim = ", ";
             // write!(f, "(")?;
-            display_comma_separated(row).analyse(ctx)?;
+            let first_parameter = ctx.get_parameter_count();
+            let mut values = Vec::with_capacity(row.len());
+            for expr in row {
+                values.push(insert_value(expr, ctx));
+                expr.analyse(ctx)?;
+            }
+            if outermost_query {
+                ctx.add_insert_row(InsertRow::new(values, first_parameter..ctx.get_parameter_count()));
+            }
             // write!(f, ")")?;
         }
         Ok(())
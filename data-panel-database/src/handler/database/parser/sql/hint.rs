@@ -0,0 +1,168 @@
+use data_panel_common::common::Error;
+
+pub type HintResult<T> = data_panel_common::common::Result<T>;
+
+const HINT_START: &str = "/*+";
+const HINT_END: &str = "*/";
+const HINT_NAME: &str = "DBMESH";
+
+/// Routing forced by `/*+ DBMESH ... */` comments in the SQL:
+/// `shard=200` sends the statement to that data segment whatever the router decides,
+/// `master` sends it to the primary of the segments.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RouteHint {
+    shard: Option<u32>,
+    master: bool,
+}
+
+impl RouteHint {
+    pub fn get_shard(&self) -> Option<u32> {
+        self.shard
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.master
+    }
+
+    fn add_option(&mut self, option: &str) -> HintResult<()> {
+        let (name, value) = match option.find('=') {
+            Some(index) => (&option[..index], Some(&option[index + 1..])),
+            None => (option, None),
+        };
+        match (name.to_lowercase().as_str(), value) {
+            ("shard", Some(value)) => {
+                let shard = value.parse::<u32>().map_err(|_| {
+                    Error::General(format!("DBMESH hint shard={} is not a data segment id", value))
+                })?;
+                self.shard = Some(shard);
+            }
+            ("master", None) => self.master = true,
+            _ => return Err(Error::General(format!("unknown DBMESH hint {}", option))),
+        }
+        Ok(())
+    }
+}
+
+/// Take the `/*+ DBMESH ... */` comments out of the SQL, which is parsed and sent to
+/// the segments without them. Other comments, and hints in string literals and quoted
+/// identifiers, are kept. Quotes in comments do not open a literal.
+pub fn extract_hint(sql: &str) -> HintResult<(Option<RouteHint>, String)> {
+    let mut hint: Option<RouteHint> = None;
+    let mut stripped = String::with_capacity(sql.len());
+    let mut quote: Option<char> = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        if let Some(q) = quote {
+            if c == '\\' && q != '`' {
+                // An escaped character, the quote does not end here.
+                let escaped: String = rest.chars().take(2).collect();
+                stripped.push_str(escaped.as_str());
+                rest = &rest[escaped.len()..];
+                continue;
+            }
+            if c == q {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' || c == '`' {
+            quote = Some(c);
+        } else if let Some(options) = hint_options(rest) {
+            let end = rest.find(HINT_END).ok_or_else(|| {
+                Error::General(String::from("DBMESH hint is not closed by */"))
+            })?;
+            let options = &options[..end - (rest.len() - options.len())];
+            let route_hint = hint.get_or_insert_with(RouteHint::default);
+            for option in options.split(|c: char| c == ',' || c.is_whitespace()) {
+                if !option.is_empty() {
+                    route_hint.add_option(option)?;
+                }
+            }
+            rest = &rest[end + HINT_END.len()..];
+            // `SELECT/*+ DBMESH master */*` must not become `SELECT*`.
+            if !stripped.ends_with(char::is_whitespace) && !rest.starts_with(char::is_whitespace) && !stripped.is_empty() {
+                stripped.push(' ');
+            }
+            continue;
+        } else if let Some(len) = comment_len(rest) {
+            stripped.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        stripped.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok((hint, stripped.trim().to_string()))
+}
+
+/// The length of the `/* ... */`, `-- ...` or `# ...` comment the SQL starts with.
+fn comment_len(sql: &str) -> Option<usize> {
+    if sql.starts_with("/*") {
+        return Some(sql[2..].find(HINT_END).map_or(sql.len(), |end| end + 2 + HINT_END.len()));
+    }
+    let dash_dash = sql.starts_with("--") && sql[2..].starts_with(char::is_whitespace);
+    if dash_dash || sql.starts_with('#') {
+        return Some(sql.find('\n').unwrap_or(sql.len()));
+    }
+    None
+}
+
+/// The text after `DBMESH` when the SQL starts with a DBMESH hint.
+fn hint_options(sql: &str) -> Option<&str> {
+    let body = sql.strip_prefix(HINT_START)?.trim_start();
+    let name = body.get(..HINT_NAME.len())?;
+    let options = &body[HINT_NAME.len()..];
+    if name.eq_ignore_ascii_case(HINT_NAME) && (options.starts_with(char::is_whitespace) || options.starts_with(HINT_END)) {
+        Some(options)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handler::database::parser::sql::hint::extract_hint;
+
+    #[test]
+    fn test_extract_hint() {
+        let (hint, sql) = extract_hint("/*+ DBMESH shard=200 */ SELECT * FROM t_order").unwrap();
+        assert_eq!(Some(200), hint.unwrap().get_shard());
+        assert!(!hint.unwrap().is_master());
+        assert_eq!("SELECT * FROM t_order", sql);
+
+        let (hint, sql) = extract_hint("SELECT /*+ dbmesh master, shard=300 */ id FROM t_order").unwrap();
+        assert_eq!(Some(300), hint.unwrap().get_shard());
+        assert!(hint.unwrap().is_master());
+        assert_eq!("SELECT  id FROM t_order", sql);
+
+        let (hint, sql) = extract_hint("SELECT/*+ DBMESH master */* FROM t_order").unwrap();
+        assert!(hint.unwrap().is_master());
+        assert_eq!("SELECT * FROM t_order", sql);
+
+        // Other comments, and hints in literals, are no DBMESH hints.
+        for sql in vec!["SELECT /*+ MAX_EXECUTION_TIME(1000) */ * FROM t_order",
+                        "SELECT '/*+ DBMESH master */' FROM t_order",
+                        "SELECT 'it\\'s /*+ DBMESH master */' FROM t_order",
+                        "SELECT /*+ DBMESHX */ 1"] {
+            assert_eq!((None, sql.to_string()), extract_hint(sql).unwrap());
+        }
+
+        assert!(extract_hint("/*+ DBMESH shard=a */ SELECT 1").is_err());
+        assert!(extract_hint("/*+ DBMESH slave */ SELECT 1").is_err());
+        assert!(extract_hint("/*+ DBMESH master SELECT 1").is_err());
+    }
+
+    #[test]
+    fn test_extract_hint_after_comments() {
+        // A quote in a comment must not hide the hint after it.
+        for sql in vec!["/* it's */ SELECT /*+ DBMESH master */ 1",
+                        "-- it's\nSELECT /*+ DBMESH master */ 1",
+                        "# it's\nSELECT /*+ DBMESH master */ 1"] {
+            let (hint, stripped) = extract_hint(sql).unwrap();
+            assert!(hint.unwrap().is_master());
+            assert_eq!(sql.replace("/*+ DBMESH master */", ""), stripped);
+        }
+
+        let (hint, sql) = extract_hint("SELECT 1 --it's /*+ DBMESH master */").unwrap();
+        assert!(hint.is_none());
+        assert_eq!("SELECT 1 --it's /*+ DBMESH master */", sql);
+    }
+}
@@ -1,4 +1,10 @@
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::ops::Range;
+
+use sqlparser::ast::{Expr, ObjectType, OrderByExpr, Select, SelectItem, Statement, Value};
+
+use crate::discovery::database::DisRules;
 
 pub mod mysql;
 pub mod postgresql;
@@ -6,51 +12,629 @@ pub mod postgresql;
 pub mod rewrite;
 pub mod analyse;
 pub mod route;
+pub mod hint;
 
 pub enum SQLStatementContext {
     Select(SelectStatementContext),
+    Insert(InsertStatementContext),
     Update(UpdateStatementContext),
     Delete(DeleteStatementContext),
+    /// `CREATE TABLE`, `ALTER TABLE`, `CREATE INDEX`, `DROP TABLE` and `TRUNCATE`, executed
+    /// on every physical table of their tables.
+    Ddl(DdlStatementContext),
     Default,
 }
 
 impl SQLStatementContext {
+    pub fn new(statement: &Statement) -> Self {
+        match statement {
+            Statement::Query(_) => SQLStatementContext::Select(SelectStatementContext::new()),
+            Statement::Insert { .. } => SQLStatementContext::Insert(InsertStatementContext::new()),
+            Statement::Update { .. } => SQLStatementContext::Update(UpdateStatementContext::new()),
+            Statement::Delete { .. } => SQLStatementContext::Delete(DeleteStatementContext::new()),
+            Statement::CreateTable { .. }
+            | Statement::AlterTable { .. }
+            | Statement::CreateIndex { .. }
+            | Statement::Drop { object_type: ObjectType::Table, .. }
+            | Statement::Truncate { .. } => SQLStatementContext::Ddl(DdlStatementContext::new()),
+            // The tables of the explained statement decide where EXPLAIN goes.
+            Statement::Explain { statement, .. } => SQLStatementContext::new(statement),
+            _ => SQLStatementContext::Default,
+        }
+    }
+
     pub fn add_table(&mut self, table: String, alias: String) {
         match self {
             SQLStatementContext::Select(s) => {
                 s.common_ctx.add_table(table, alias);
             }
-            SQLStatementContext::Update(_) => {}
-            SQLStatementContext::Delete(_) => {}
+            SQLStatementContext::Insert(s) => {
+                s.common_ctx.add_table(table, alias);
+            }
+            SQLStatementContext::Update(s) => {
+                s.common_ctx.add_table(table, alias);
+            }
+            SQLStatementContext::Delete(s) => {
+                s.common_ctx.add_table(table, alias);
+            }
+            SQLStatementContext::Ddl(s) => {
+                s.common_ctx.add_table(table, alias);
+            }
             SQLStatementContext::Default => {}
         }
     }
+
+    fn get_common_ctx_mut(&mut self) -> Option<&mut CommonStatementContext> {
+        match self {
+            SQLStatementContext::Select(s) => Some(&mut s.common_ctx),
+            SQLStatementContext::Insert(s) => Some(&mut s.common_ctx),
+            SQLStatementContext::Update(s) => Some(&mut s.common_ctx),
+            SQLStatementContext::Delete(s) => Some(&mut s.common_ctx),
+            SQLStatementContext::Ddl(s) => Some(&mut s.common_ctx),
+            SQLStatementContext::Default => None,
+        }
+    }
+
+    pub fn get_common_ctx(&self) -> Option<&CommonStatementContext> {
+        match self {
+            SQLStatementContext::Select(s) => Some(&s.common_ctx),
+            SQLStatementContext::Insert(s) => Some(&s.common_ctx),
+            SQLStatementContext::Update(s) => Some(&s.common_ctx),
+            SQLStatementContext::Delete(s) => Some(&s.common_ctx),
+            SQLStatementContext::Ddl(s) => Some(&s.common_ctx),
+            SQLStatementContext::Default => None,
+        }
+    }
+
+    /// Logical table names referenced by the statement, without schema and quotes.
+    pub fn get_table_names(&self) -> Vec<String> {
+        let mut table_names = vec![];
+        if let Some(common_ctx) = self.get_common_ctx() {
+            for table in common_ctx.tables.keys() {
+                let table_name = logical_table_name(table);
+                if !table_names.contains(&table_name) {
+                    table_names.push(table_name);
+                }
+            }
+        }
+        table_names.sort();
+        table_names
+    }
+
+    pub fn add_column_condition(&mut self, column_condition: ColumnCondition) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.column_conditions.push(column_condition);
+        }
+    }
+
+    pub fn add_column_equality(&mut self, column_equality: ColumnEquality) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.column_equalities.push(column_equality);
+        }
+    }
+
+    /// Index of the next `?` placeholder, counted in the order they appear in the SQL.
+    pub fn get_parameter_count(&self) -> usize {
+        self.get_common_ctx().map(|c| c.parameter_count).unwrap_or(0)
+    }
+
+    pub fn add_parameter(&mut self) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.parameter_count += 1;
+        }
+    }
+
+    pub fn enter_query(&mut self) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.query_depth += 1;
+        }
+    }
+
+    pub fn exit_query(&mut self) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.query_depth -= 1;
+        }
+    }
+
+    /// Only the WHERE clause of the outermost query filters the rows of the statement.
+    pub fn is_outermost_query(&self) -> bool {
+        self.get_common_ctx().map(|c| c.query_depth <= 1).unwrap_or(false)
+    }
+
+    /// Mark the next analysed expression as a predicate which must hold for every row,
+    /// i.e. the WHERE clause itself or an operand of a top level `AND`.
+    pub fn set_predicate(&mut self, predicate: bool) {
+        if let Some(common_ctx) = self.get_common_ctx_mut() {
+            common_ctx.predicate = predicate;
+        }
+    }
+
+    pub fn take_predicate(&mut self) -> bool {
+        match self.get_common_ctx_mut() {
+            Some(common_ctx) => std::mem::replace(&mut common_ctx.predicate, false),
+            None => false,
+        }
+    }
+
+    /// `ORDER BY` of the outermost query, which the merged rows of all segments must follow.
+    pub fn set_order_by(&mut self, order_by: Vec<OrderByExpr>) {
+        if let SQLStatementContext::Select(s) = self {
+            s.order_by = order_by;
+        }
+    }
+
+    pub fn get_order_by(&self) -> &[OrderByExpr] {
+        match self {
+            SQLStatementContext::Select(s) => s.order_by.as_slice(),
+            _ => &[],
+        }
+    }
+
+    /// Select list, `GROUP BY` and `HAVING` of the outermost query when it is a plain `SELECT`.
+    pub fn set_select(&mut self, select: &Select) {
+        if let SQLStatementContext::Select(s) = self {
+            s.distinct = select.distinct;
+            s.projection = select.projection.clone();
+            s.group_by = select.group_by.clone();
+            s.having = select.having.clone();
+        }
+    }
+
+    pub fn is_distinct(&self) -> bool {
+        match self {
+            SQLStatementContext::Select(s) => s.distinct,
+            _ => false,
+        }
+    }
+
+    pub fn get_projection(&self) -> &[SelectItem] {
+        match self {
+            SQLStatementContext::Select(s) => s.projection.as_slice(),
+            _ => &[],
+        }
+    }
+
+    pub fn get_group_by(&self) -> &[Expr] {
+        match self {
+            SQLStatementContext::Select(s) => s.group_by.as_slice(),
+            _ => &[],
+        }
+    }
+
+    pub fn get_having(&self) -> Option<&Expr> {
+        match self {
+            SQLStatementContext::Select(s) => s.having.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// `LIMIT` and `OFFSET` of the outermost query.
+    pub fn set_pagination(&mut self, limit: Option<ShardingValue>, offset: Option<ShardingValue>) {
+        if let SQLStatementContext::Select(s) = self {
+            s.limit = limit;
+            s.offset = offset;
+        }
+    }
+
+    pub fn get_limit(&self) -> Option<&ShardingValue> {
+        match self {
+            SQLStatementContext::Select(s) => s.limit.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_offset(&self) -> Option<&ShardingValue> {
+        match self {
+            SQLStatementContext::Select(s) => s.offset.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Column list of an `INSERT`, empty when the values are for all columns in table order.
+    pub fn set_insert_columns(&mut self, columns: Vec<String>) {
+        if let SQLStatementContext::Insert(s) = self {
+            s.columns = columns;
+        }
+    }
+
+    pub fn get_insert_columns(&self) -> &[String] {
+        match self {
+            SQLStatementContext::Insert(s) => s.columns.as_slice(),
+            _ => &[],
+        }
+    }
+
+    pub fn add_insert_row(&mut self, row: InsertRow) {
+        if let SQLStatementContext::Insert(s) = self {
+            s.rows.push(row);
+        }
+    }
+
+    /// Rows of `INSERT ... VALUES`, empty for other statements and `INSERT ... SELECT`.
+    pub fn get_insert_rows(&self) -> &[InsertRow] {
+        match self {
+            SQLStatementContext::Insert(s) => s.rows.as_slice(),
+            _ => &[],
+        }
+    }
+
+    /// The table an `UPDATE` writes, as opposed to those of its subqueries.
+    pub fn set_update_table(&mut self, table: String) {
+        if let SQLStatementContext::Update(s) = self {
+            s.table = table;
+        }
+    }
+
+    pub fn add_update_assignment(&mut self, assignment: UpdateAssignment) {
+        if let SQLStatementContext::Update(s) = self {
+            s.assignments.push(assignment);
+        }
+    }
+
+    /// The `SET` of an `UPDATE`, empty for other statements.
+    pub fn get_update_assignments(&self) -> &[UpdateAssignment] {
+        match self {
+            SQLStatementContext::Update(s) => s.assignments.as_slice(),
+            _ => &[],
+        }
+    }
+
+    /// The dis keys an `UPDATE` of a distributed table sets.
+    pub fn get_updated_dis_keys(&self, dis_rules: &DisRules) -> Vec<String> {
+        let dis_table = match self {
+            SQLStatementContext::Update(s) => match dis_rules.get_distributed_table(s.table.as_str()) {
+                Some(dis_table) => dis_table,
+                None => return vec![],
+            },
+            _ => return vec![],
+        };
+        dis_table.get_dis_keys().iter()
+            .filter(|dis_key| self.get_update_assignments().iter().any(|a| a.get_column().eq_ignore_ascii_case(dis_key)))
+            .cloned()
+            .collect()
+    }
+
+    /// Conditions on the `dis_keys` of the distributed tables of the statement,
+    /// keyed by logical table name and then by dis key.
+    pub fn get_sharding_conditions(&self, dis_rules: &DisRules) -> ShardingConditions {
+        let mut sharding_conditions = ShardingConditions::new();
+        let common_ctx = match self.get_common_ctx() {
+            Some(common_ctx) => common_ctx,
+            None => return sharding_conditions,
+        };
+        for column_condition in common_ctx.column_conditions.iter() {
+            let table_names = match column_condition.get_qualifier() {
+                Some(qualifier) => common_ctx.resolve_qualifier(qualifier).into_iter().collect(),
+                None => self.get_table_names(),
+            };
+            for table_name in table_names {
+                let dis_table = match dis_rules.get_distributed_table(table_name.as_str()) {
+                    Some(dis_table) => dis_table,
+                    None => continue,
+                };
+                let dis_key = dis_table.get_dis_keys().iter()
+                    .find(|k| k.eq_ignore_ascii_case(column_condition.get_column()));
+                if let Some(dis_key) = dis_key {
+                    sharding_conditions.entry(table_name)
+                        .or_insert_with(HashMap::new)
+                        .entry(dis_key.clone())
+                        .or_insert_with(Vec::new)
+                        .push(column_condition.get_condition().clone());
+                }
+            }
+        }
+
+        // A dis key value puts the rows of bound tables on the same segment, so the
+        // conditions on the dis key of one table hold for the bound one too.
+        let bindings = self.get_table_bindings(dis_rules);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for binding in bindings.iter() {
+                for (from, to) in vec![(binding.get_left(), binding.get_right()), (binding.get_right(), binding.get_left())] {
+                    let conditions = match sharding_conditions.get(&from.0).and_then(|c| c.get(&from.1)) {
+                        Some(conditions) => conditions.clone(),
+                        None => continue,
+                    };
+                    let to_conditions = sharding_conditions.entry(to.0.clone())
+                        .or_insert_with(HashMap::new)
+                        .entry(to.1.clone())
+                        .or_insert_with(Vec::new);
+                    for condition in conditions {
+                        if !to_conditions.contains(&condition) {
+                            to_conditions.push(condition);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+        sharding_conditions
+    }
+
+    /// Pairs of distributed tables of the statement which are `dis_relatives` and
+    /// joined on their dis keys.
+    pub fn get_table_bindings(&self, dis_rules: &DisRules) -> Vec<TableBinding> {
+        let mut table_bindings = vec![];
+        let common_ctx = match self.get_common_ctx() {
+            Some(common_ctx) => common_ctx,
+            None => return table_bindings,
+        };
+        for column_equality in common_ctx.column_equalities.iter() {
+            let left = common_ctx.resolve_dis_key(column_equality.get_left(), dis_rules);
+            let right = common_ctx.resolve_dis_key(column_equality.get_right(), dis_rules);
+            let (left, right) = match (left, right) {
+                (Some(left), Some(right)) => (left, right),
+                _ => continue,
+            };
+            if left.0 == right.0 || left.2 != right.2 || !dis_rules.is_dis_relative(left.0.as_str(), right.0.as_str()) {
+                continue;
+            }
+            let table_binding = TableBinding::new((left.0, left.1), (right.0, right.1));
+            if !table_bindings.contains(&table_binding) {
+                table_bindings.push(table_binding);
+            }
+        }
+        table_bindings
+    }
+}
+
+/// Logical table name -> dis key -> conditions, all of which hold for the statement.
+pub type ShardingConditions = HashMap<String, HashMap<String, Vec<ShardingCondition>>>;
+
+/// The value side of a column predicate, or a `LIMIT` / `OFFSET` count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShardingValue {
+    Literal(Value),
+    /// Zero based index of the `?` placeholder in a prepared statement.
+    Parameter(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShardingCondition {
+    /// `col = v` or `col IN (v1, v2, ...)`
+    Equal(Vec<ShardingValue>),
+    /// `col BETWEEN low AND high`
+    Range(ShardingValue, ShardingValue),
+    /// `col > v` or `col >= v`, the flag tells whether `v` itself matches.
+    GreaterThan(ShardingValue, bool),
+    /// `col < v` or `col <= v`, the flag tells whether `v` itself matches.
+    LessThan(ShardingValue, bool),
+}
+
+/// One row of `INSERT ... VALUES`: the literal or placeholder of each column, `None`
+/// for other expressions, and the indexes of the `?` placeholders in the row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertRow {
+    values: Vec<Option<ShardingValue>>,
+    parameters: Range<usize>,
+}
+
+impl InsertRow {
+    pub fn new(values: Vec<Option<ShardingValue>>, parameters: Range<usize>) -> Self {
+        InsertRow {
+            values,
+            parameters,
+        }
+    }
+
+    pub fn get_values(&self) -> &[Option<ShardingValue>] {
+        self.values.as_slice()
+    }
+
+    pub fn get_parameters(&self) -> &Range<usize> {
+        &self.parameters
+    }
+}
+
+/// `column = value` of an `UPDATE`, the value is `None` unless it is a literal or a `?` placeholder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateAssignment {
+    column: String,
+    value: Option<ShardingValue>,
+}
+
+impl UpdateAssignment {
+    pub fn new(column: String, value: Option<ShardingValue>) -> Self {
+        UpdateAssignment {
+            column,
+            value,
+        }
+    }
+
+    pub fn get_column(&self) -> &String {
+        &self.column
+    }
+
+    pub fn get_value(&self) -> Option<&ShardingValue> {
+        self.value.as_ref()
+    }
+}
+
+/// A predicate on a column found in the WHERE clause, the qualifier is the
+/// table name or alias as written in the SQL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnCondition {
+    qualifier: Option<String>,
+    column: String,
+    condition: ShardingCondition,
+}
+
+impl ColumnCondition {
+    pub fn new(qualifier: Option<String>, column: String, condition: ShardingCondition) -> Self {
+        ColumnCondition {
+            qualifier,
+            column,
+            condition,
+        }
+    }
+
+    pub fn get_qualifier(&self) -> Option<&String> {
+        self.qualifier.as_ref()
+    }
+
+    pub fn get_column(&self) -> &String {
+        &self.column
+    }
+
+    pub fn get_condition(&self) -> &ShardingCondition {
+        &self.condition
+    }
+}
+
+/// A column as written in the SQL, with the table name or alias qualifying it.
+pub type ColumnRef = (Option<String>, String);
+
+/// `a.x = b.y`, found in a join constraint or the WHERE clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnEquality {
+    left: ColumnRef,
+    right: ColumnRef,
+}
+
+impl ColumnEquality {
+    pub fn new(left: ColumnRef, right: ColumnRef) -> Self {
+        ColumnEquality {
+            left,
+            right,
+        }
+    }
+
+    pub fn get_left(&self) -> &ColumnRef {
+        &self.left
+    }
+
+    pub fn get_right(&self) -> &ColumnRef {
+        &self.right
+    }
+}
+
+/// Two tables joined on the same position of their dis keys, as (logical table name, dis key).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableBinding {
+    left: (String, String),
+    right: (String, String),
+}
+
+impl TableBinding {
+    pub fn new(left: (String, String), right: (String, String)) -> Self {
+        TableBinding {
+            left,
+            right,
+        }
+    }
+
+    pub fn get_left(&self) -> &(String, String) {
+        &self.left
+    }
+
+    pub fn get_right(&self) -> &(String, String) {
+        &self.right
+    }
+}
+
+/// Strip the schema qualifier and the quote characters from a table name.
+pub fn logical_table_name(table: &str) -> String {
+    let table = table.rsplit('.').next().unwrap_or(table);
+    table.trim_matches(|c| c == '`' || c == '"' || c == '[' || c == ']').to_string()
 }
 
 pub struct CommonStatementContext {
     tables: HashMap<String, String>,
+    column_conditions: Vec<ColumnCondition>,
+    column_equalities: Vec<ColumnEquality>,
+    parameter_count: usize,
+    query_depth: usize,
+    predicate: bool,
 }
 
 impl CommonStatementContext {
     pub fn new() -> Self {
         CommonStatementContext {
             tables: Default::default(),
+            column_conditions: vec![],
+            column_equalities: vec![],
+            parameter_count: 0,
+            query_depth: 0,
+            predicate: false,
         }
     }
 
     pub fn add_table(&mut self, table: String, alias: String) {
         self.tables.insert(table, alias);
     }
+
+    pub fn get_tables(&self) -> &HashMap<String, String> {
+        &self.tables
+    }
+
+    pub fn get_column_conditions(&self) -> &Vec<ColumnCondition> {
+        &self.column_conditions
+    }
+
+    /// Logical table name, dis key and its position for a qualified column which is a dis key.
+    fn resolve_dis_key(&self, column: &ColumnRef, dis_rules: &DisRules) -> Option<(String, String, usize)> {
+        let table = self.resolve_qualifier(column.0.as_ref()?)?;
+        let dis_keys = dis_rules.get_distributed_table(table.as_str())?.get_dis_keys();
+        let index = dis_keys.iter().position(|k| k.eq_ignore_ascii_case(column.1.as_str()))?;
+        let dis_key = dis_keys[index].clone();
+        Some((table, dis_key, index))
+    }
+
+    /// Logical name of the table referred to by an alias or a (schema qualified) table name.
+    fn resolve_qualifier(&self, qualifier: &str) -> Option<String> {
+        if let Some((table, _)) = self.tables.iter().find(|(_, alias)| alias.as_str() == qualifier) {
+            return Some(logical_table_name(table));
+        }
+        let qualifier = logical_table_name(qualifier);
+        self.tables.keys()
+            .map(|table| logical_table_name(table))
+            .find(|table| *table == qualifier)
+    }
 }
 
 pub struct SelectStatementContext {
     common_ctx: CommonStatementContext,
+    distinct: bool,
+    projection: Vec<SelectItem>,
+    group_by: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<OrderByExpr>,
+    limit: Option<ShardingValue>,
+    offset: Option<ShardingValue>,
 }
 
 impl SelectStatementContext {
     pub fn new() -> Self {
         SelectStatementContext {
-            common_ctx: CommonStatementContext::new()
+            common_ctx: CommonStatementContext::new(),
+            distinct: false,
+            projection: vec![],
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        }
+    }
+
+    pub fn add_table(&mut self, table: String, alias: String) {
+        self.common_ctx.tables.insert(table, alias);
+    }
+}
+
+pub struct InsertStatementContext {
+    common_ctx: CommonStatementContext,
+    columns: Vec<String>,
+    rows: Vec<InsertRow>,
+}
+
+impl InsertStatementContext {
+    pub fn new() -> Self {
+        InsertStatementContext {
+            common_ctx: CommonStatementContext::new(),
+            columns: vec![],
+            rows: vec![],
         }
     }
 
@@ -61,12 +645,16 @@ impl SelectStatementContext {
 
 pub struct UpdateStatementContext {
     common_ctx: CommonStatementContext,
+    table: String,
+    assignments: Vec<UpdateAssignment>,
 }
 
 impl UpdateStatementContext {
     pub fn new() -> Self {
         UpdateStatementContext {
-            common_ctx: CommonStatementContext::new()
+            common_ctx: CommonStatementContext::new(),
+            table: String::new(),
+            assignments: vec![],
         }
     }
 
@@ -91,4 +679,138 @@ impl DeleteStatementContext {
     }
 }
 
-pub struct SQLRewriteContext {}
\ No newline at end of file
+pub struct DdlStatementContext {
+    common_ctx: CommonStatementContext,
+}
+
+impl DdlStatementContext {
+    pub fn new() -> Self {
+        DdlStatementContext {
+            common_ctx: CommonStatementContext::new()
+        }
+    }
+
+    pub fn add_table(&mut self, table: String, alias: String) {
+        self.common_ctx.tables.insert(table, alias);
+    }
+}
+
+/// A column the proxy adds to the select list of the outermost query for the merge
+/// stage, e.g. the `SUM` and `COUNT` an `AVG` is computed from, not sent to the client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedColumn {
+    expr: Expr,
+    alias: String,
+}
+
+impl DerivedColumn {
+    pub fn new(expr: Expr, alias: String) -> Self {
+        DerivedColumn {
+            expr,
+            alias,
+        }
+    }
+
+    pub fn get_expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn get_alias(&self) -> &String {
+        &self.alias
+    }
+}
+
+/// What the rewrite does with `LIMIT` and `OFFSET` of the outermost query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaginationRewrite {
+    Keep,
+    /// `LIMIT row_count` without `OFFSET`, the merge skips the offset of all segments.
+    RowCount(u64),
+    /// Neither, the merge needs every row, e.g. to regroup them.
+    Remove,
+}
+
+/// State of a rewrite for one data segment.
+pub struct SQLRewriteContext {
+    /// Logical table name -> physical name on the segment, `table` or `database.table`.
+    table_mappings: HashMap<String, String>,
+    pagination: PaginationRewrite,
+    derived_columns: Vec<DerivedColumn>,
+    /// Appended to `GROUP BY`, so that the arguments of `COUNT(DISTINCT ...)` reach the merge.
+    derived_group_by: Vec<Expr>,
+    /// `HAVING` is applied after the partial aggregates of the segments are merged.
+    remove_having: bool,
+    /// Indexes of the `INSERT ... VALUES` rows which go to the segment, `None` for all of them.
+    insert_rows: Option<Vec<usize>>,
+    query_depth: Cell<usize>,
+}
+
+impl SQLRewriteContext {
+    pub fn new(table_mappings: HashMap<String, String>) -> Self {
+        SQLRewriteContext {
+            table_mappings,
+            pagination: PaginationRewrite::Keep,
+            derived_columns: vec![],
+            derived_group_by: vec![],
+            remove_having: false,
+            insert_rows: None,
+            query_depth: Cell::new(0),
+        }
+    }
+
+    pub fn get_table_mapping(&self, table: &str) -> Option<&String> {
+        self.table_mappings.get(table)
+    }
+
+    pub fn set_pagination(&mut self, pagination: PaginationRewrite) {
+        self.pagination = pagination;
+    }
+
+    pub fn get_pagination(&self) -> PaginationRewrite {
+        self.pagination
+    }
+
+    pub fn set_derived_columns(&mut self, derived_columns: Vec<DerivedColumn>) {
+        self.derived_columns = derived_columns;
+    }
+
+    pub fn get_derived_columns(&self) -> &[DerivedColumn] {
+        self.derived_columns.as_slice()
+    }
+
+    pub fn set_derived_group_by(&mut self, derived_group_by: Vec<Expr>) {
+        self.derived_group_by = derived_group_by;
+    }
+
+    pub fn get_derived_group_by(&self) -> &[Expr] {
+        self.derived_group_by.as_slice()
+    }
+
+    pub fn set_remove_having(&mut self, remove_having: bool) {
+        self.remove_having = remove_having;
+    }
+
+    pub fn is_remove_having(&self) -> bool {
+        self.remove_having
+    }
+
+    pub fn set_insert_rows(&mut self, insert_rows: Option<Vec<usize>>) {
+        self.insert_rows = insert_rows;
+    }
+
+    pub fn get_insert_rows(&self) -> Option<&Vec<usize>> {
+        self.insert_rows.as_ref()
+    }
+
+    pub fn enter_query(&self) {
+        self.query_depth.set(self.query_depth.get() + 1);
+    }
+
+    pub fn exit_query(&self) {
+        self.query_depth.set(self.query_depth.get() - 1);
+    }
+
+    pub fn is_outermost_query(&self) -> bool {
+        self.query_depth.get() <= 1
+    }
+}
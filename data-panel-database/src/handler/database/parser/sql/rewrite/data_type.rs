@@ -10,18 +10,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::DataType;
 
 use crate::handler::database::parser::sql::rewrite::SQLReWrite;
+use crate::handler::database::parser::sql::SQLRewriteContext;
 
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 /// SQL data types
 impl SQLReWrite for DataType {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             DataType::Char(size) => {
                 format_type_with_optional_length(f, "CHAR", size)?;
@@ -12,18 +12,18 @@
 
 //! AST types specific to CREATE/ALTER variants of [Statement]
 //! (commonly referred to as Data Definition Language, or DDL)
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::{AlterTableOperation, ColumnDef, ColumnOption, ColumnOptionDef, Ident, ReferentialAction, TableConstraint};
 
 use crate::handler::database::parser::sql::rewrite::{display_comma_separated, display_separated, SQLReWrite};
+use crate::handler::database::parser::sql::SQLRewriteContext;
 
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 /// An `ALTER TABLE` (`Statement::AlterTable`) operation
 impl SQLReWrite for AlterTableOperation {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             AlterTableOperation::AddPartitions {
                 if_not_exists,
@@ -130,7 +130,7 @@ impl SQLReWrite for AlterTableOperation {
 /// A table-level constraint, specified in a `CREATE TABLE` or an
 /// `ALTER TABLE ADD <constraint>` statement.
 impl SQLReWrite for TableConstraint {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             TableConstraint::Unique {
                 name,
@@ -189,7 +189,7 @@ impl SQLReWrite for TableConstraint {
 
 /// SQL column definition
 impl SQLReWrite for ColumnDef {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.name.rewrite(f, ctx)?;
         write!(f, " ")?;
         self.data_type.rewrite(f, ctx)?;
@@ -218,7 +218,7 @@ impl SQLReWrite for ColumnDef {
 /// non-constraint options, lumping them all together under the umbrella of
 /// "column options," and we allow any column option to be named.
 impl SQLReWrite for ColumnOptionDef {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         display_constraint_name(&self.name).rewrite(f, ctx)?;
         self.option.rewrite(f, ctx)?;
         Ok(())
@@ -228,7 +228,7 @@ impl SQLReWrite for ColumnOptionDef {
 /// `ColumnOption`s are modifiers that follow a column definition in a `CREATE
 /// TABLE` statement.
 impl SQLReWrite for ColumnOption {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use ColumnOption::*;
         match self {
             Null => {
@@ -291,7 +291,7 @@ impl SQLReWrite for ColumnOption {
 fn display_constraint_name<'a>(name: &'a Option<Ident>) -> impl SQLReWrite + 'a {
     struct ConstraintName<'a>(&'a Option<Ident>);
     impl<'a> SQLReWrite for ConstraintName<'a> {
-        fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+        fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
             if let Some(name) = self.0 {
                 write!(f, "CONSTRAINT {} ", name)?;
             }
@@ -302,7 +302,7 @@ fn display_constraint_name<'a>(name: &'a Option<Ident>) -> impl SQLReWrite + 'a
 }
 
 impl SQLReWrite for ReferentialAction {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             ReferentialAction::Restrict => "RESTRICT",
             ReferentialAction::Cascade => "CASCADE",
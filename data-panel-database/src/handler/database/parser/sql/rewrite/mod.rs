@@ -12,12 +12,13 @@
 
 //! SQL Abstract Syntax Tree (AST) types
 
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::{AddDropSync, Assignment, Expr, FileFormat, Function, FunctionArg, HiveDistributionStyle, HiveFormat, HiveIOFormat, HiveRowFormat, Ident, ListAgg, ListAggOnOverflow, ObjectName, ObjectType, SetVariableValue, ShowStatementFilter, SqliteOnConflict, SqlOption, Statement, TransactionAccessMode, TransactionIsolationLevel, TransactionMode, UnaryOperator, WindowFrameBound, WindowFrameUnits, WindowSpec};
 use sqlparser::tokenizer::{Token, Whitespace, Word};
 
+use crate::handler::database::parser::sql::SQLRewriteContext;
+
 mod data_type;
 mod ddl;
 mod operator;
@@ -27,7 +28,7 @@ mod value;
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 pub trait SQLReWrite {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult;
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult;
 }
 
 struct DisplaySeparated<'a, T>
@@ -42,7 +43,7 @@ impl<'a, T> SQLReWrite for DisplaySeparated<'a, T>
     where
         T: SQLReWrite,
 {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         let mut delim = "";
         for t in self.slice {
             write!(f, "{}", delim)?;
@@ -68,26 +69,74 @@ fn display_comma_separated<T>(slice: &[T]) -> DisplaySeparated<'_, T>
 }
 
 impl SQLReWrite for Ident {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
-        match self.quote_style {
-            Some(q) if q == '"' || q == '\'' || q == '`' => write!(f, "{}{}{}", q, self.value, q)?,
-            Some(q) if q == '[' => write!(f, "[{}]", self.value)?,
-            None => f.write_str(&self.value)?,
-            _ => panic!("unexpected quote style"),
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
+        write_ident(f, &self.value, self.quote_style)
+    }
+}
+
+fn write_ident(f: &mut String, value: &str, quote_style: Option<char>) -> SRWResult {
+    match quote_style {
+        Some(q) if q == '"' || q == '\'' || q == '`' => write!(f, "{}{}{}", q, value, q)?,
+        Some(q) if q == '[' => write!(f, "[{}]", value)?,
+        None => f.write_str(value)?,
+        _ => panic!("unexpected quote style"),
+    }
+    Ok(())
+}
+
+/// The `ctx` of a rewrite maps logical table names to the physical names on the
+/// target segment, either `table` or `database.table`.
+fn physical_table_name<'a>(ctx: &'a SQLRewriteContext, table: &str) -> Option<(Option<&'a str>, &'a str)> {
+    let physical = ctx.get_table_mapping(table)?;
+    match physical.rfind('.') {
+        Some(i) => Some((Some(&physical[..i]), &physical[i + 1..])),
+        None => Some((None, physical.as_str())),
+    }
+}
+
+/// Write a dotted name whose part at `table_index` may be a logical table name, e.g.
+/// `db.t_order` or the qualifier of `t_order.user_id`. A mapped table is replaced by its
+/// physical name, the database by the physical database if there is one, and the
+/// quote style of the original parts is kept.
+fn rewrite_qualified_name(f: &mut String, idents: &[Ident], table_index: usize, ctx: &SQLRewriteContext) -> SRWResult {
+    let table = &idents[table_index];
+    let (database, physical_table) = match physical_table_name(ctx, &table.value) {
+        Some(physical) => physical,
+        None => return display_separated(idents, ".").rewrite(f, ctx),
+    };
+    match database {
+        Some(database) => {
+            let quote_style = if table_index > 0 { idents[0].quote_style } else { table.quote_style };
+            write_ident(f, database, quote_style)?;
+            write!(f, ".")?;
+        }
+        None => {
+            for ident in &idents[..table_index] {
+                ident.rewrite(f, ctx)?;
+                write!(f, ".")?;
+            }
         }
-        Ok(())
     }
+    write_ident(f, physical_table, table.quote_style)?;
+    for ident in &idents[table_index + 1..] {
+        write!(f, ".")?;
+        ident.rewrite(f, ctx)?;
+    }
+    Ok(())
 }
 
 impl SQLReWrite for ObjectName {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
-        display_separated(&self.0, ".").rewrite(f, ctx)?;
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        rewrite_qualified_name(f, &self.0, self.0.len() - 1, ctx)?;
         Ok(())
     }
 }
 
 impl SQLReWrite for String {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(&self)?;
         Ok(())
     }
@@ -99,7 +148,7 @@ impl SQLReWrite for String {
 /// (e.g. boolean vs string), so the caller must handle expressions of
 /// inappropriate type, like `WHERE 1` or `SELECT 1=1`, as necessary.
 impl SQLReWrite for Expr {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             Expr::Identifier(s) => {
                 s.rewrite(f, ctx)?;
@@ -112,11 +161,19 @@ impl SQLReWrite for Expr {
                 f.write_str("*")?;
             }
             Expr::QualifiedWildcard(q) => {
-                display_separated(q, ".").rewrite(f, ctx)?;
+                if q.is_empty() {
+                    display_separated(q, ".").rewrite(f, ctx)?;
+                } else {
+                    rewrite_qualified_name(f, q, q.len() - 1, ctx)?;
+                }
                 write!(f, ".*")?;
             }
             Expr::CompoundIdentifier(s) => {
-                display_separated(s, ".").rewrite(f, ctx)?;
+                if s.len() < 2 {
+                    display_separated(s, ".").rewrite(f, ctx)?;
+                } else {
+                    rewrite_qualified_name(f, s, s.len() - 2, ctx)?;
+                }
             }
             Expr::IsNull(ast) => {
                 ast.rewrite(f, ctx)?;
@@ -294,7 +351,7 @@ impl SQLReWrite for Expr {
 
 /// A window specification (i.e. `OVER (PARTITION BY .. ORDER BY .. etc.)`)
 impl SQLReWrite for WindowSpec {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         let mut delim = "";
         if !self.partition_by.is_empty() {
             delim = " ";
@@ -341,7 +398,7 @@ impl SQLReWrite for WindowSpec {
 /// Note: The parser does not validate the specified bounds; the caller should
 /// reject invalid bounds like `ROWS UNBOUNDED FOLLOWING` before execution.
 impl SQLReWrite for WindowFrameUnits {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             WindowFrameUnits::Rows => "ROWS",
             WindowFrameUnits::Range => "RANGE",
@@ -353,7 +410,7 @@ impl SQLReWrite for WindowFrameUnits {
 
 /// Specifies [WindowFrame]'s `start_bound` and `end_bound`
 impl SQLReWrite for WindowFrameBound {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             WindowFrameBound::CurrentRow => f.write_str("CURRENT ROW"),
             WindowFrameBound::Preceding(None) => f.write_str("UNBOUNDED PRECEDING"),
@@ -366,7 +423,7 @@ impl SQLReWrite for WindowFrameBound {
 }
 
 impl SQLReWrite for AddDropSync {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             AddDropSync::SYNC => f.write_str("SYNC PARTITIONS"),
             AddDropSync::DROP => f.write_str("DROP PARTITIONS"),
@@ -381,7 +438,7 @@ impl SQLReWrite for Statement {
     // Clippy thinks this function is too complicated, but it is painful to
     // split up without extracting structs for each `Statement` variant.
     #[allow(clippy::cognitive_complexity)]
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             Statement::Explain {
                 verbose,
@@ -1031,7 +1088,7 @@ impl SQLReWrite for Statement {
 
 /// SQL assignment `foo = expr` as used in SQLUpdate
 impl SQLReWrite for Assignment {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.id.rewrite(f, ctx)?;
         write!(f, " = ")?;
         self.value.rewrite(f, ctx)
@@ -1039,7 +1096,7 @@ impl SQLReWrite for Assignment {
 }
 
 impl SQLReWrite for FunctionArg {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             FunctionArg::Named { name, arg } => {
                 name.rewrite(f, ctx)?;
@@ -1056,7 +1113,7 @@ impl SQLReWrite for FunctionArg {
 
 /// A function call
 impl SQLReWrite for Function {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.name.rewrite(f, ctx)?;
         write!(
             f,
@@ -1079,7 +1136,7 @@ impl SQLReWrite for Function {
 
 /// External table's available file format
 impl SQLReWrite for FileFormat {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use self::FileFormat::*;
         f.write_str(match self {
             TEXTFILE => "TEXTFILE",
@@ -1095,7 +1152,7 @@ impl SQLReWrite for FileFormat {
 }
 
 impl SQLReWrite for ListAgg {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(
             f,
             "LISTAGG({}",
@@ -1126,7 +1183,7 @@ impl SQLReWrite for ListAgg {
 }
 
 impl SQLReWrite for ListAggOnOverflow {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(f, " ON OVERFLOW")?;
         match self {
             ListAggOnOverflow::Error => {
@@ -1151,7 +1208,7 @@ impl SQLReWrite for ListAggOnOverflow {
 }
 
 impl SQLReWrite for ObjectType {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             ObjectType::Table => "TABLE",
             ObjectType::View => "VIEW",
@@ -1163,7 +1220,7 @@ impl SQLReWrite for ObjectType {
 }
 
 impl SQLReWrite for SqlOption {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.name.rewrite(f, ctx)?;
         write!(f, " = ")?;
         self.value.rewrite(f, ctx)?;
@@ -1172,7 +1229,7 @@ impl SQLReWrite for SqlOption {
 }
 
 impl SQLReWrite for TransactionMode {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use TransactionMode::*;
         match self {
             AccessMode(access_mode) => {
@@ -1188,7 +1245,7 @@ impl SQLReWrite for TransactionMode {
 }
 
 impl SQLReWrite for TransactionAccessMode {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use TransactionAccessMode::*;
         f.write_str(match self {
             ReadOnly => "READ ONLY",
@@ -1199,7 +1256,7 @@ impl SQLReWrite for TransactionAccessMode {
 }
 
 impl SQLReWrite for TransactionIsolationLevel {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use TransactionIsolationLevel::*;
         f.write_str(match self {
             ReadUncommitted => "READ UNCOMMITTED",
@@ -1212,7 +1269,7 @@ impl SQLReWrite for TransactionIsolationLevel {
 }
 
 impl SQLReWrite for ShowStatementFilter {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use ShowStatementFilter::*;
         match self {
             Like(pattern) => {
@@ -1230,7 +1287,7 @@ impl SQLReWrite for ShowStatementFilter {
 }
 
 impl SQLReWrite for SetVariableValue {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use SetVariableValue::*;
         match self {
             Ident(ident) => {
@@ -1245,7 +1302,7 @@ impl SQLReWrite for SetVariableValue {
 }
 
 impl SQLReWrite for SqliteOnConflict {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         use SqliteOnConflict::*;
         match self {
             Rollback => write!(f, "ROLLBACK"),
@@ -1259,7 +1316,7 @@ impl SQLReWrite for SqliteOnConflict {
 }
 
 impl SQLReWrite for Token {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             Token::EOF => f.write_str("EOF")?,
             Token::Word(ref w) => {
@@ -1318,7 +1375,7 @@ impl SQLReWrite for Token {
 }
 
 impl SQLReWrite for Word {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self.quote_style {
             Some(s) if s == '"' || s == '[' || s == '`' => {
                 write!(f, "{}{}{}", s, self.value, matching_end_quote(s))?
@@ -1340,7 +1397,7 @@ fn matching_end_quote(ch: char) -> char {
 }
 
 impl SQLReWrite for Whitespace {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             Whitespace::Space => f.write_str(" ")?,
             Whitespace::Newline => f.write_str("\n")?,
@@ -1358,6 +1415,7 @@ mod tests {
 
     use crate::handler::database::parser::sql::mysql::parser;
     use crate::handler::database::parser::sql::rewrite::SQLReWrite;
+    use crate::handler::database::parser::sql::SQLRewriteContext;
 
     #[test]
     fn test_rewrite() {
@@ -1371,8 +1429,35 @@ mod tests {
         let mut ast = parser(sql.to_string());
         let stmt = ast.pop().unwrap();
         let mut resql = String::new();
-        let mut ctx: HashMap<String, String> = HashMap::new();
+        let ctx = SQLRewriteContext::new(HashMap::new());
         stmt.rewrite(&mut resql, &ctx).unwrap();
         assert_eq!(sql.to_uppercase(), resql.to_uppercase());
     }
+
+    #[test]
+    fn test_rewrite_table_names() {
+        let mut table_mappings: HashMap<String, String> = HashMap::new();
+        table_mappings.insert(String::from("t_order"), String::from("t_order_3"));
+        table_mappings.insert(String::from("t_order_item"), String::from("martlet_1.t_order_item_3"));
+        let ctx = SQLRewriteContext::new(table_mappings);
+
+        let cases = vec![
+            ("SELECT t_order.user_id, t_order.* FROM t_order WHERE t_order.status = 'PAID'",
+             "SELECT t_order_3.user_id, t_order_3.* FROM t_order_3 WHERE t_order_3.status = 'PAID'"),
+            ("SELECT o.user_id FROM `martlet`.`t_order` AS o JOIN t_order_item AS i ON o.order_id = i.order_id",
+             "SELECT o.user_id FROM `martlet`.`t_order_3` AS o JOIN martlet_1.t_order_item_3 AS i ON o.order_id = i.order_id"),
+            ("SELECT `t_order_item`.order_id FROM `t_order_item`",
+             "SELECT `martlet_1`.`t_order_item_3`.order_id FROM `martlet_1`.`t_order_item_3`"),
+            ("UPDATE t_order SET status = 'PAID' WHERE user_id = 1",
+             "UPDATE t_order_3 SET status = 'PAID' WHERE user_id = 1"),
+            ("SELECT count(*) FROM t_user WHERE t_user.id = 1",
+             "SELECT count(*) FROM t_user WHERE t_user.id = 1"),
+        ];
+        for (sql, expected) in cases {
+            let stmt = parser(sql.to_string()).pop().unwrap();
+            let mut resql = String::new();
+            stmt.rewrite(&mut resql, &ctx).unwrap();
+            assert_eq!(expected.to_uppercase(), resql.to_uppercase());
+        }
+    }
 }
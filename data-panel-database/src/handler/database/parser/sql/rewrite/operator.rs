@@ -10,18 +10,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::{BinaryOperator, UnaryOperator};
 
 use crate::handler::database::parser::sql::rewrite::SQLReWrite;
+use crate::handler::database::parser::sql::SQLRewriteContext;
 
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 /// Unary operators
 impl SQLReWrite for UnaryOperator {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             UnaryOperator::Plus => "+",
             UnaryOperator::Minus => "-",
@@ -39,7 +39,7 @@ impl SQLReWrite for UnaryOperator {
 
 /// Binary operators
 impl SQLReWrite for BinaryOperator {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             BinaryOperator::Plus => "+",
             BinaryOperator::Minus => "-",
@@ -10,19 +10,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::{Cte, Fetch, Join, JoinConstraint, JoinOperator, Offset, OffsetRows, OrderByExpr, Query, Select, SelectItem, SetExpr, SetOperator, TableAlias, TableFactor, TableWithJoins, Top, Values, With};
 
 use crate::handler::database::parser::sql::rewrite::{display_comma_separated, SQLReWrite};
+use crate::handler::database::parser::sql::{PaginationRewrite, SQLRewriteContext};
 
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 /// The most complete variant of a `SELECT` query expression, optionally
 /// including `WITH`, `UNION` / other set operations, and `ORDER BY`.
 impl SQLReWrite for Query {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
+        ctx.enter_query();
         if let Some(ref with) = self.with {
             with.rewrite(f, ctx)?;
         }
@@ -31,18 +32,29 @@ impl SQLReWrite for Query {
             write!(f, " ORDER BY ")?;
             display_comma_separated(&self.order_by).rewrite(f, ctx)?;
         }
-        if let Some(ref limit) = self.limit {
-            write!(f, " LIMIT ")?;
-            limit.rewrite(f, ctx)?;
-        }
-        if let Some(ref offset) = self.offset {
-            write!(f, " ")?;
-            offset.rewrite(f, ctx)?;
+        let pagination = if ctx.is_outermost_query() { ctx.get_pagination() } else { PaginationRewrite::Keep };
+        match pagination {
+            // Every segment returns the first `offset + limit` rows, the merge skips `offset` of all of them.
+            PaginationRewrite::RowCount(row_count) => {
+                write!(f, " LIMIT {}", row_count)?;
+            }
+            PaginationRewrite::Remove => {}
+            PaginationRewrite::Keep => {
+                if let Some(ref limit) = self.limit {
+                    write!(f, " LIMIT ")?;
+                    limit.rewrite(f, ctx)?;
+                }
+                if let Some(ref offset) = self.offset {
+                    write!(f, " ")?;
+                    offset.rewrite(f, ctx)?;
+                }
+            }
         }
         if let Some(ref fetch) = self.fetch {
             write!(f, " ")?;
             fetch.rewrite(f, ctx)?;
         }
+        ctx.exit_query();
         Ok(())
     }
 }
@@ -50,7 +62,7 @@ impl SQLReWrite for Query {
 /// A node in a tree, representing a "query body" expression, roughly:
 /// `SELECT ... [ {UNION|EXCEPT|INTERSECT} SELECT ...]`
 impl SQLReWrite for SetExpr {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             SetExpr::Select(s) => {
                 s.rewrite(f, ctx)?;
@@ -84,7 +96,7 @@ impl SQLReWrite for SetExpr {
 }
 
 impl SQLReWrite for SetOperator {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             SetOperator::Union => "UNION",
             SetOperator::Except => "EXCEPT",
@@ -98,7 +110,7 @@ impl SQLReWrite for SetOperator {
 /// appear either as the only body item of an `SQLQuery`, or as an operand
 /// to a set operation like `UNION`.
 impl SQLReWrite for Select {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(f, "SELECT{}", if self.distinct { " DISTINCT" } else { "" })?;
         if let Some(ref top) = self.top {
             write!(f, " ")?;
@@ -106,6 +118,14 @@ impl SQLReWrite for Select {
         }
         write!(f, " ")?;
         display_comma_separated(&self.projection).rewrite(f, ctx)?;
+        let outermost_query = ctx.is_outermost_query();
+        if outermost_query {
+            for derived_column in ctx.get_derived_columns() {
+                write!(f, ", ")?;
+                derived_column.get_expr().rewrite(f, ctx)?;
+                write!(f, " AS {}", derived_column.get_alias())?;
+            }
+        }
         if !self.from.is_empty() {
             write!(f, " FROM ")?;
             display_comma_separated(&self.from).rewrite(f, ctx)?;
@@ -114,20 +134,29 @@ impl SQLReWrite for Select {
             write!(f, " WHERE ")?;
             selection.rewrite(f, ctx)?;
         }
-        if !self.group_by.is_empty() {
+        let derived_group_by = if outermost_query { ctx.get_derived_group_by() } else { &[] };
+        if !self.group_by.is_empty() || !derived_group_by.is_empty() {
             write!(f, " GROUP BY ")?;
             display_comma_separated(&self.group_by).rewrite(f, ctx)?;
+            for (index, expr) in derived_group_by.iter().enumerate() {
+                if index > 0 || !self.group_by.is_empty() {
+                    write!(f, ", ")?;
+                }
+                expr.rewrite(f, ctx)?;
+            }
         }
         if let Some(ref having) = self.having {
-            write!(f, " HAVING ")?;
-            having.rewrite(f, ctx)?;
+            if !(outermost_query && ctx.is_remove_having()) {
+                write!(f, " HAVING ")?;
+                having.rewrite(f, ctx)?;
+            }
         }
         Ok(())
     }
 }
 
 impl SQLReWrite for With {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(
             f,
             "WITH {}",
@@ -143,7 +172,7 @@ impl SQLReWrite for With {
 /// of the columns returned by the query. The parser does not validate that the
 /// number of columns in the query matches the number of columns in the query.
 impl SQLReWrite for Cte {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.alias.rewrite(f, ctx)?;
         write!(f, " AS (")?;
         self.query.rewrite(f, ctx)?;
@@ -154,7 +183,7 @@ impl SQLReWrite for Cte {
 
 /// One item of the comma-separated list following `SELECT`
 impl SQLReWrite for SelectItem {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match &self {
             SelectItem::UnnamedExpr(expr) => {
                 expr.rewrite(f, ctx)?;
@@ -177,7 +206,7 @@ impl SQLReWrite for SelectItem {
 }
 
 impl SQLReWrite for TableWithJoins {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.relation.rewrite(f, ctx)?;
         for join in &self.joins {
             join.rewrite(f, ctx)?;
@@ -188,7 +217,7 @@ impl SQLReWrite for TableWithJoins {
 
 /// A table name or a parenthesized subquery with an optional alias
 impl SQLReWrite for TableFactor {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             TableFactor::Table {
                 name,
@@ -251,7 +280,7 @@ impl SQLReWrite for TableFactor {
 }
 
 impl SQLReWrite for TableAlias {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.name.rewrite(f, ctx)?;
         if !self.columns.is_empty() {
             write!(f, " (")?;
@@ -263,7 +292,7 @@ impl SQLReWrite for TableAlias {
 }
 
 impl SQLReWrite for Join {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         fn prefix(constraint: &JoinConstraint) -> &'static str {
             match constraint {
                 JoinConstraint::Natural => "NATURAL ",
@@ -273,7 +302,7 @@ impl SQLReWrite for Join {
         fn suffix<'a>(constraint: &'a JoinConstraint) -> impl SQLReWrite + 'a {
             struct Suffix<'a>(&'a JoinConstraint);
             impl<'a> SQLReWrite for Suffix<'a> {
-                fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+                fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
                     match self.0 {
                         JoinConstraint::On(expr) => {
                             write!(f, " ON ")?;
@@ -347,7 +376,7 @@ impl SQLReWrite for Join {
 }
 
 impl SQLReWrite for OrderByExpr {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         self.expr.rewrite(f, ctx)?;
         match self.asc {
             Some(true) => write!(f, " ASC")?,
@@ -364,7 +393,7 @@ impl SQLReWrite for OrderByExpr {
 }
 
 impl SQLReWrite for Offset {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(f, "OFFSET ")?;
         self.value.rewrite(f, ctx)?;
         self.rows.rewrite(f, ctx)?;
@@ -373,7 +402,7 @@ impl SQLReWrite for Offset {
 }
 
 impl SQLReWrite for OffsetRows {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             OffsetRows::None => write!(f, "")?,
             OffsetRows::Row => write!(f, " ROW")?,
@@ -384,7 +413,7 @@ impl SQLReWrite for OffsetRows {
 }
 
 impl SQLReWrite for Fetch {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         let extension = if self.with_ties { "WITH TIES" } else { "ONLY" };
         if let Some(ref quantity) = self.quantity {
             let percent = if self.percent { " PERCENT" } else { "" };
@@ -399,7 +428,7 @@ impl SQLReWrite for Fetch {
 }
 
 impl SQLReWrite for Top {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         let extension = if self.with_ties { " WITH TIES" } else { "" };
         if let Some(ref quantity) = self.quantity {
             let percent = if self.percent { " PERCENT" } else { "" };
@@ -414,10 +443,16 @@ impl SQLReWrite for Top {
 }
 
 impl SQLReWrite for Values {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         write!(f, "VALUES ")?;
+        let insert_rows = if ctx.is_outermost_query() { ctx.get_insert_rows() } else { None };
         let mut delim = "";
-        for row in &self.0 {
+        for (index, row) in self.0.iter().enumerate() {
+            if let Some(insert_rows) = insert_rows {
+                if !insert_rows.contains(&index) {
+                    continue;
+                }
+            }
             write!(f, "{}", delim)?;
             delim = ", ";
             write!(f, "(")?;
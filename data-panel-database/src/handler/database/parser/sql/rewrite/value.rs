@@ -10,7 +10,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::fmt::Write;
 
 use sqlparser::ast::{DateTimeField, Value};
@@ -19,12 +18,13 @@ use sqlparser::ast::{DateTimeField, Value};
 use bigdecimal::BigDecimal;
 
 use crate::handler::database::parser::sql::rewrite::SQLReWrite;
+use crate::handler::database::parser::sql::SQLRewriteContext;
 
 pub type SRWResult = data_panel_common::common::Result<()>;
 
 /// Primitive SQL values such as number and string
 impl SQLReWrite for Value {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         match self {
             Value::Number(v, l) => {
                 write!(f, "{}{long}", v, long = if *l { "L" } else { "" })?;
@@ -98,7 +98,7 @@ impl SQLReWrite for Value {
 }
 
 impl SQLReWrite for DateTimeField {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         f.write_str(match self {
             DateTimeField::Year => "YEAR",
             DateTimeField::Month => "MONTH",
@@ -114,7 +114,7 @@ impl SQLReWrite for DateTimeField {
 pub struct EscapeSingleQuoteString<'a>(&'a str);
 
 impl<'a> SQLReWrite for EscapeSingleQuoteString<'a> {
-    fn rewrite(&self, f: &mut String, ctx: &HashMap<String, String>) -> SRWResult {
+    fn rewrite(&self, f: &mut String, ctx: &SQLRewriteContext) -> SRWResult {
         for c in self.0.chars() {
             if c == '\'' {
                 write!(f, "\'\'")?;
@@ -0,0 +1,620 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use dashmap::DashMap;
+use rhai::{AST, Dynamic, Engine, FLOAT, ImmutableString, INT, RegisterFn, Scope};
+use sqlparser::ast::Value;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{DisAlgorithm, DisRange, DisRules, DisTable, DisType};
+use crate::handler::database::mysql::keygen;
+use crate::handler::database::parser::sql::{ShardingCondition, ShardingValue};
+use crate::handler::database::parser::sql::route::{partition, RouteResult};
+use crate::handler::database::parser::sql::route::ring::{HashRing, ring_position};
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+
+lazy_static! {
+    static ref ENGINE: Engine = new_engine();
+    static ref AST_CACHE: DashMap<String, AST> = DashMap::new();
+}
+
+/// The engine every `dis_expression` runs in. Besides the rhai built-ins it knows
+/// `hash(v)` and the `Date` type, which string literals like `'2021-01-01'` turn into.
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.register_fn("hash", |s: ImmutableString| hash(s.as_str()));
+    engine.register_fn("hash", |i: INT| hash(i.to_string().as_str()));
+    engine.register_type_with_name::<NaiveDateTime>("Date");
+    engine.register_get("year", |d: &mut NaiveDateTime| d.year() as INT);
+    engine.register_get("month", |d: &mut NaiveDateTime| d.month() as INT);
+    engine.register_get("day", |d: &mut NaiveDateTime| d.day() as INT);
+    engine.register_get("hour", |d: &mut NaiveDateTime| d.hour() as INT);
+    engine.register_get("minute", |d: &mut NaiveDateTime| d.minute() as INT);
+    engine.register_get("second", |d: &mut NaiveDateTime| d.second() as INT);
+    engine.register_get("timestamp", |d: &mut NaiveDateTime| d.timestamp() as INT);
+    engine
+}
+
+/// FNV-1a over the text of the value, stable across processes unlike `DefaultHasher`,
+/// so `1` and `'1'` land on the same segment whichever protocol sent them.
+pub fn hash(s: &str) -> INT {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in s.as_bytes() {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    (h & INT::MAX as u64) as INT
+}
+
+fn compile(dis_expression: &str) -> RouteResult<()> {
+    if AST_CACHE.contains_key(dis_expression) {
+        return Ok(());
+    }
+    let ast = ENGINE.compile(dis_expression).map_err(|e| {
+        Error::General(format!("invalid dis_expression `{}`: {}", dis_expression, e))
+    })?;
+    AST_CACHE.insert(dis_expression.to_string(), ast);
+    Ok(())
+}
+
+/// Compile the expressions of all distributed tables and check their ranges, partitions,
+/// key generators and `dis_relatives`, so broken rules fail when the config loads.
+pub fn compile_dis_rules(dis_rules: &DisRules) -> RouteResult<()> {
+    for (table, dis_table) in dis_rules.get_distributed_tables() {
+        if dis_table.get_dis_algorithm().get_dis_ranges().is_empty() {
+            let dis_expression = dis_table.get_dis_algorithm().get_dis_expression();
+            if let Err(Error::General(message)) = compile(dis_expression) {
+                return Err(Error::General(format!("{}: {}", table, message)));
+            }
+        } else {
+            check_dis_ranges(table, dis_table)?;
+        }
+        if let Some(dis_partition) = dis_table.get_dis_partition() {
+            partition::check_dis_partition(table, dis_table, dis_partition)?;
+        }
+        if let Some(key_generator) = dis_table.get_key_generator() {
+            keygen::check_key_generator(table, key_generator)?;
+        }
+    }
+    for (table, dis_table) in dis_rules.get_distributed_tables() {
+        for relative in dis_table.get_dis_relatives() {
+            check_dis_relative(table, dis_table, relative, dis_rules)?;
+        }
+    }
+    Ok(())
+}
+
+/// Relatives are co-located: a dis key value has to choose the same segment for both,
+/// so they need the same dis type and the same expression up to the names of the keys.
+fn check_dis_relative(table: &str, dis_table: &DisTable, relative: &str, dis_rules: &DisRules) -> RouteResult<()> {
+    let relative_table = dis_rules.get_distributed_table(relative).ok_or_else(|| {
+        Error::General(format!("{}: dis relative {} is not a distributed table", table, relative))
+    })?;
+    let algorithm = dis_table.get_dis_algorithm();
+    let relative_algorithm = relative_table.get_dis_algorithm();
+    let keys = dis_table.get_dis_keys();
+    let relative_keys = relative_table.get_dis_keys();
+    let compatible = keys.len() == relative_keys.len()
+        && algorithm.get_dis_type() == relative_algorithm.get_dis_type()
+        && algorithm.get_dis_ranges() == relative_algorithm.get_dis_ranges()
+        && algorithm.get_dis_ring() == relative_algorithm.get_dis_ring()
+        && rename_keys(relative_algorithm.get_dis_expression(), relative_keys, keys) == rename_keys(algorithm.get_dis_expression(), keys, keys);
+    if !compatible {
+        return Err(Error::General(format!(
+            "{}: dis relative {} must have as many dis keys, the same dis type, the same dis expression and the same dis ring",
+            table, relative)));
+    }
+    Ok(())
+}
+
+/// Ranges are for a single dis key of a RANGE table, in ascending order, and only the last
+/// one may be open-ended.
+fn check_dis_ranges(table: &str, dis_table: &DisTable) -> RouteResult<()> {
+    let algorithm = dis_table.get_dis_algorithm();
+    if *algorithm.get_dis_type() != DisType::RANGE || dis_table.get_dis_keys().len() != 1 {
+        return Err(Error::General(format!("{}: dis_ranges need dis_type RANGE and one dis key", table)));
+    }
+    let dis_ranges = algorithm.get_dis_ranges();
+    let mut previous: Option<Dynamic> = None;
+    for (index, dis_range) in dis_ranges.iter().enumerate() {
+        let upper = match dis_range.get_upper() {
+            Some(upper) => range_bound(upper),
+            None if index == dis_ranges.len() - 1 => break,
+            None => return Err(Error::General(format!("{}: only the last of the dis_ranges may leave out upper", table))),
+        };
+        if let Some(previous) = previous {
+            if compare_dynamic(&previous, &upper) != Some(Ordering::Less) {
+                return Err(Error::General(format!("{}: the upper bounds of dis_ranges must ascend", table)));
+            }
+        }
+        previous = Some(upper);
+    }
+    Ok(())
+}
+
+/// The expression with every identifier among `from` replaced by the key at the same position
+/// in `to`, and the whitespace removed.
+fn rename_keys(dis_expression: &str, from: &[String], to: &[String]) -> String {
+    let mut renamed = String::with_capacity(dis_expression.len());
+    let mut identifier = String::new();
+    let flush = |identifier: &mut String, renamed: &mut String| {
+        match from.iter().position(|key| key == identifier) {
+            Some(index) => renamed.push_str(to[index].as_str()),
+            None => renamed.push_str(identifier.as_str()),
+        }
+        identifier.clear();
+    };
+    for c in dis_expression.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            identifier.push(c);
+            continue;
+        }
+        flush(&mut identifier, &mut renamed);
+        if !c.is_whitespace() {
+            renamed.push(c);
+        }
+    }
+    flush(&mut identifier, &mut renamed);
+    renamed
+}
+
+/// Evaluate the expression with the dis key values in scope and map the result to a data segment.
+/// HASH results pick a segment by modulo over the ordered segment ids, or on the ring of
+/// `dis_ring` when it is set. RANGE and CUSTOM results must be a segment id. With
+/// `dis_ranges` the range holding the value decides.
+pub fn eval_segment(dis_algorithm: &DisAlgorithm,
+                    values: &[(&str, Dynamic)],
+                    segment_ids: &[u32]) -> RouteResult<u32> {
+    let dis_ranges = dis_algorithm.get_dis_ranges();
+    if !dis_ranges.is_empty() {
+        let value = values.first().map(|(_, value)| value).ok_or_else(|| {
+            Error::General(String::from("dis_ranges need the value of the dis key"))
+        })?;
+        let segment_id = range_segment(dis_ranges, value)?;
+        if !segment_ids.contains(&segment_id) {
+            return Err(Error::General(format!("dis range of {} is on unknown data segment {}", value, segment_id)));
+        }
+        return Ok(segment_id);
+    }
+    let dis_expression = dis_algorithm.get_dis_expression().as_str();
+    let result = eval_expression(dis_expression, values)?;
+
+    if segment_ids.is_empty() {
+        return Err(Error::General(String::from("no data segment configured")));
+    }
+    match dis_algorithm.get_dis_type() {
+        DisType::HASH => match dis_algorithm.get_dis_ring() {
+            Some(dis_ring) => HashRing::cached(dis_ring, segment_ids).segment(ring_position(result)).ok_or_else(|| {
+                Error::General(String::from("dis_ring has no points, the data segments have weight 0"))
+            }),
+            None => Ok(segment_ids[result.rem_euclid(segment_ids.len() as INT) as usize]),
+        },
+        DisType::RANGE | DisType::CUSTOM => {
+            segment_ids.iter()
+                .find(|id| **id as INT == result)
+                .copied()
+                .ok_or_else(|| {
+                    Error::General(format!("dis_expression `{}` evaluated to unknown data segment {}", dis_expression, result))
+                })
+        }
+    }
+}
+
+/// Evaluate the expression with the dis key values in scope to an integer.
+pub fn eval_expression(dis_expression: &str, values: &[(&str, Dynamic)]) -> RouteResult<INT> {
+    compile(dis_expression)?;
+    let ast = AST_CACHE.get(dis_expression).ok_or_else(|| {
+        Error::General(format!("dis_expression `{}` is not compiled", dis_expression))
+    })?;
+
+    let mut scope = Scope::new();
+    for (name, value) in values {
+        scope.push_dynamic(name.to_string(), value.clone());
+    }
+    let result = ENGINE.eval_ast_with_scope::<Dynamic>(&mut scope, &ast).map_err(|e| {
+        Error::General(format!("failed to evaluate dis_expression `{}`: {}", dis_expression, e))
+    })?;
+    result.try_cast::<INT>().ok_or_else(|| {
+        Error::General(format!("dis_expression `{}` must evaluate to an integer", dis_expression))
+    })
+}
+
+/// Data segments holding the rows of the table which satisfy the conditions on its dis keys,
+/// `None` when the conditions do not narrow the table down.
+pub fn sharding_segments(dis_table: &DisTable,
+                         conditions: &HashMap<String, Vec<ShardingCondition>>,
+                         parameters: &[PrepareParamValue],
+                         segment_ids: &[u32]) -> RouteResult<Option<Vec<u32>>> {
+    let dis_keys = dis_table.get_dis_keys();
+    if dis_keys.is_empty() {
+        return Ok(None);
+    }
+
+    let mut key_values: Vec<(&str, Vec<Dynamic>)> = Vec::with_capacity(dis_keys.len());
+    for dis_key in dis_keys {
+        // Every condition holds, so the one with the fewest values is enough.
+        let values = conditions.get(dis_key).into_iter()
+            .flatten()
+            .filter_map(|condition| match condition {
+                ShardingCondition::Equal(values) => equal_values(values, parameters),
+                _ => None,
+            })
+            .min_by_key(|values| values.len());
+        match values {
+            Some(values) => key_values.push((dis_key.as_str(), values)),
+            None if dis_keys.len() == 1 => return range_segments(dis_table, dis_key, conditions, parameters, segment_ids),
+            None => return Ok(None),
+        }
+    }
+
+    let mut combinations: Vec<Vec<(&str, Dynamic)>> = vec![vec![]];
+    for (dis_key, values) in key_values {
+        let mut next_combinations = Vec::with_capacity(combinations.len() * values.len());
+        for combination in combinations.iter() {
+            for value in values.iter() {
+                let mut next_combination = combination.clone();
+                next_combination.push((dis_key, value.clone()));
+                next_combinations.push(next_combination);
+            }
+        }
+        combinations = next_combinations;
+    }
+
+    let mut segments = vec![];
+    for combination in combinations {
+        let segment_id = eval_segment(dis_table.get_dis_algorithm(), &combination, segment_ids)?;
+        if !segments.contains(&segment_id) {
+            segments.push(segment_id);
+        }
+    }
+    segments.sort();
+    Ok(Some(segments))
+}
+
+/// Segments holding the values between the bounds of the range conditions on the dis key:
+/// those of the `dis_ranges` the bounds overlap, or, since a RANGE expression maps ascending
+/// keys to ascending segment ids, the segments between the ones of both bounds.
+fn range_segments(dis_table: &DisTable,
+                  dis_key: &str,
+                  conditions: &HashMap<String, Vec<ShardingCondition>>,
+                  parameters: &[PrepareParamValue],
+                  segment_ids: &[u32]) -> RouteResult<Option<Vec<u32>>> {
+    let dis_algorithm = dis_table.get_dis_algorithm();
+    if *dis_algorithm.get_dis_type() != DisType::RANGE {
+        return Ok(None);
+    }
+    let (low, high) = range_bounds(conditions.get(dis_key), parameters);
+    if !dis_algorithm.get_dis_ranges().is_empty() {
+        if low.is_none() && high.is_none() {
+            return Ok(None);
+        }
+        return Ok(Some(segments_between(dis_algorithm, dis_key, low.as_ref(), high.as_ref(), segment_ids)?));
+    }
+    match (low, high) {
+        (Some(low), Some(high)) => Ok(Some(segments_between(dis_algorithm, dis_key, Some(&low), Some(&high), segment_ids)?)),
+        _ => Ok(None),
+    }
+}
+
+/// A bound of a range condition: the value and whether the value itself matches.
+pub type Bound = (Dynamic, bool);
+
+/// The tightest lower and upper bounds of the range conditions, `None` for an open side.
+pub fn range_bounds(conditions: Option<&Vec<ShardingCondition>>,
+                    parameters: &[PrepareParamValue]) -> (Option<Bound>, Option<Bound>) {
+    let mut low: Option<Bound> = None;
+    let mut high: Option<Bound> = None;
+    for condition in conditions.into_iter().flatten() {
+        let value = |value: &ShardingValue, inclusive: bool| sharding_value(value, parameters).map(|v| (v, inclusive));
+        let (condition_low, condition_high) = match condition {
+            ShardingCondition::Range(l, h) => (value(l, true), value(h, true)),
+            ShardingCondition::GreaterThan(v, inclusive) => (value(v, *inclusive), None),
+            ShardingCondition::LessThan(v, inclusive) => (None, value(v, *inclusive)),
+            ShardingCondition::Equal(_) => (None, None),
+        };
+        if let Some(condition_low) = condition_low {
+            if low.as_ref().map(|low| is_tighter(&condition_low, low, Ordering::Greater)).unwrap_or(true) {
+                low = Some(condition_low);
+            }
+        }
+        if let Some(condition_high) = condition_high {
+            if high.as_ref().map(|high| is_tighter(&condition_high, high, Ordering::Less)).unwrap_or(true) {
+                high = Some(condition_high);
+            }
+        }
+    }
+    (low, high)
+}
+
+/// Whether `bound` leaves out more values than `other`, lower bounds are tighter when
+/// greater, upper bounds when less.
+pub fn is_tighter(bound: &Bound, other: &Bound, tighter: Ordering) -> bool {
+    match compare_dynamic(&bound.0, &other.0) {
+        Some(Ordering::Equal) => !bound.1 && other.1,
+        Some(ordering) => ordering == tighter,
+        None => false,
+    }
+}
+
+/// Segments which may hold values between the bounds, both are needed for a RANGE expression.
+pub fn segments_between(dis_algorithm: &DisAlgorithm,
+                        dis_key: &str,
+                        low: Option<&Bound>,
+                        high: Option<&Bound>,
+                        segment_ids: &[u32]) -> RouteResult<Vec<u32>> {
+    let dis_ranges = dis_algorithm.get_dis_ranges();
+    if !dis_ranges.is_empty() {
+        return Ok(ranges_between(dis_ranges, low, high).into_iter()
+            .filter(|id| segment_ids.contains(id))
+            .collect());
+    }
+    match (dis_algorithm.get_dis_type(), low, high) {
+        (DisType::RANGE, Some(low), Some(high)) => {
+            let low = eval_segment(dis_algorithm, &[(dis_key, low.0.clone())], segment_ids)?;
+            let high = eval_segment(dis_algorithm, &[(dis_key, high.0.clone())], segment_ids)?;
+            Ok(segment_ids.iter()
+                .filter(|id| **id >= low && **id <= high)
+                .copied()
+                .collect())
+        }
+        _ => Ok(segment_ids.to_vec()),
+    }
+}
+
+/// The segment of the range holding the value.
+fn range_segment(dis_ranges: &[DisRange], value: &Dynamic) -> RouteResult<u32> {
+    for dis_range in dis_ranges {
+        let upper = match dis_range.get_upper() {
+            Some(upper) => upper,
+            None => return Ok(dis_range.get_segment()),
+        };
+        match compare_dynamic(value, &range_bound(upper)) {
+            Some(Ordering::Less) => return Ok(dis_range.get_segment()),
+            Some(_) => continue,
+            None => return Err(Error::General(format!("{} can not be compared with the dis range bound {}", value, upper))),
+        }
+    }
+    Err(Error::General(format!("no dis range holds {}", value)))
+}
+
+/// Segments of the ranges overlapping the values between the bounds, in ascending order.
+fn ranges_between(dis_ranges: &[DisRange], low: Option<&Bound>, high: Option<&Bound>) -> Vec<u32> {
+    let mut segments = vec![];
+    let mut lower: Option<Dynamic> = None;
+    for dis_range in dis_ranges {
+        let upper = dis_range.get_upper().map(|upper| range_bound(upper));
+        let starts_below_high = match (&lower, high) {
+            (Some(lower), Some((high, inclusive))) => match compare_dynamic(lower, high) {
+                Some(Ordering::Equal) => *inclusive,
+                Some(ordering) => ordering == Ordering::Less,
+                None => true,
+            },
+            _ => true,
+        };
+        let ends_above_low = match (&upper, low) {
+            (Some(upper), Some((low, _))) => compare_dynamic(low, upper).map(|o| o == Ordering::Less).unwrap_or(true),
+            _ => true,
+        };
+        if starts_below_high && ends_above_low && !segments.contains(&dis_range.get_segment()) {
+            segments.push(dis_range.get_segment());
+        }
+        if upper.is_none() {
+            break;
+        }
+        lower = upper;
+    }
+    segments.sort();
+    segments
+}
+
+/// A bound of `dis_ranges` like the values it is compared with: dates, numbers or strings.
+fn range_bound(bound: &str) -> Dynamic {
+    let value = string_to_dynamic(bound);
+    if value.is::<NaiveDateTime>() {
+        return value;
+    }
+    match bound.parse::<INT>() {
+        Ok(i) => Dynamic::from(i),
+        Err(_) => match bound.parse::<FLOAT>() {
+            Ok(f) => Dynamic::from(f),
+            Err(_) => value,
+        },
+    }
+}
+
+/// Order of two dis key values of the same kind, `None` for values which can not be compared.
+pub fn compare_dynamic(a: &Dynamic, b: &Dynamic) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (a.clone().try_cast::<INT>(), b.clone().try_cast::<INT>()) {
+        return Some(a.cmp(&b));
+    }
+    let float = |d: &Dynamic| d.clone().try_cast::<FLOAT>().or_else(|| d.clone().try_cast::<INT>().map(|i| i as FLOAT));
+    if let (Some(a), Some(b)) = (float(a), float(b)) {
+        return a.partial_cmp(&b);
+    }
+    if let (Some(a), Some(b)) = (a.clone().try_cast::<NaiveDateTime>(), b.clone().try_cast::<NaiveDateTime>()) {
+        return Some(a.cmp(&b));
+    }
+    if let (Some(a), Some(b)) = (a.clone().try_cast::<String>(), b.clone().try_cast::<String>()) {
+        return Some(a.cmp(&b));
+    }
+    None
+}
+
+/// The values of an equality, NULL never matches and is left out.
+/// `None` if a parameter has no value, e.g. when routing without the execute parameters.
+pub fn equal_values(values: &[ShardingValue], parameters: &[PrepareParamValue]) -> Option<Vec<Dynamic>> {
+    let mut dynamics = Vec::with_capacity(values.len());
+    for value in values {
+        let value = match value {
+            ShardingValue::Literal(value) => to_dynamic(value),
+            ShardingValue::Parameter(index) => param_to_dynamic(parameters.get(*index)?),
+        };
+        if let Some(value) = value {
+            dynamics.push(value);
+        }
+    }
+    Some(dynamics)
+}
+
+fn sharding_value(value: &ShardingValue, parameters: &[PrepareParamValue]) -> Option<Dynamic> {
+    match value {
+        ShardingValue::Literal(value) => to_dynamic(value),
+        ShardingValue::Parameter(index) => param_to_dynamic(parameters.get(*index)?),
+    }
+}
+
+/// Convert a SQL literal into the value the expression sees: numbers become `INT` or
+/// `FLOAT`, date and datetime strings become `Date`, NULL becomes `None`.
+pub fn to_dynamic(value: &Value) -> Option<Dynamic> {
+    match value {
+        Value::Number(n, _) => {
+            let value = match n.parse::<INT>() {
+                Ok(i) => Dynamic::from(i),
+                Err(_) => match n.parse::<FLOAT>() {
+                    Ok(f) => Dynamic::from(f),
+                    Err(_) => Dynamic::from(n.clone()),
+                },
+            };
+            Some(value)
+        }
+        Value::SingleQuotedString(s) | Value::NationalStringLiteral(s) | Value::HexStringLiteral(s) => {
+            Some(string_to_dynamic(s))
+        }
+        Value::Boolean(b) => Some(Dynamic::from(*b)),
+        Value::Null => None,
+        _ => Some(Dynamic::from(value.to_string())),
+    }
+}
+
+/// Convert a prepared statement parameter like the literal it stands for, `None` for NULL.
+pub fn param_to_dynamic(value: &PrepareParamValue) -> Option<Dynamic> {
+    let value = match value {
+        PrepareParamValue::NULL => return None,
+        PrepareParamValue::Bytes(bytes) => string_to_dynamic(String::from_utf8_lossy(bytes).as_ref()),
+        PrepareParamValue::Int(i) => Dynamic::from(*i as INT),
+        PrepareParamValue::UInt(u) => Dynamic::from(*u as INT),
+        PrepareParamValue::Float(f) => Dynamic::from(*f as FLOAT),
+        PrepareParamValue::Double(f) => Dynamic::from(*f as FLOAT),
+        PrepareParamValue::Date(year, month, day, hour, minutes, seconds, micro_seconds) => {
+            let date_time = NaiveDate::from_ymd_opt(*year as i32, *month as u32, *day as u32)
+                .and_then(|d| d.and_hms_micro_opt(*hour as u32, *minutes as u32, *seconds as u32, *micro_seconds));
+            match date_time {
+                Some(date_time) => Dynamic::from(date_time),
+                None => return None,
+            }
+        }
+        PrepareParamValue::Time(is_negative, days, hours, minutes, seconds, _) => {
+            let seconds = ((*days as INT * 24 + *hours as INT) * 60 + *minutes as INT) * 60 + *seconds as INT;
+            Dynamic::from(if *is_negative { -seconds } else { seconds })
+        }
+    };
+    Some(value)
+}
+
+pub fn string_to_dynamic(s: &str) -> Dynamic {
+    let date_time = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|d| d.and_hms(0, 0, 0)));
+    match date_time {
+        Some(date_time) => Dynamic::from(date_time),
+        None => Dynamic::from(s.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rhai::{Dynamic, INT};
+
+    use data_panel_common::common::Error;
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::parser::sql::route::algorithm::{compile_dis_rules, eval_segment, string_to_dynamic};
+
+    /// A cluster of the data segments 100 and 200 with the (name, dis key, dis algorithm,
+    /// dis relatives) tables.
+    fn test_cluster(distributed_tables: &[(&str, &str, &str, &str)]) -> Cluster {
+        let tables: String = distributed_tables.iter()
+            .map(|(name, dis_key, dis_algorithm, dis_relatives)| format!(r#"
+    {}:
+      dis_keys: [ {} ]
+      dis_algorithm: {}
+      dis_relatives: [ {} ]"#, name, dis_key, dis_algorithm, dis_relatives))
+            .collect();
+        Cluster::from_str(&format!(r#"
+name: test
+segments:
+  meta_segment:
+    primary: {{ id: 0, url: "jdbc:mysql://localhost:3306/martlet", username: root, password: root }}
+    mirrors: [ ]
+  data_segments:
+    100:
+      primary: {{ id: 0, url: "jdbc:mysql://localhost:3306/martlet", username: root, password: root }}
+      mirrors: [ ]
+    200:
+      primary: {{ id: 0, url: "jdbc:mysql://localhost:3306/martlet", username: root, password: root }}
+      mirrors: [ ]
+dis_rules:
+  distributed_tables:{}
+  replicated_tables: [ ]
+"#, tables)).unwrap()
+    }
+
+    #[test]
+    fn test_eval_segment() {
+        let cluster = test_cluster(&[
+            ("t_hash", "id", r#"{ dis_type: HASH, dis_expression: "id" }"#, ""),
+            ("t_ring", "id", r#"{ dis_type: HASH, dis_expression: "id", dis_ring: { virtual_nodes: 16, weights: { 200: 0 } } }"#, ""),
+            ("t_range", "created", r#"{ dis_type: RANGE, dis_expression: "if created.year < 2021 { 100 } else { 200 }" }"#, ""),
+            ("t_custom", "code", r#"{ dis_type: CUSTOM, dis_expression: "if code == \"a\" { 100 } else { 300 }" }"#, ""),
+        ]);
+        compile_dis_rules(cluster.get_dis_rules()).unwrap();
+        let segment_ids = cluster.get_segments().get_data_segment_ids();
+        let dis_rules = cluster.get_dis_rules();
+        let algorithm = |t: &str| dis_rules.get_distributed_table(t).unwrap().get_dis_algorithm();
+
+        assert_eq!(100, eval_segment(algorithm("t_hash"), &[("id", Dynamic::from(4 as INT))], &segment_ids).unwrap());
+        assert_eq!(200, eval_segment(algorithm("t_hash"), &[("id", Dynamic::from(-3 as INT))], &segment_ids).unwrap());
+        assert_eq!(100, eval_segment(algorithm("t_range"), &[("created", string_to_dynamic("2020-12-31 23:59:59"))], &segment_ids).unwrap());
+        assert_eq!(200, eval_segment(algorithm("t_range"), &[("created", string_to_dynamic("2021-01-01"))], &segment_ids).unwrap());
+        assert_eq!(100, eval_segment(algorithm("t_custom"), &[("code", string_to_dynamic("a"))], &segment_ids).unwrap());
+        assert!(eval_segment(algorithm("t_custom"), &[("code", string_to_dynamic("b"))], &segment_ids).is_err());
+        assert!(eval_segment(algorithm("t_hash"), &[], &segment_ids).is_err());
+        // Segment 200 has no points on the ring.
+        for id in 0..10 as INT {
+            assert_eq!(100, eval_segment(algorithm("t_ring"), &[("id", Dynamic::from(id))], &segment_ids).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_compile_dis_rules() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        assert!(compile_dis_rules(cluster.get_dis_rules()).is_ok());
+
+        let cluster = test_cluster(&[("t_order", "user_id", r#"{ dis_type: HASH, dis_expression: "hash(user_id" }"#, "")]);
+        assert!(compile_dis_rules(cluster.get_dis_rules()).is_err());
+
+        // A snowflake worker_id has 10 bits.
+        let config = std::fs::read_to_string("./etc/dbmesh.yaml").unwrap();
+        let cluster = Cluster::from_str(config.replace("worker_id: 1", "worker_id: 1024").as_str()).unwrap();
+        match compile_dis_rules(cluster.get_dis_rules()) {
+            Err(Error::General(message)) => assert_eq!("t_order: worker_id 1024 is greater than 1023", message),
+            Ok(()) => panic!("worker_id 1024 does not fit a snowflake key"),
+        }
+    }
+
+    #[test]
+    fn test_check_dis_relatives() {
+        let rules = |relative_expression: &str| {
+            let relative_algorithm = format!(r#"{{ dis_type: HASH, dis_expression: "{}" }}"#, relative_expression);
+            test_cluster(&[
+                ("t_order", "user_id", r#"{ dis_type: HASH, dis_expression: "hash(user_id)" }"#, "t_order_item"),
+                ("t_order_item", "uid", relative_algorithm.as_str(), ""),
+            ])
+        };
+        assert!(compile_dis_rules(rules("hash( uid )").get_dis_rules()).is_ok());
+        assert!(compile_dis_rules(rules("hash(uid) + 1").get_dis_rules()).is_err());
+        assert!(compile_dis_rules(rules("uid").get_dis_rules()).is_err());
+    }
+}
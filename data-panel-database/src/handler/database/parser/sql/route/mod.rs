@@ -1,8 +1,786 @@
-// use crate::discovery::database::Cluster;
-// use crate::handler::database::mysql::explainplan::{ExplainPlanContext, ExplainPlan};
-// use crate::handler::database::parser::sql::SQLStatementContext;
-
-// pub fn route(route_ctx: RouteContext,
-//              stmt_ctx: SQLStatementContext) -> ExplainPlan {
-//
-// }
\ No newline at end of file
+use std::collections::HashMap;
+
+use chrono::Local;
+use sqlparser::ast::Statement;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{Cluster, DisRules, DisTable};
+use crate::handler::database::mysql::aggregate::AggregationPlan;
+use crate::handler::database::mysql::explainplan::PlanTask;
+use crate::handler::database::mysql::merge::order_by_derived_columns;
+use crate::handler::database::parser::sql::hint::RouteHint;
+use crate::handler::database::parser::sql::rewrite::SQLReWrite;
+use crate::handler::database::parser::sql::{PaginationRewrite, ShardingCondition, ShardingValue, SQLRewriteContext, SQLStatementContext, TableBinding};
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+use crate::session::mysql::SessionContext;
+
+pub mod algorithm;
+pub mod partition;
+pub mod readwrite;
+pub mod ring;
+
+pub type RouteResult<T> = data_panel_common::common::Result<T>;
+
+pub struct RouteContext<'a> {
+    cluster: &'a Cluster,
+    statement: &'a Statement,
+    session_ctx: &'a SessionContext,
+    parameters: &'a [PrepareParamValue],
+    hint: Option<RouteHint>,
+}
+
+impl<'a> RouteContext<'a> {
+    pub fn new(cluster: &'a Cluster,
+               statement: &'a Statement,
+               session_ctx: &'a SessionContext,
+               parameters: &'a [PrepareParamValue]) -> Self {
+        RouteContext {
+            cluster,
+            statement,
+            session_ctx,
+            parameters,
+            hint: None,
+        }
+    }
+
+    pub fn get_cluster(&self) -> &'a Cluster {
+        self.cluster
+    }
+
+    pub fn get_statement(&self) -> &'a Statement {
+        self.statement
+    }
+
+    pub fn get_session_ctx(&self) -> &'a SessionContext {
+        self.session_ctx
+    }
+
+    /// Values of the `?` placeholders of a prepared statement, empty for text queries.
+    pub fn get_parameters(&self) -> &'a [PrepareParamValue] {
+        self.parameters
+    }
+
+    pub fn set_hint(&mut self, hint: Option<RouteHint>) {
+        self.hint = hint;
+    }
+
+    /// The DBMESH hint of the SQL, which overrides the routing.
+    pub fn get_hint(&self) -> Option<RouteHint> {
+        self.hint
+    }
+}
+
+/// One data segment the statement is sent to, together with the
+/// logical -> physical table names used to rewrite the SQL for it.
+#[derive(Debug, PartialEq)]
+pub struct RouteUnit {
+    segment_id: u32,
+    table_mappings: HashMap<String, String>,
+    /// Indexes of the `INSERT ... VALUES` rows for the segment, `None` for all of them.
+    insert_rows: Option<Vec<usize>>,
+}
+
+impl RouteUnit {
+    pub fn new(segment_id: u32) -> Self {
+        RouteUnit {
+            segment_id,
+            table_mappings: HashMap::new(),
+            insert_rows: None,
+        }
+    }
+
+    pub fn get_insert_rows(&self) -> Option<&Vec<usize>> {
+        self.insert_rows.as_ref()
+    }
+
+    pub fn get_segment_id(&self) -> u32 {
+        self.segment_id
+    }
+
+    pub fn get_table_mappings(&self) -> &HashMap<String, String> {
+        &self.table_mappings
+    }
+
+    pub fn add_table_mapping(&mut self, logical_table: String, physical_table: String) {
+        self.table_mappings.insert(logical_table, physical_table);
+    }
+}
+
+/// A unit for the segment with the physical names of the distributed tables on it.
+fn distributed_route_unit(segment_id: u32, distributed_tables: &[&String], dis_rules: &DisRules) -> RouteUnit {
+    let mut route_unit = RouteUnit::new(segment_id);
+    for table in distributed_tables {
+        let physical_table = dis_rules.get_distributed_table(table.as_str())
+            .and_then(|dis_table| dis_table.get_dis_physical_table(segment_id));
+        if let Some(physical_table) = physical_table {
+            route_unit.add_table_mapping(table.to_string(), physical_table.clone());
+        }
+    }
+    route_unit
+}
+
+/// Route the analysed statement to data segments, one `PlanTask` per segment, or per
+/// partition of a partitioned table.
+pub fn route(route_ctx: &RouteContext<'_>,
+             stmt_ctx: &SQLStatementContext) -> RouteResult<Vec<PlanTask>> {
+    let route_units = route_units(route_ctx, stmt_ctx)?;
+    let mut pagination_rewrite = PaginationRewrite::Keep;
+    let mut aggregation = None;
+    let mut derived_columns = vec![];
+    let mut parameters = route_ctx.get_parameters().to_vec();
+    if route_units.len() > 1 {
+        aggregation = AggregationPlan::new(stmt_ctx, route_ctx.get_cluster().get_dis_rules())?;
+        if aggregation.is_none() {
+            derived_columns = order_by_derived_columns(stmt_ctx);
+        }
+        let pagination = pagination(route_ctx, stmt_ctx)?;
+        if aggregation.is_some() {
+            // Groups are only complete once the rows of every segment are merged.
+            if pagination.is_some() {
+                pagination_rewrite = PaginationRewrite::Remove;
+            }
+        } else if let Some(row_count) = pagination.and_then(|p| p.get_row_count()) {
+            pagination_rewrite = PaginationRewrite::RowCount(row_count);
+        }
+        if pagination_rewrite != PaginationRewrite::Keep {
+            // The `?` of LIMIT and OFFSET are not in the SQL sent to the segments.
+            let pagination_parameters: Vec<usize> = vec![stmt_ctx.get_limit(), stmt_ctx.get_offset()].into_iter()
+                .filter_map(|value| match value {
+                    Some(ShardingValue::Parameter(index)) => Some(*index),
+                    _ => None,
+                })
+                .collect();
+            parameters = parameters.into_iter().enumerate()
+                .filter(|(index, _)| !pagination_parameters.contains(index))
+                .map(|(_, parameter)| parameter)
+                .collect();
+        }
+    }
+    let mut tasks = Vec::with_capacity(route_units.len());
+    for route_unit in route_units {
+        let mut sql = String::new();
+        let segment_id = route_unit.get_segment_id();
+        let task_parameters = match route_unit.get_insert_rows() {
+            Some(insert_rows) => insert_parameters(&parameters, stmt_ctx, insert_rows),
+            None => parameters.clone(),
+        };
+        let mut rewrite_ctx = SQLRewriteContext::new(route_unit.table_mappings);
+        rewrite_ctx.set_insert_rows(route_unit.insert_rows);
+        rewrite_ctx.set_pagination(pagination_rewrite);
+        if let Some(ref aggregation) = aggregation {
+            rewrite_ctx.set_derived_columns(aggregation.get_derived_columns().to_vec());
+            rewrite_ctx.set_derived_group_by(aggregation.get_derived_group_by().to_vec());
+            rewrite_ctx.set_remove_having(aggregation.has_having());
+        } else {
+            rewrite_ctx.set_derived_columns(derived_columns.clone());
+        }
+        route_ctx.get_statement().rewrite(&mut sql, &rewrite_ctx)?;
+        tasks.push(PlanTask::new(segment_id, sql, task_parameters));
+    }
+    Ok(tasks)
+}
+
+/// The parameters of the statement without the `?` of the `INSERT ... VALUES` rows
+/// which go to other segments.
+fn insert_parameters(parameters: &[PrepareParamValue], stmt_ctx: &SQLStatementContext, insert_rows: &[usize]) -> Vec<PrepareParamValue> {
+    let rows = stmt_ctx.get_insert_rows();
+    parameters.iter().enumerate()
+        .filter(|(index, _)| {
+            match rows.iter().position(|row| row.get_parameters().contains(index)) {
+                Some(row) => insert_rows.contains(&row),
+                None => true,
+            }
+        })
+        .map(|(_, parameter)| parameter.clone())
+        .collect()
+}
+
+/// `LIMIT` and `OFFSET` of a query sent to several segments, applied again when their rows are merged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pagination {
+    offset: u64,
+    limit: Option<u64>,
+}
+
+impl Pagination {
+    pub fn new(offset: u64, limit: Option<u64>) -> Self {
+        Pagination {
+            offset,
+            limit,
+        }
+    }
+
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// How many rows each segment has to return, `None` without a `LIMIT`.
+    pub fn get_row_count(&self) -> Option<u64> {
+        self.limit.map(|limit| self.offset.saturating_add(limit))
+    }
+}
+
+pub fn pagination(route_ctx: &RouteContext<'_>,
+                  stmt_ctx: &SQLStatementContext) -> RouteResult<Option<Pagination>> {
+    if stmt_ctx.get_limit().is_none() && stmt_ctx.get_offset().is_none() {
+        return Ok(None);
+    }
+    let limit = match stmt_ctx.get_limit() {
+        Some(limit) => Some(pagination_count(limit, route_ctx.get_parameters())?),
+        None => None,
+    };
+    let offset = match stmt_ctx.get_offset() {
+        Some(offset) => pagination_count(offset, route_ctx.get_parameters())?,
+        None => 0,
+    };
+    let pagination = Pagination::new(offset, limit);
+
+    let max_pagination_rows = route_ctx.get_cluster().get_props().get_max_pagination_rows();
+    let row_count = pagination.get_row_count().unwrap_or(offset);
+    if max_pagination_rows > 0 && row_count > max_pagination_rows {
+        return Err(Error::General(format!(
+            "LIMIT {} OFFSET {} needs {} rows from every data segment, more than max_pagination_rows {}",
+            limit.unwrap_or(0), offset, row_count, max_pagination_rows)));
+    }
+    Ok(Some(pagination))
+}
+
+fn pagination_count(value: &ShardingValue, parameters: &[PrepareParamValue]) -> RouteResult<u64> {
+    let count = match value {
+        ShardingValue::Literal(sqlparser::ast::Value::Number(n, _)) => n.parse::<u64>().ok(),
+        ShardingValue::Parameter(index) => match parameters.get(*index) {
+            Some(PrepareParamValue::Int(i)) if *i >= 0 => Some(*i as u64),
+            Some(PrepareParamValue::UInt(u)) => Some(*u),
+            Some(PrepareParamValue::Bytes(bytes)) => String::from_utf8_lossy(bytes).trim().parse::<u64>().ok(),
+            _ => None,
+        },
+        _ => None,
+    };
+    count.ok_or_else(|| Error::General(String::from("LIMIT and OFFSET must be non-negative integers")))
+}
+
+pub fn route_units(route_ctx: &RouteContext<'_>,
+                   stmt_ctx: &SQLStatementContext) -> RouteResult<Vec<RouteUnit>> {
+    let segment_ids = route_ctx.get_cluster().get_segments().get_data_segment_ids();
+    if segment_ids.is_empty() {
+        return Err(Error::General(String::from("no data segment configured")));
+    }
+    let default_segment_id = segment_ids[0];
+
+    let dis_rules = route_ctx.get_cluster().get_dis_rules();
+    let table_names = stmt_ctx.get_table_names();
+
+    let distributed_tables: Vec<&String> = table_names.iter()
+        .filter(|t| dis_rules.is_distributed_table(t.as_str()))
+        .collect();
+    if let Some(shard) = route_ctx.get_hint().and_then(|hint| hint.get_shard()) {
+        if !segment_ids.contains(&shard) {
+            return Err(Error::General(format!("DBMESH hint shard={} is not a data segment", shard)));
+        }
+        return Ok(vec![distributed_route_unit(shard, &distributed_tables, dis_rules)]);
+    }
+    if let SQLStatementContext::Ddl(_) = stmt_ctx {
+        return ddl_route_units(route_ctx, &table_names, &segment_ids);
+    }
+    if !distributed_tables.is_empty() {
+        if !stmt_ctx.get_insert_rows().is_empty() {
+            if let Some(dis_table) = dis_rules.get_distributed_table(distributed_tables[0].as_str()) {
+                return insert_route_units(route_ctx, stmt_ctx, distributed_tables[0], dis_table, &segment_ids);
+            }
+        }
+        let sharding_conditions = stmt_ctx.get_sharding_conditions(dis_rules);
+        let no_conditions = HashMap::new();
+        let partitioned_table = distributed_tables.iter()
+            .find(|t| dis_rules.get_distributed_table(t.as_str()).and_then(|d| d.get_dis_partition()).is_some());
+        if let Some(table) = partitioned_table {
+            if distributed_tables.len() > 1 {
+                return Err(Error::General(format!("partitioned table {} can not be joined with other distributed tables", table)));
+            }
+            let conditions = sharding_conditions.get(table.as_str()).unwrap_or(&no_conditions);
+            return partition_route_units(route_ctx, table, conditions, &segment_ids);
+        }
+        let mut sharding_segment_ids = vec![];
+        for table in distributed_tables.iter() {
+            let dis_table = match dis_rules.get_distributed_table(table.as_str()) {
+                Some(dis_table) => dis_table,
+                None => continue,
+            };
+            let conditions = sharding_conditions.get(table.as_str()).unwrap_or(&no_conditions);
+            match algorithm::sharding_segments(dis_table, conditions, route_ctx.get_parameters(), &segment_ids)? {
+                Some(table_segment_ids) => {
+                    for segment_id in table_segment_ids {
+                        if !sharding_segment_ids.contains(&segment_id) {
+                            sharding_segment_ids.push(segment_id);
+                        }
+                    }
+                }
+                // Without sharding conditions every segment may hold matching rows.
+                None => {
+                    sharding_segment_ids = segment_ids.clone();
+                    break;
+                }
+            }
+        }
+        if sharding_segment_ids.is_empty() {
+            // Nothing can match, any single segment answers with an empty result.
+            sharding_segment_ids.push(default_segment_id);
+        }
+        if sharding_segment_ids.len() > 1 && !is_co_located(&distributed_tables, &stmt_ctx.get_table_bindings(dis_rules)) {
+            let tables: Vec<&str> = distributed_tables.iter().map(|t| t.as_str()).collect();
+            return Err(Error::General(format!(
+                "{} are on different data segments, join distributed tables only with their dis_relatives on the dis keys",
+                tables.join(", "))));
+        }
+        sharding_segment_ids.sort();
+        return Ok(sharding_segment_ids.into_iter()
+            .map(|segment_id| distributed_route_unit(segment_id, &distributed_tables, dis_rules))
+            .collect());
+    }
+
+    let has_replicated_table = table_names.iter()
+        .any(|t| dis_rules.is_replicated_table(t.as_str()));
+    if has_replicated_table && is_write_statement(route_ctx.get_statement()) {
+        return Ok(segment_ids.into_iter().map(RouteUnit::new).collect());
+    }
+    let replicated_only = !table_names.is_empty() && table_names.iter()
+        .all(|t| dis_rules.is_replicated_table(t.as_str()));
+    if replicated_only {
+        // Every segment has the rows, the sessions are spread over them and each one
+        // keeps reading from the same segment.
+        let index = route_ctx.get_session_ctx().get_thread_id() as usize % segment_ids.len();
+        return Ok(vec![RouteUnit::new(segment_ids[index])]);
+    }
+
+    Ok(vec![RouteUnit::new(default_segment_id)])
+}
+
+/// Every physical table of the tables of a DDL statement: distributed and replicated tables
+/// are on every segment, partitioned tables have a table for each period, other tables
+/// are on the default segment.
+fn ddl_route_units(route_ctx: &RouteContext<'_>,
+                   table_names: &[String],
+                   segment_ids: &[u32]) -> RouteResult<Vec<RouteUnit>> {
+    let dis_rules = route_ctx.get_cluster().get_dis_rules();
+    let is_sharded = |t: &String| dis_rules.is_distributed_table(t.as_str()) || dis_rules.is_replicated_table(t.as_str());
+    if !table_names.iter().any(is_sharded) {
+        return Ok(vec![RouteUnit::new(segment_ids[0])]);
+    }
+    if !table_names.iter().all(is_sharded) {
+        return Err(Error::General(format!(
+            "{} are not all on every data segment, change the tables on one segment separately",
+            table_names.join(", "))));
+    }
+    let partitioned_table = table_names.iter()
+        .find(|t| dis_rules.get_distributed_table(t.as_str()).and_then(|d| d.get_dis_partition()).is_some());
+    if let Some(table) = partitioned_table {
+        if table_names.len() > 1 {
+            return Err(Error::General(format!("change the partitioned table {} separately", table)));
+        }
+        return partition_route_units(route_ctx, table, &HashMap::new(), segment_ids);
+    }
+    let distributed_tables: Vec<&String> = table_names.iter()
+        .filter(|t| dis_rules.is_distributed_table(t.as_str()))
+        .collect();
+    Ok(segment_ids.iter()
+        .map(|segment_id| distributed_route_unit(*segment_id, &distributed_tables, dis_rules))
+        .collect())
+}
+
+/// One unit per partition of the table which may hold rows the statement needs. When no
+/// partition can, the first one on the default segment answers with nothing.
+fn partition_route_units(route_ctx: &RouteContext<'_>,
+                         table: &String,
+                         conditions: &HashMap<String, Vec<ShardingCondition>>,
+                         segment_ids: &[u32]) -> RouteResult<Vec<RouteUnit>> {
+    let dis_table = match route_ctx.get_cluster().get_dis_rules().get_distributed_table(table.as_str()) {
+        Some(dis_table) => dis_table,
+        None => return Ok(vec![RouteUnit::new(segment_ids[0])]),
+    };
+    let dis_partition = match dis_table.get_dis_partition() {
+        Some(dis_partition) => dis_partition,
+        None => return Ok(vec![RouteUnit::new(segment_ids[0])]),
+    };
+    let mut units = partition::partition_units(dis_table, dis_partition, conditions, route_ctx.get_parameters(),
+                                               segment_ids, Local::today().naive_local())?;
+    if units.is_empty() {
+        units.push((segment_ids[0], partition::first_partition_table(dis_partition)?));
+    }
+    Ok(units.into_iter()
+        .map(|(segment_id, physical_table)| {
+            let mut route_unit = RouteUnit::new(segment_id);
+            route_unit.add_table_mapping(table.to_string(), physical_table);
+            route_unit
+        })
+        .collect())
+}
+
+/// A statement sent to several segments only sees the rows each segment has, so its
+/// distributed tables have to be bound to each other, directly or through other tables.
+pub fn is_co_located(distributed_tables: &[&String], table_bindings: &[TableBinding]) -> bool {
+    if distributed_tables.is_empty() {
+        return true;
+    }
+    let mut bound = vec![distributed_tables[0].as_str()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for binding in table_bindings {
+            let (left, right) = (binding.get_left().0.as_str(), binding.get_right().0.as_str());
+            if bound.contains(&left) != bound.contains(&right) {
+                bound.push(if bound.contains(&left) { right } else { left });
+                changed = true;
+            }
+        }
+    }
+    distributed_tables.iter().all(|table| bound.contains(&table.as_str()))
+}
+
+/// Split `INSERT ... VALUES` by the segment of every row, which its dis key values choose.
+/// The units keep the rows in the order they were written.
+fn insert_route_units(route_ctx: &RouteContext<'_>,
+                      stmt_ctx: &SQLStatementContext,
+                      table: &String,
+                      dis_table: &DisTable,
+                      segment_ids: &[u32]) -> RouteResult<Vec<RouteUnit>> {
+    let columns = stmt_ctx.get_insert_columns();
+    let mut key_indexes = Vec::with_capacity(dis_table.get_dis_keys().len());
+    for dis_key in dis_table.get_dis_keys() {
+        match columns.iter().position(|column| column.eq_ignore_ascii_case(dis_key)) {
+            Some(index) => key_indexes.push((dis_key, index)),
+            None => {
+                return Err(Error::General(format!("INSERT INTO {} must list the dis key column {}", table, dis_key)));
+            }
+        }
+    }
+
+    let mut segment_rows: Vec<((u32, Option<String>), Vec<usize>)> = vec![];
+    for (row_index, row) in stmt_ctx.get_insert_rows().iter().enumerate() {
+        let mut conditions = HashMap::new();
+        for (dis_key, index) in key_indexes.iter() {
+            let value = match row.get_values().get(*index) {
+                Some(Some(value)) => value.clone(),
+                _ => {
+                    return Err(Error::General(format!(
+                        "the value of dis key {} in row {} of INSERT INTO {} must be a literal or a parameter",
+                        dis_key, row_index + 1, table)));
+                }
+            };
+            conditions.insert(dis_key.to_string(), vec![ShardingCondition::Equal(vec![value])]);
+        }
+        let no_segment = || Error::General(format!(
+            "row {} of INSERT INTO {} has no data segment, its dis key is NULL or has no value",
+            row_index + 1, table));
+        let unit = match dis_table.get_dis_partition() {
+            Some(dis_partition) => {
+                let mut units = partition::partition_units(dis_table, dis_partition, &conditions, route_ctx.get_parameters(),
+                                                           segment_ids, Local::today().naive_local())?;
+                match units.pop() {
+                    Some((segment_id, physical_table)) if units.is_empty() => (segment_id, Some(physical_table)),
+                    _ => return Err(no_segment()),
+                }
+            }
+            None => match algorithm::sharding_segments(dis_table, &conditions, route_ctx.get_parameters(), segment_ids)? {
+                Some(ref segments) if segments.len() == 1 => (segments[0], None),
+                _ => return Err(no_segment()),
+            },
+        };
+        match segment_rows.iter_mut().find(|(u, _)| *u == unit) {
+            Some((_, rows)) => rows.push(row_index),
+            None => segment_rows.push((unit, vec![row_index])),
+        }
+    }
+    segment_rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let distributed_tables = vec![table];
+    Ok(segment_rows.into_iter()
+        .map(|((segment_id, physical_table), rows)| {
+            let mut route_unit = distributed_route_unit(segment_id, &distributed_tables, route_ctx.get_cluster().get_dis_rules());
+            if let Some(physical_table) = physical_table {
+                route_unit.add_table_mapping(table.to_string(), physical_table);
+            }
+            route_unit.insert_rows = Some(rows);
+            route_unit
+        })
+        .collect())
+}
+
+/// Statements which change data or schema, everything else is a read.
+pub fn is_write_statement(statement: &Statement) -> bool {
+    match statement {
+        Statement::Query(_) => false,
+        Statement::ShowVariable { .. } => false,
+        Statement::ShowColumns { .. } => false,
+        Statement::Explain { .. } => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::mysql::explainplan::PlanTask;
+    use crate::handler::database::parser::sql::analyse::SQLAnalyse;
+    use crate::handler::database::parser::sql::hint::extract_hint;
+    use crate::handler::database::parser::sql::mysql::parser;
+    use crate::handler::database::parser::sql::route::{route, RouteContext};
+    use crate::handler::database::parser::sql::{PaginationRewrite, ShardingValue, SQLRewriteContext, SQLStatementContext};
+    use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+    use crate::session::mysql::SessionContext;
+
+    /// Parse and analyse the SQL, and plan it with the route context of the cluster and the
+    /// session of id 1. The planners' tests share it.
+    pub fn with_route_ctx<T, F>(cluster: &Cluster, sql: &str, parameters: &[PrepareParamValue], plan: F) -> T
+        where
+            F: FnOnce(&RouteContext<'_>, &SQLStatementContext) -> T,
+    {
+        let session_ctx = SessionContext::new(1);
+        let statement = parser(sql.to_string()).pop().unwrap();
+        let mut stmt_ctx = SQLStatementContext::new(&statement);
+        statement.analyse(&mut stmt_ctx).unwrap();
+        let route_ctx = RouteContext::new(cluster, &statement, &session_ctx, parameters);
+        plan(&route_ctx, &stmt_ctx)
+    }
+
+    fn route_tasks_with_parameters(sql: &str, parameters: &[PrepareParamValue]) -> Vec<PlanTask> {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        with_route_ctx(&cluster, sql, parameters, |route_ctx, stmt_ctx| route(route_ctx, stmt_ctx).unwrap())
+    }
+
+    fn route_tasks(sql: &str) -> Vec<PlanTask> {
+        route_tasks_with_parameters(sql, &[])
+    }
+
+    fn route_segments(sql: &str) -> Vec<u32> {
+        route_tasks(sql).iter().map(|t| t.get_segment_id()).collect()
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(vec![100, 200, 300], route_segments("SELECT * FROM t_order"));
+        assert_eq!(vec![100, 200, 300], route_segments("INSERT INTO t_dept (id, name) VALUES (1, 'a')"));
+        // The session of the tests has id 1.
+        assert_eq!(vec![200], route_segments("SELECT * FROM t_dept"));
+        assert_eq!(vec![100], route_segments("SELECT * FROM t_dept, t_user"));
+        assert_eq!(vec![100], route_segments("SELECT * FROM t_user"));
+
+        assert_eq!(vec![200], route_segments("SELECT * FROM t_order WHERE user_id = 10"));
+        assert_eq!(vec![200], route_segments("SELECT * FROM t_order WHERE user_id = '10'"));
+        assert_eq!(vec![200, 300], route_segments("SELECT * FROM t_order WHERE user_id IN (10, 11)"));
+        assert_eq!(vec![100], route_segments("UPDATE t_order SET status = 'PAID' WHERE user_id = 7"));
+        assert_eq!(vec![100, 200, 300], route_segments("SELECT * FROM t_order WHERE user_id = ?"));
+        assert_eq!(vec![100, 200, 300], route_segments("SELECT * FROM t_order WHERE user_id > 10"));
+        assert_eq!(vec![100, 200], route_segments("SELECT * FROM t_order_log WHERE created_at BETWEEN '2020-06-01' AND '2021-06-01'"));
+        assert_eq!(vec![300], route_segments("SELECT * FROM t_order_log WHERE created_at = '2022-01-01 10:00:00'"));
+    }
+
+    #[test]
+    fn test_route_partitions() {
+        let tasks = route_tasks("SELECT * FROM t_order_event WHERE created_at >= '2020-12-15' AND created_at < '2021-02-01'");
+        let tasks: Vec<(u32, &str)> = tasks.iter().map(|t| (t.get_segment_id(), t.get_sql())).collect();
+        assert_eq!(vec![(100, "SELECT * FROM t_order_event_202012 WHERE created_at >= '2020-12-15' AND created_at < '2021-02-01'"),
+                        (200, "SELECT * FROM t_order_event_202101 WHERE created_at >= '2020-12-15' AND created_at < '2021-02-01'")], tasks);
+
+        let tasks = route_tasks("INSERT INTO t_order_event (id, created_at) VALUES (1, '2021-01-05'), (2, '2020-12-31'), (3, '2021-01-20')");
+        let tasks: Vec<(u32, &str)> = tasks.iter().map(|t| (t.get_segment_id(), t.get_sql())).collect();
+        assert_eq!(vec![(100, "INSERT INTO t_order_event_202012 (id, created_at) VALUES (2, '2020-12-31')"),
+                        (200, "INSERT INTO t_order_event_202101 (id, created_at) VALUES (1, '2021-01-05'), (3, '2021-01-20')")], tasks);
+    }
+
+    #[test]
+    fn test_route_physical_tables() {
+        let tasks = route_tasks("SELECT o.order_id FROM t_order AS o WHERE o.user_id IN (10, 11)");
+        let sqls: Vec<&str> = tasks.iter().map(|t| t.get_sql()).collect();
+        assert_eq!(vec!["SELECT o.order_id FROM t_order_1 AS o WHERE o.user_id IN (10, 11)",
+                        "SELECT o.order_id FROM t_order_2 AS o WHERE o.user_id IN (10, 11)"], sqls);
+
+        let tasks = route_tasks("SELECT * FROM t_order_item WHERE user_id = 10");
+        assert_eq!("SELECT * FROM t_order_item WHERE user_id = 10", tasks[0].get_sql());
+    }
+
+    #[test]
+    fn test_route_binding_tables() {
+        let tasks = route_tasks("SELECT o.order_id, i.item_id FROM t_order AS o JOIN t_order_item AS i ON o.user_id = i.user_id \
+                                 WHERE o.user_id = 10");
+        assert_eq!(vec![200], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+        assert_eq!("SELECT o.order_id, i.item_id FROM t_order_1 AS o JOIN t_order_item AS i ON o.user_id = i.user_id \
+                    WHERE o.user_id = 10", tasks[0].get_sql());
+
+        // The condition on the relative narrows the bound table down as well.
+        let tasks = route_tasks("SELECT * FROM t_order AS o, t_order_item AS i WHERE o.user_id = i.user_id AND i.user_id = 10");
+        assert_eq!(vec![200], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+
+        let tasks = route_tasks("SELECT * FROM t_order AS o JOIN t_order_item AS i ON o.user_id = i.user_id");
+        assert_eq!(vec![100, 200, 300], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_route_replicated_join() {
+        let tasks = route_tasks("SELECT * FROM t_order AS o JOIN t_dept AS d ON o.dept_id = d.id WHERE o.user_id IN (10, 11)");
+        let sqls: Vec<&str> = tasks.iter().map(|t| t.get_sql()).collect();
+        assert_eq!(vec!["SELECT * FROM t_order_1 AS o JOIN t_dept AS d ON o.dept_id = d.id WHERE o.user_id IN (10, 11)",
+                        "SELECT * FROM t_order_2 AS o JOIN t_dept AS d ON o.dept_id = d.id WHERE o.user_id IN (10, 11)"], sqls);
+    }
+
+    #[test]
+    fn test_route_not_co_located() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        for sql in vec!["SELECT * FROM t_order AS o JOIN t_order_item AS i ON o.order_id = i.order_id",
+                        "SELECT * FROM t_order AS o JOIN t_order_log AS l ON o.user_id = l.user_id",
+                        "SELECT * FROM t_order_event AS e JOIN t_order AS o ON e.id = o.order_id WHERE o.user_id = 10"] {
+            assert!(with_route_ctx(&cluster, sql, &[], |route_ctx, stmt_ctx| route(route_ctx, stmt_ctx).is_err()));
+        }
+    }
+
+    #[test]
+    fn test_route_parameters() {
+        let sql = "SELECT * FROM t_order WHERE status = ? AND user_id = ?";
+        let parameters = vec![PrepareParamValue::Bytes(b"PAID".to_vec()), PrepareParamValue::Int(10)];
+        let tasks = route_tasks_with_parameters(sql, &parameters);
+        assert_eq!(vec![200], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+        assert!(tasks[0].get_parameters() == parameters.as_slice());
+
+        let parameters = vec![PrepareParamValue::Bytes(b"PAID".to_vec()), PrepareParamValue::Bytes(b"10".to_vec())];
+        let tasks = route_tasks_with_parameters(sql, &parameters);
+        assert_eq!(vec![200], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_route_insert() {
+        let tasks = route_tasks("INSERT INTO t_order (order_id, user_id, status) VALUES (1, 10, 'PAID'), (2, 11, 'PAID'), (3, 10, 'NEW')");
+        assert_eq!(vec![200, 300], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+        assert_eq!("INSERT INTO t_order_1 (order_id, user_id, status) VALUES (1, 10, 'PAID'), (3, 10, 'NEW')", tasks[0].get_sql());
+        assert_eq!("INSERT INTO t_order_2 (order_id, user_id, status) VALUES (2, 11, 'PAID')", tasks[1].get_sql());
+
+        let sql = "INSERT INTO t_order (order_id, user_id) VALUES (?, ?), (?, ?), (?, ?)";
+        let parameters = vec![PrepareParamValue::Int(1), PrepareParamValue::Int(10),
+                              PrepareParamValue::Int(2), PrepareParamValue::Int(11),
+                              PrepareParamValue::Int(3), PrepareParamValue::Bytes(b"10".to_vec())];
+        let tasks = route_tasks_with_parameters(sql, &parameters);
+        assert_eq!(vec![200, 300], tasks.iter().map(|t| t.get_segment_id()).collect::<Vec<u32>>());
+        assert_eq!("INSERT INTO t_order_1 (order_id, user_id) VALUES (?, ?), (?, ?)", tasks[0].get_sql());
+        assert!(tasks[0].get_parameters() == &[parameters[0].clone(), parameters[1].clone(), parameters[4].clone(), parameters[5].clone()][..]);
+        assert!(tasks[1].get_parameters() == &parameters[2..4]);
+    }
+
+    #[test]
+    fn test_route_insert_without_dis_key() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let sql = "INSERT INTO t_order (order_id, status) VALUES (1, 'PAID')";
+        assert!(with_route_ctx(&cluster, sql, &[], |route_ctx, stmt_ctx| route(route_ctx, stmt_ctx).is_err()));
+    }
+
+    #[test]
+    fn test_route_pagination() {
+        let tasks = route_tasks("SELECT * FROM t_order ORDER BY order_id LIMIT 10 OFFSET 100");
+        assert_eq!(3, tasks.len());
+        for task in tasks.iter() {
+            assert!(task.get_sql().ends_with("ORDER BY order_id LIMIT 110"));
+        }
+
+        let tasks = route_tasks("SELECT * FROM t_order WHERE user_id = 10 LIMIT 10 OFFSET 100");
+        assert_eq!("SELECT * FROM t_order_1 WHERE user_id = 10 LIMIT 10 OFFSET 100", tasks[0].get_sql());
+
+        let sql = "SELECT * FROM t_order WHERE status = ? LIMIT ? OFFSET ?";
+        let parameters = vec![PrepareParamValue::Bytes(b"PAID".to_vec()), PrepareParamValue::Int(5), PrepareParamValue::Int(20)];
+        let tasks = route_tasks_with_parameters(sql, &parameters);
+        assert_eq!("SELECT * FROM t_order_0 WHERE status = ? LIMIT 25", tasks[0].get_sql());
+        assert!(tasks[0].get_parameters() == &parameters[..1]);
+    }
+
+    #[test]
+    fn test_route_aggregation() {
+        let tasks = route_tasks("SELECT status, AVG(amount) FROM t_order GROUP BY status HAVING COUNT(*) > 1 ORDER BY status LIMIT 5");
+        assert_eq!("SELECT status, AVG(amount), SUM(amount) AS DP_DERIVED_AVG_SUM_0, COUNT(amount) AS DP_DERIVED_AVG_COUNT_1, \
+                    COUNT(*) AS DP_DERIVED_HAVING_2 FROM t_order_0 GROUP BY status ORDER BY status",
+                   tasks[0].get_sql());
+
+        let tasks = route_tasks("SELECT COUNT(*) FROM t_order WHERE user_id = 10");
+        assert_eq!("SELECT COUNT(*) FROM t_order_1 WHERE user_id = 10", tasks[0].get_sql());
+    }
+
+    #[test]
+    fn test_route_order_by_not_selected() {
+        let tasks = route_tasks("SELECT order_id, amount AS total FROM t_order ORDER BY create_time DESC, total, 1 LIMIT 10");
+        assert_eq!("SELECT order_id, amount AS total, create_time AS DP_DERIVED_ORDER_BY_0 FROM t_order_0 \
+                    ORDER BY create_time DESC, total, 1 LIMIT 10",
+                   tasks[0].get_sql());
+
+        let tasks = route_tasks("SELECT * FROM t_order ORDER BY create_time");
+        assert_eq!("SELECT * FROM t_order_0 ORDER BY create_time", tasks[0].get_sql());
+
+        let tasks = route_tasks("SELECT DISTINCT status FROM t_order ORDER BY create_time");
+        assert_eq!("SELECT DISTINCT status FROM t_order_0 ORDER BY create_time", tasks[0].get_sql());
+    }
+
+    #[test]
+    fn test_route_distinct() {
+        let tasks = route_tasks("SELECT DISTINCT status FROM t_order ORDER BY status LIMIT 10");
+        assert_eq!("SELECT DISTINCT status FROM t_order_0 ORDER BY status LIMIT 10", tasks[0].get_sql());
+
+        let tasks = route_tasks("SELECT user_id, COUNT(DISTINCT status), SUM(amount) FROM t_order GROUP BY user_id");
+        assert_eq!("SELECT user_id, COUNT(DISTINCT status), SUM(amount), status AS DP_DERIVED_DISTINCT_0 \
+                    FROM t_order_0 GROUP BY user_id, status",
+                   tasks[0].get_sql());
+
+        let tasks = route_tasks("SELECT COUNT(DISTINCT status) FROM t_order");
+        assert_eq!("SELECT COUNT(DISTINCT status), status AS DP_DERIVED_DISTINCT_0 FROM t_order_0 GROUP BY status",
+                   tasks[0].get_sql());
+
+        // Every user_id lives on one segment, the distinct counts of the segments add up.
+        let tasks = route_tasks("SELECT COUNT(DISTINCT user_id) FROM t_order");
+        assert_eq!("SELECT COUNT(DISTINCT user_id) FROM t_order_0", tasks[0].get_sql());
+    }
+
+    #[test]
+    fn test_route_hint() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let session_ctx = SessionContext::new(1);
+        let route_hinted = |sql: &str| {
+            let (hint, sql) = extract_hint(sql).unwrap();
+            let statement = parser(sql).pop().unwrap();
+            let mut stmt_ctx = SQLStatementContext::new(&statement);
+            statement.analyse(&mut stmt_ctx).unwrap();
+            let mut route_ctx = RouteContext::new(&cluster, &statement, &session_ctx, &[]);
+            route_ctx.set_hint(hint);
+            route(&route_ctx, &stmt_ctx).map(|tasks| {
+                tasks.iter().map(|t| (t.get_segment_id(), t.get_sql().to_string())).collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(vec![(300, String::from("SELECT * FROM t_order_2 WHERE user_id = 10"))],
+                   route_hinted("/*+ DBMESH shard=300 */ SELECT * FROM t_order WHERE user_id = 10").unwrap());
+        assert_eq!(vec![(200, String::from("INSERT INTO t_dept (id, name) VALUES (1, 'a')"))],
+                   route_hinted("INSERT /*+ DBMESH shard=200 */ INTO t_dept (id, name) VALUES (1, 'a')").unwrap());
+        // master only chooses the primaries, the statement still goes to every segment.
+        assert_eq!(vec![(100, String::from("SELECT * FROM t_order_0")),
+                        (200, String::from("SELECT * FROM t_order_1")),
+                        (300, String::from("SELECT * FROM t_order_2"))],
+                   route_hinted("/*+ DBMESH master */ SELECT * FROM t_order").unwrap());
+        assert!(route_hinted("/*+ DBMESH shard=400 */ SELECT * FROM t_order").is_err());
+    }
+
+    #[test]
+    fn test_route_ddl() {
+        let tasks = |sql: &str| -> Vec<(u32, String)> {
+            route_tasks(sql).iter().map(|t| (t.get_segment_id(), t.get_sql().to_string())).collect()
+        };
+        assert_eq!(vec![(100, String::from("ALTER TABLE t_order_0 ADD COLUMN remark VARCHAR(64)")),
+                        (200, String::from("ALTER TABLE t_order_1 ADD COLUMN remark VARCHAR(64)")),
+                        (300, String::from("ALTER TABLE t_order_2 ADD COLUMN remark VARCHAR(64)"))],
+                   tasks("ALTER TABLE t_order ADD COLUMN remark VARCHAR(64)"));
+        assert_eq!(vec![100, 200, 300], route_segments("TRUNCATE TABLE t_dept"));
+        assert_eq!(vec![100, 200, 300], route_segments("CREATE INDEX idx_status ON t_order (status)"));
+        assert_eq!(vec![100], route_segments("DROP TABLE t_user"));
+
+        // One table for every period, up to the one created ahead.
+        let partitions = tasks("TRUNCATE TABLE t_order_event");
+        assert_eq!((100, String::from("TRUNCATE TABLE t_order_event_202001")), partitions[0]);
+        assert!(partitions.len() > 24);
+        assert!(partitions.iter().all(|(_, sql)| sql.starts_with("TRUNCATE TABLE t_order_event_")));
+    }
+}
@@ -0,0 +1,255 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use rhai::Dynamic;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{Cluster, DisPartition, DisTable, PartitionInterval};
+use crate::handler::database::mysql::explainplan::PlanTask;
+use crate::handler::database::parser::sql::ShardingCondition;
+use crate::handler::database::parser::sql::route::algorithm::{Bound, compare_dynamic, equal_values, eval_segment, is_tighter, range_bounds, segments_between};
+use crate::handler::database::parser::sql::route::RouteResult;
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+
+lazy_static! {
+    /// `CREATE TABLE ... LIKE` tasks which succeeded, they are not sent again.
+    static ref CREATED_AHEAD: Mutex<HashSet<(u32, String)>> = Mutex::new(HashSet::new());
+}
+
+/// A partitioned table has one date dis key and names a table for every period from its start.
+pub fn check_dis_partition(table: &str, dis_table: &DisTable, dis_partition: &DisPartition) -> RouteResult<()> {
+    if dis_table.get_dis_keys().len() != 1 {
+        return Err(Error::General(format!("{}: dis_partition needs one dis key", table)));
+    }
+    partition_start(dis_partition)
+        .map_err(|Error::General(message)| Error::General(format!("{}: {}", table, message)))?;
+    if !dis_partition.get_table_format().contains('%') {
+        return Err(Error::General(format!("{}: table_format {} must format the period, e.g. with %Y%m",
+                                          table, dis_partition.get_table_format())));
+    }
+    Ok(())
+}
+
+/// The first day of the period holding the day.
+pub fn period_start(interval: PartitionInterval, day: NaiveDate) -> NaiveDate {
+    match interval {
+        PartitionInterval::DAY => day,
+        PartitionInterval::MONTH => NaiveDate::from_ymd(day.year(), day.month(), 1),
+        PartitionInterval::YEAR => NaiveDate::from_ymd(day.year(), 1, 1),
+    }
+}
+
+/// The first day of the period after the one starting on `start`.
+pub fn next_period(interval: PartitionInterval, start: NaiveDate) -> NaiveDate {
+    match interval {
+        PartitionInterval::DAY => start.succ(),
+        PartitionInterval::MONTH if start.month() == 12 => NaiveDate::from_ymd(start.year() + 1, 1, 1),
+        PartitionInterval::MONTH => NaiveDate::from_ymd(start.year(), start.month() + 1, 1),
+        PartitionInterval::YEAR => NaiveDate::from_ymd(start.year() + 1, 1, 1),
+    }
+}
+
+pub fn partition_table(dis_partition: &DisPartition, start: NaiveDate) -> String {
+    start.format(dis_partition.get_table_format()).to_string()
+}
+
+/// The table of the first period.
+pub fn first_partition_table(dis_partition: &DisPartition) -> RouteResult<String> {
+    Ok(partition_table(dis_partition, partition_start(dis_partition)?))
+}
+
+fn partition_start(dis_partition: &DisPartition) -> RouteResult<NaiveDate> {
+    let start = NaiveDate::parse_from_str(dis_partition.get_start(), "%Y-%m-%d").map_err(|e| {
+        Error::General(format!("invalid dis_partition start {}: {}", dis_partition.get_start(), e))
+    })?;
+    Ok(period_start(*dis_partition.get_interval(), start))
+}
+
+/// The periods with a table: from the start to the period of today, or the next one when
+/// tables are created ahead.
+fn partition_span(dis_partition: &DisPartition, today: NaiveDate) -> RouteResult<(NaiveDate, NaiveDate)> {
+    let interval = *dis_partition.get_interval();
+    let mut last = period_start(interval, today);
+    if dis_partition.is_create_ahead() {
+        last = next_period(interval, last);
+    }
+    Ok((partition_start(dis_partition)?, last))
+}
+
+fn to_date(value: &Dynamic) -> RouteResult<NaiveDate> {
+    value.clone().try_cast::<NaiveDateTime>()
+        .map(|date_time| date_time.date())
+        .ok_or_else(|| Error::General(format!("the partition key value {} is not a date", value)))
+}
+
+fn date_bound(day: NaiveDate, inclusive: bool) -> Bound {
+    (Dynamic::from(day.and_hms(0, 0, 0)), inclusive)
+}
+
+/// The partitions, as data segment and physical table, which may hold the rows of the
+/// table satisfying the conditions on its dis key: those of the values of an equality, or
+/// of the periods between the bounds of the range conditions, each on the segments its
+/// rows between the bounds may be on. Unbounded sides are limited by the periods with a table.
+pub fn partition_units(dis_table: &DisTable,
+                       dis_partition: &DisPartition,
+                       conditions: &HashMap<String, Vec<ShardingCondition>>,
+                       parameters: &[PrepareParamValue],
+                       segment_ids: &[u32],
+                       today: NaiveDate) -> RouteResult<Vec<(u32, String)>> {
+    let dis_key = dis_table.get_dis_keys()[0].as_str();
+    let dis_algorithm = dis_table.get_dis_algorithm();
+    let interval = *dis_partition.get_interval();
+    let key_conditions = conditions.get(dis_key);
+    let mut units = vec![];
+
+    let values = key_conditions.into_iter()
+        .flatten()
+        .filter_map(|condition| match condition {
+            ShardingCondition::Equal(values) => equal_values(values, parameters),
+            _ => None,
+        })
+        .min_by_key(|values| values.len());
+    if let Some(values) = values {
+        for value in values {
+            let segment_id = eval_segment(dis_algorithm, &[(dis_key, value.clone())], segment_ids)?;
+            let unit = (segment_id, partition_table(dis_partition, period_start(interval, to_date(&value)?)));
+            if !units.contains(&unit) {
+                units.push(unit);
+            }
+        }
+        units.sort();
+        return Ok(units);
+    }
+
+    let (low, high) = range_bounds(key_conditions, parameters);
+    let (first, last) = partition_span(dis_partition, today)?;
+    let mut start = match low {
+        Some((ref value, _)) => period_start(interval, to_date(value)?).max(first),
+        None => first,
+    };
+    let end = match high {
+        Some((ref value, _)) => period_start(interval, to_date(value)?).min(last),
+        None => last,
+    };
+    while start <= end {
+        let next = next_period(interval, start);
+        let mut period_low = date_bound(start, true);
+        if let Some(ref low) = low {
+            if is_tighter(low, &period_low, Ordering::Greater) {
+                period_low = low.clone();
+            }
+        }
+        let mut period_high = date_bound(next, false);
+        if let Some(ref high) = high {
+            if is_tighter(high, &period_high, Ordering::Less) {
+                period_high = high.clone();
+            }
+        }
+        // `created_at < '2021-02-01'` leaves nothing of February.
+        let empty = match compare_dynamic(&period_low.0, &period_high.0) {
+            Some(Ordering::Less) | None => false,
+            Some(Ordering::Equal) => !(period_low.1 && period_high.1),
+            Some(Ordering::Greater) => true,
+        };
+        if !empty {
+            let table = partition_table(dis_partition, start);
+            for segment_id in segments_between(dis_algorithm, dis_key, Some(&period_low), Some(&period_high), segment_ids)? {
+                units.push((segment_id, table.clone()));
+            }
+        }
+        start = next;
+    }
+    units.sort();
+    Ok(units)
+}
+
+/// `CREATE TABLE ... LIKE` the table of the current period for the next period of every
+/// partitioned table with `create_ahead` among the tables, on the segments the next period
+/// is on, unless this proxy already created it.
+pub fn create_ahead_tasks(cluster: &Cluster, table_names: &[String], today: NaiveDate) -> RouteResult<Vec<PlanTask>> {
+    let dis_rules = cluster.get_dis_rules();
+    let segment_ids = cluster.get_segments().get_data_segment_ids();
+    let created_ahead = CREATED_AHEAD.lock().unwrap();
+    let mut tasks = vec![];
+    for table in table_names {
+        let dis_table = match dis_rules.get_distributed_table(table.as_str()) {
+            Some(dis_table) => dis_table,
+            None => continue,
+        };
+        let dis_partition = match dis_table.get_dis_partition() {
+            Some(dis_partition) if dis_partition.is_create_ahead() => dis_partition,
+            _ => continue,
+        };
+        let interval = *dis_partition.get_interval();
+        let current = period_start(interval, today);
+        let next = next_period(interval, current);
+        let sql = format!("CREATE TABLE IF NOT EXISTS {} LIKE {}",
+                          partition_table(dis_partition, next), partition_table(dis_partition, current));
+        let dis_key = dis_table.get_dis_keys()[0].as_str();
+        let low = date_bound(next, true);
+        let high = date_bound(next_period(interval, next), false);
+        for segment_id in segments_between(dis_table.get_dis_algorithm(), dis_key, Some(&low), Some(&high), &segment_ids)? {
+            if !created_ahead.contains(&(segment_id, sql.clone())) {
+                tasks.push(PlanTask::new(segment_id, sql.clone(), vec![]));
+            }
+        }
+    }
+    Ok(tasks)
+}
+
+pub fn set_created_ahead(tasks: &[PlanTask]) {
+    let mut created_ahead = CREATED_AHEAD.lock().unwrap();
+    for task in tasks {
+        created_ahead.insert((task.get_segment_id(), task.get_sql().to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use sqlparser::ast::Value;
+
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::parser::sql::{ShardingCondition, ShardingValue};
+    use crate::handler::database::parser::sql::route::partition::{create_ahead_tasks, partition_units};
+
+    fn units(conditions: Vec<ShardingCondition>, today: NaiveDate) -> Vec<(u32, String)> {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let dis_table = cluster.get_dis_rules().get_distributed_table("t_order_event").unwrap();
+        let mut key_conditions = HashMap::new();
+        key_conditions.insert(String::from("created_at"), conditions);
+        partition_units(dis_table, dis_table.get_dis_partition().unwrap(), &key_conditions, &[],
+                        &cluster.get_segments().get_data_segment_ids(), today).unwrap()
+    }
+
+    #[test]
+    fn test_partition_units() {
+        let date = |s: &str| ShardingValue::Literal(Value::SingleQuotedString(s.to_string()));
+        let unit = |segment_id: u32, table: &str| (segment_id, table.to_string());
+        let today = NaiveDate::from_ymd(2021, 3, 15);
+
+        assert_eq!(vec![unit(100, "t_order_event_202011"), unit(300, "t_order_event_202203")],
+                   units(vec![ShardingCondition::Equal(vec![date("2022-03-05 10:00:00"), date("2020-11-30")])], today));
+        assert_eq!(vec![unit(100, "t_order_event_202011"), unit(100, "t_order_event_202012"), unit(200, "t_order_event_202101")],
+                   units(vec![ShardingCondition::GreaterThan(date("2020-11-15"), true),
+                              ShardingCondition::LessThan(date("2021-02-01"), false)], today));
+        // Open-ended, up to the period created ahead.
+        assert_eq!(vec![unit(200, "t_order_event_202102"), unit(200, "t_order_event_202103"), unit(200, "t_order_event_202104")],
+                   units(vec![ShardingCondition::GreaterThan(date("2021-02-01"), true)], today));
+        assert_eq!(vec![unit(100, "t_order_event_202001")],
+                   units(vec![ShardingCondition::LessThan(date("2020-02-01"), false)], today));
+    }
+
+    #[test]
+    fn test_create_ahead_tasks() {
+        let cluster = Cluster::from_file("./etc/dbmesh.yaml").unwrap();
+        let tasks = create_ahead_tasks(&cluster, &[String::from("t_order_event")], NaiveDate::from_ymd(2021, 12, 20)).unwrap();
+        let tasks: Vec<(u32, &str)> = tasks.iter().map(|t| (t.get_segment_id(), t.get_sql())).collect();
+        assert_eq!(vec![(300, "CREATE TABLE IF NOT EXISTS t_order_event_202201 LIKE t_order_event_202112")], tasks);
+    }
+}
@@ -0,0 +1,181 @@
+use sqlparser::ast::{SetVariableValue, Statement, Value};
+use sqlparser::tokenizer::{Token, Tokenizer};
+
+use data_panel_common::common::Error;
+
+use crate::handler::database::parser::sql::mysql::MySQLDialect;
+use crate::handler::database::parser::sql::route::{is_write_statement, RouteContext, RouteResult};
+use crate::session::mysql::SessionContext;
+
+/// The session option which sends the reads after a write of the session to the primaries.
+const READ_YOUR_WRITES: &str = "dbmesh_read_your_writes";
+
+/// Whether a statement has to run on the primaries of its segments: writes, locking reads,
+/// `/*+ DBMESH master */` and the statements of a transaction. Other reads may be answered
+/// by a mirror.
+pub fn is_primary_statement(route_ctx: &RouteContext<'_>, sql: &str) -> bool {
+    route_ctx.get_hint().map(|hint| hint.is_master()).unwrap_or(false)
+        || is_write_statement(route_ctx.get_statement())
+        || route_ctx.get_session_ctx().is_read_from_primary()
+        || is_locking_read(sql)
+}
+
+/// `FOR UPDATE`, `FOR SHARE` or `LOCK IN SHARE MODE`, outside of literals, quoted
+/// identifiers and comments.
+pub fn is_locking_read(sql: &str) -> bool {
+    let dialect = MySQLDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return true,
+    };
+    // Other tokens are left empty, so that the words of a clause have to follow each other.
+    let words: Vec<String> = tokens.iter()
+        .filter_map(|token| match token {
+            Token::Word(word) if word.quote_style.is_none() => Some(word.value.to_uppercase()),
+            Token::Whitespace(_) => None,
+            _ => Some(String::new()),
+        })
+        .collect();
+    let words = format!(" {} ", words.join(" "));
+    [" FOR UPDATE ", " FOR SHARE ", " LOCK IN SHARE MODE "].iter().any(|clause| words.contains(clause))
+}
+
+/// `SET dbmesh_read_your_writes = ON|OFF`, an option of the session the segments do not
+/// know. Whether the statement set it.
+pub fn set_session_option(statement: &Statement, session_ctx: &mut SessionContext) -> RouteResult<bool> {
+    match statement {
+        Statement::SetVariable { variable, value, .. } if variable.value.eq_ignore_ascii_case(READ_YOUR_WRITES) => {
+            let read_your_writes = switch(value)
+                .ok_or_else(|| Error::General(format!("{} is ON or OFF", READ_YOUR_WRITES)))?;
+            session_ctx.set_read_your_writes(read_your_writes);
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Follow the transaction and the writes of the session, which decide where its next
+/// reads go.
+pub fn track_session(statement: &Statement, session_ctx: &mut SessionContext) {
+    match statement {
+        Statement::StartTransaction { .. } => session_ctx.set_in_transaction(true),
+        Statement::Commit { .. } => session_ctx.set_in_transaction(false),
+        Statement::Rollback { savepoint: None, .. } => session_ctx.set_in_transaction(false),
+        Statement::Rollback { .. } => {}
+        Statement::SetVariable { variable, value, .. } => {
+            if variable.value.eq_ignore_ascii_case("autocommit") {
+                if let Some(autocommit) = switch(value) {
+                    session_ctx.set_autocommit(autocommit);
+                }
+            }
+        }
+        statement if is_write_statement(statement) => session_ctx.set_written(true),
+        _ => {}
+    }
+}
+
+/// How a statement ends the transaction of the session: with a commit (`true`) for `COMMIT`
+/// and the statements which commit implicitly, `START TRANSACTION` and `SET autocommit = 1`,
+/// with a rollback (`false`) for `ROLLBACK`.
+pub fn transaction_end(statement: &Statement) -> Option<bool> {
+    match statement {
+        Statement::StartTransaction { .. } | Statement::Commit { .. } => Some(true),
+        Statement::Rollback { savepoint: None, .. } => Some(false),
+        Statement::SetVariable { variable, value, .. } if variable.value.eq_ignore_ascii_case("autocommit") => {
+            switch(value).filter(|autocommit| *autocommit)
+        }
+        _ => None,
+    }
+}
+
+/// `ON`, `OFF`, `1`, `0`, `TRUE` or `FALSE`.
+fn switch(value: &[SetVariableValue]) -> Option<bool> {
+    let value = match value {
+        [SetVariableValue::Ident(ident)] => ident.value.to_uppercase(),
+        [SetVariableValue::Literal(Value::Number(n, _))] => n.clone(),
+        [SetVariableValue::Literal(Value::SingleQuotedString(s))] => s.to_uppercase(),
+        [SetVariableValue::Literal(Value::Boolean(b))] => return Some(*b),
+        _ => return None,
+    };
+    match value.as_str() {
+        "ON" | "1" | "TRUE" => Some(true),
+        "OFF" | "0" | "FALSE" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discovery::database::Cluster;
+    use crate::handler::database::parser::sql::hint::extract_hint;
+    use crate::handler::database::parser::sql::mysql::parser;
+    use crate::handler::database::parser::sql::route::RouteContext;
+    use crate::handler::database::parser::sql::route::readwrite::{is_locking_read, is_primary_statement, set_session_option, track_session, transaction_end};
+    use crate::session::mysql::SessionContext;
+
+    fn is_primary(sql: &str, session_ctx: &SessionContext) -> bool {
+        let cluster = Cluster::default();
+        let (hint, sql) = extract_hint(sql).unwrap();
+        let statement = parser(sql.clone()).pop().unwrap();
+        let mut route_ctx = RouteContext::new(&cluster, &statement, session_ctx, &[]);
+        route_ctx.set_hint(hint);
+        is_primary_statement(&route_ctx, sql.as_str())
+    }
+
+    fn track(sql: &str, session_ctx: &mut SessionContext) {
+        let statement = parser(sql.to_string()).pop().unwrap();
+        if !set_session_option(&statement, session_ctx).unwrap() {
+            track_session(&statement, session_ctx);
+        }
+    }
+
+    #[test]
+    fn test_is_locking_read() {
+        assert!(is_locking_read("SELECT * FROM t_order WHERE order_id = 1 FOR UPDATE"));
+        assert!(is_locking_read("SELECT * FROM t_order for share nowait"));
+        assert!(is_locking_read("SELECT * FROM t_order LOCK IN SHARE MODE"));
+        assert!(!is_locking_read("SELECT * FROM t_order WHERE remark = 'for update'"));
+        assert!(!is_locking_read("SELECT `for`, `update` FROM t_order /* FOR UPDATE */"));
+        assert!(!is_locking_read("SELECT for_update FROM t_order"));
+    }
+
+    #[test]
+    fn test_is_primary_statement() {
+        let mut session_ctx = SessionContext::new(1);
+        assert!(!is_primary("SELECT * FROM t_order", &session_ctx));
+        assert!(is_primary("/*+ DBMESH master */ SELECT * FROM t_order", &session_ctx));
+        assert!(is_primary("UPDATE t_order SET status = 1 WHERE order_id = 1", &session_ctx));
+
+        // In a transaction every statement goes to the primaries.
+        track("START TRANSACTION", &mut session_ctx);
+        assert!(is_primary("SELECT * FROM t_order", &session_ctx));
+        track("COMMIT", &mut session_ctx);
+        assert!(!is_primary("SELECT * FROM t_order", &session_ctx));
+        track("SET autocommit = 0", &mut session_ctx);
+        assert!(is_primary("SELECT * FROM t_order", &session_ctx));
+        track("SET autocommit = 1", &mut session_ctx);
+
+        // Reads see the session's own writes only when it asked to.
+        track("UPDATE t_order SET status = 1 WHERE order_id = 1", &mut session_ctx);
+        assert!(!is_primary("SELECT * FROM t_order", &session_ctx));
+        track("SET dbmesh_read_your_writes = ON", &mut session_ctx);
+        assert!(is_primary("SELECT * FROM t_order", &session_ctx));
+        track("SET dbmesh_read_your_writes = 0", &mut session_ctx);
+        assert!(!is_primary("SELECT * FROM t_order", &session_ctx));
+
+        let statement = parser(String::from("SET dbmesh_read_your_writes = 'maybe'")).pop().unwrap();
+        assert!(set_session_option(&statement, &mut session_ctx).is_err());
+    }
+
+    #[test]
+    fn test_transaction_end() {
+        let end = |sql: &str| transaction_end(&parser(sql.to_string()).pop().unwrap());
+        assert_eq!(Some(true), end("COMMIT"));
+        assert_eq!(Some(true), end("START TRANSACTION"));
+        assert_eq!(Some(true), end("SET autocommit = 1"));
+        assert_eq!(Some(false), end("ROLLBACK"));
+        assert_eq!(None, end("ROLLBACK TO SAVEPOINT s1"));
+        assert_eq!(None, end("SET autocommit = 0"));
+        assert_eq!(None, end("SELECT * FROM t_order"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use rhai::INT;
+
+use data_panel_common::common::Error;
+
+use crate::discovery::database::{Cluster, DisRing};
+use crate::handler::database::parser::sql::route::algorithm::hash;
+use crate::handler::database::parser::sql::route::RouteResult;
+
+lazy_static! {
+    static ref RING_CACHE: DashMap<RingKey, Arc<HashRing>> = DashMap::new();
+}
+
+/// Virtual nodes and the weight of every segment, which decide the points of a ring.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RingKey {
+    virtual_nodes: u32,
+    segments: Vec<(u32, u32)>,
+}
+
+/// Points of the data segments on a ring of hash values: a key belongs to the segment of
+/// the first point at or after the hash of the key, wrapping around after the last point.
+#[derive(Debug)]
+pub struct HashRing {
+    points: Vec<(INT, u32)>,
+}
+
+impl HashRing {
+    pub fn new(dis_ring: &DisRing, segment_ids: &[u32]) -> Self {
+        let mut points = Vec::new();
+        for segment_id in segment_ids {
+            let nodes = dis_ring.get_virtual_nodes() * dis_ring.get_weight(*segment_id);
+            for node in 0..nodes {
+                points.push((hash(format!("{}#{}", segment_id, node).as_str()), *segment_id));
+            }
+        }
+        points.sort();
+        // On a collision the smaller segment id keeps the point.
+        points.dedup_by_key(|(point, _)| *point);
+        HashRing { points }
+    }
+
+    /// The ring of the dis ring and segments, built once.
+    pub fn cached(dis_ring: &DisRing, segment_ids: &[u32]) -> Arc<HashRing> {
+        let key = RingKey {
+            virtual_nodes: dis_ring.get_virtual_nodes(),
+            segments: segment_ids.iter().map(|id| (*id, dis_ring.get_weight(*id))).collect(),
+        };
+        if let Some(ring) = RING_CACHE.get(&key) {
+            return ring.clone();
+        }
+        let ring = Arc::new(HashRing::new(dis_ring, segment_ids));
+        RING_CACHE.insert(key, ring.clone());
+        ring
+    }
+
+    pub fn get_points(&self) -> &[(INT, u32)] {
+        self.points.as_slice()
+    }
+
+    /// The segment of a position on the ring, `None` for a ring without points.
+    pub fn segment(&self, position: INT) -> Option<u32> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let index = match self.points.binary_search_by(|(point, _)| point.cmp(&position)) {
+            Ok(index) => index,
+            Err(index) => index % self.points.len(),
+        };
+        Some(self.points[index].1)
+    }
+}
+
+/// The position of the result of a dis expression on the ring. The result is hashed again,
+/// expressions like `id` would crowd the keys into one arc otherwise.
+pub fn ring_position(result: INT) -> INT {
+    hash(result.to_string().as_str())
+}
+
+/// The hash values in `(start, end]` move from segment `from` to segment `to`. A range with
+/// `start >= end` wraps around: it holds the values above `start` and those up to `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedRange {
+    start: INT,
+    end: INT,
+    from: u32,
+    to: u32,
+}
+
+impl MovedRange {
+    pub fn get_start(&self) -> INT {
+        self.start
+    }
+
+    pub fn get_end(&self) -> INT {
+        self.end
+    }
+
+    pub fn get_from(&self) -> u32 {
+        self.from
+    }
+
+    pub fn get_to(&self) -> u32 {
+        self.to
+    }
+
+    /// Whether a key at this position on the ring moves.
+    pub fn contains(&self, position: INT) -> bool {
+        if self.start < self.end {
+            position > self.start && position <= self.end
+        } else {
+            position > self.start || position <= self.end
+        }
+    }
+}
+
+/// The ranges of the ring whose keys belong to another segment after the change. Between
+/// two adjacent points of either ring every position has the same segment in both, so
+/// comparing the segments of these arcs finds every key that moves.
+pub fn moved_ranges(before: &HashRing, after: &HashRing) -> Vec<MovedRange> {
+    let mut boundaries: Vec<INT> = before.points.iter().chain(after.points.iter())
+        .map(|(point, _)| *point)
+        .collect();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut moved: Vec<MovedRange> = Vec::new();
+    for (index, end) in boundaries.iter().enumerate() {
+        // The first arc wraps around from the last boundary.
+        let start = if index == 0 { boundaries[boundaries.len() - 1] } else { boundaries[index - 1] };
+        let (from, to) = match (before.segment(*end), after.segment(*end)) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            _ => continue,
+        };
+        match moved.last_mut() {
+            Some(last) if last.end == start && last.from == from && last.to == to => last.end = *end,
+            _ => moved.push(MovedRange { start, end: *end, from, to }),
+        }
+    }
+    moved
+}
+
+/// The ranges of a consistent-hash table which move from the segments of one cluster config
+/// to those of another, to plan the migration of their rows.
+pub fn table_moved_ranges(table: &str, before: &Cluster, after: &Cluster) -> RouteResult<Vec<MovedRange>> {
+    let ring = |cluster: &Cluster| -> RouteResult<HashRing> {
+        let dis_ring = cluster.get_dis_rules().get_distributed_table(table)
+            .and_then(|dis_table| dis_table.get_dis_algorithm().get_dis_ring())
+            .ok_or_else(|| Error::General(format!("{} has no dis_ring", table)))?;
+        Ok(HashRing::new(dis_ring, &cluster.get_segments().get_data_segment_ids()))
+    };
+    Ok(moved_ranges(&ring(before)?, &ring(after)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rhai::INT;
+
+    use crate::discovery::database::DisRing;
+    use crate::handler::database::parser::sql::route::ring::{HashRing, moved_ranges, ring_position};
+
+    fn dis_ring(weights: &[(u32, u32)]) -> DisRing {
+        let weights: HashMap<u32, u32> = weights.iter().copied().collect();
+        serde_yaml::from_str(&format!("{{ virtual_nodes: 160, weights: {:?} }}", weights)).unwrap()
+    }
+
+    #[test]
+    fn test_hash_ring() {
+        let ring = HashRing::new(&dis_ring(&[(300, 2)]), &[100, 200, 300]);
+        assert_eq!(4 * 160, ring.get_points().len());
+
+        let mut counts: HashMap<u32, u32> = HashMap::new();
+        for key in 0..10000 as INT {
+            *counts.entry(ring.segment(ring_position(key)).unwrap()).or_insert(0) += 1;
+        }
+        assert!(counts[&100] > 1500 && counts[&200] > 1500);
+        assert!(counts[&300] > counts[&100] && counts[&300] > counts[&200]);
+    }
+
+    #[test]
+    fn test_moved_ranges() {
+        let before = HashRing::new(&dis_ring(&[]), &[100, 200, 300]);
+        let after = HashRing::new(&dis_ring(&[]), &[100, 200, 300, 400]);
+        let moved = moved_ranges(&before, &after);
+        assert!(!moved.is_empty());
+        assert!(moved.iter().all(|range| range.get_to() == 400));
+
+        let mut moved_keys = 0;
+        for key in 0..10000 as INT {
+            let position = ring_position(key);
+            let (from, to) = (before.segment(position).unwrap(), after.segment(position).unwrap());
+            let range = moved.iter().find(|range| range.contains(position));
+            if from == to {
+                assert!(range.is_none());
+            } else {
+                moved_keys += 1;
+                assert_eq!(Some((from, to)), range.map(|r| (r.get_from(), r.get_to())));
+            }
+        }
+        // About a quarter of the keys, far from the three quarters a modulo moves.
+        assert!(moved_keys > 1500 && moved_keys < 3500);
+    }
+}
@@ -4,6 +4,9 @@ use tokio_util::codec::LengthDelimitedCodec;
 
 use data_panel_common::service::ServiceCodec;
 
+/// The longest payload of one packet, longer payloads are split into several packets.
+pub const MAX_PAYLOAD_LENGTH: usize = 0xff_ffff;
+
 pub struct MySQLCodec {}
 
 impl ServiceCodec for MySQLCodec {
@@ -12,6 +15,7 @@ impl ServiceCodec for MySQLCodec {
             .length_field_offset(0)
             .length_field_length(3)
             .length_adjustment(1)
+            .max_frame_length(MAX_PAYLOAD_LENGTH + 1)
             .little_endian()
             .num_skip(0)
             .new_write(io)
@@ -22,6 +26,7 @@ impl ServiceCodec for MySQLCodec {
             .length_field_offset(0)
             .length_field_length(3)
             .length_adjustment(4)
+            .max_frame_length(MAX_PAYLOAD_LENGTH + 4)
             .little_endian()
             .num_skip(0)
             .new_read(io)
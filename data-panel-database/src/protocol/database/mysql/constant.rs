@@ -283,6 +283,7 @@ pub enum MySQLAuthenticationMethod {
     ClearTextAuthentication,
     WindowsNativeAuthentication,
     SHA256,
+    CachingSHA2Password,
 }
 
 impl MySQLAuthenticationMethod {
@@ -293,6 +294,7 @@ impl MySQLAuthenticationMethod {
             MySQLAuthenticationMethod::ClearTextAuthentication => "mysql_clear_password",
             MySQLAuthenticationMethod::WindowsNativeAuthentication => "authentication_windows_client",
             MySQLAuthenticationMethod::SHA256 => "sha256_password",
+            MySQLAuthenticationMethod::CachingSHA2Password => "caching_sha2_password",
         }
     }
 }
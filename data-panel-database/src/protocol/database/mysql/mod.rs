@@ -1,3 +1,4 @@
 pub mod codec;
 pub mod constant;
-pub mod packet;
\ No newline at end of file
+pub mod packet;
+pub mod resultset;
\ No newline at end of file
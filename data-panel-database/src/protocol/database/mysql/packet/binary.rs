@@ -28,12 +28,23 @@ impl MySQLComStmtPreparePacket {
         self.sql.clone()
     }
 
+    pub fn set_sql(&mut self, sql: Vec<u8>) {
+        self.sql = sql;
+    }
+
     pub fn get_command_type(&self) -> u8 {
         self.command_type
     }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLComStmtPreparePacket {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_u8(this.command_type);
+        payload.put_slice(this.sql.as_slice());
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         let bytes = payload.get_remaining_bytes();
         this.sql = Vec::from(bytes.as_slice());
@@ -80,6 +91,18 @@ impl MySQLComStmtPrepareOKPacket {
             warning_count,
         }
     }
+
+    pub fn get_statement_id(&self) -> u32 {
+        self.statement_id
+    }
+
+    pub fn get_columns_count(&self) -> u16 {
+        self.columns_count
+    }
+
+    pub fn get_parameters_count(&self) -> u16 {
+        self.parameters_count
+    }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLComStmtPrepareOKPacket {
@@ -94,6 +117,17 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
         payload.put_u16_le(this.warning_count);
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.sequence_id = header.sequence_id;
+        this.status = (payload.get_uint(1) & 0xff) as u8;
+        this.statement_id = payload.get_uint_le(4) as u32;
+        this.columns_count = payload.get_uint_le(2) as u16;
+        this.parameters_count = payload.get_uint_le(2) as u16;
+        payload.advance(1); // reserved
+        this.warning_count = payload.get_uint_le(2) as u16;
+        this
+    }
 }
 
 impl MySQLPacket for MySQLComStmtPrepareOKPacket {
@@ -147,6 +181,10 @@ impl MySQLComStmtExecutePacket {
         self.statement_id
     }
 
+    pub fn set_statement_id(&mut self, statement_id: u32) {
+        self.statement_id = statement_id;
+    }
+
     pub fn get_command_type(&self) -> u8 {
         self.command_type
     }
@@ -154,9 +192,41 @@ impl MySQLComStmtExecutePacket {
     pub fn get_parameters(&self) -> Vec<PrepareParamValue> {
         self.parameters.clone()
     }
+
+    pub fn set_parameters(&mut self, parameters: Vec<PrepareParamValue>) {
+        self.parameters = parameters;
+    }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLComStmtExecutePacket {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_u8(this.command_type);
+        payload.put_u32_le(this.statement_id);
+        payload.put_u8(this.flags as u8); // CURSOR_TYPE_NO_CURSOR
+        payload.put_u32_le(1); // iteration count
+        if !this.parameters.is_empty() {
+            let mut null_bit_map = vec![0u8; (this.parameters.len() + 7) / 8];
+            for (i, parameter) in this.parameters.iter().enumerate() {
+                if *parameter == PrepareParamValue::NULL {
+                    null_bit_map[i / 8] |= 1 << (i % 8);
+                }
+            }
+            payload.put_slice(null_bit_map.as_slice());
+            payload.put_u8(MySQLNewParametersBoundFlag::ParameterTypeExist as u8);
+            for parameter in this.parameters.iter() {
+                let (column_type, unsigned) = parameter_type(parameter);
+                payload.put_u8(column_type as u8);
+                payload.put_u8(if unsigned { 0x80 } else { 0x00 });
+            }
+            // NULL values are only in the bitmap.
+            for parameter in this.parameters.iter().filter(|parameter| **parameter != PrepareParamValue::NULL) {
+                write_bin(parameter, payload);
+            }
+        }
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         this.sequence_id = header.sequence_id;
         this.statement_id = payload.get_uint_le(4) as u32;
@@ -297,9 +367,20 @@ impl MySQLComStmtClosePacket {
     pub fn get_statement_id(&self) -> u32 {
         self.statement_id
     }
+
+    pub fn set_statement_id(&mut self, statement_id: u32) {
+        self.statement_id = statement_id;
+    }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLComStmtClosePacket {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_u8(this.command_type);
+        payload.put_u32_le(this.statement_id);
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         this.sequence_id = header.sequence_id;
         this.statement_id = payload.get_uint_le(4) as u32;
@@ -356,7 +437,7 @@ impl MySQLPacket for MySQLComStmtResetPacket {
 }
 
 /// The `Value` is also used as a parameter to a prepared statement.
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum PrepareParamValue {
     NULL,
     Bytes(Vec<u8>),
@@ -431,11 +512,13 @@ pub fn read_bin(payload: &mut MySQLPacketPayload, column_type: MySQLColumnType,
                 year = payload.get_uint_le(2) as u16;
                 month = (payload.get_uint(1) & 0xff) as u8;
                 day = (payload.get_uint(1) & 0xff) as u8;
-            } else if len >= 7u8 {
+            }
+            if len >= 7u8 {
                 hour = (payload.get_uint(1) & 0xff) as u8;
                 minute = (payload.get_uint(1) & 0xff) as u8;
                 second = (payload.get_uint(1) & 0xff) as u8;
-            } else if len == 11u8 {
+            }
+            if len == 11u8 {
                 micro_second = payload.get_uint_le(4) as u32;
             }
             Ok(PrepareParamValue::Date(year, month, day, hour, minute, second, micro_second))
@@ -454,7 +537,8 @@ pub fn read_bin(payload: &mut MySQLPacketPayload, column_type: MySQLColumnType,
                 hours = (payload.get_uint(1) & 0xff) as u8;
                 minutes = (payload.get_uint(1) & 0xff) as u8;
                 seconds = (payload.get_uint(1) & 0xff) as u8;
-            } else if len == 12u8 {
+            }
+            if len == 12u8 {
                 micro_seconds = payload.get_uint_le(4) as u32;
             }
             Ok(PrepareParamValue::Time(
@@ -471,6 +555,20 @@ pub fn read_bin(payload: &mut MySQLPacketPayload, column_type: MySQLColumnType,
     }
 }
 
+/// The type of a parameter of COM_STMT_EXECUTE and whether it is unsigned.
+pub fn parameter_type(value: &PrepareParamValue) -> (MySQLColumnType, bool) {
+    match value {
+        PrepareParamValue::NULL => (MySQLColumnType::MysqlTypeNull, false),
+        PrepareParamValue::Bytes(_) => (MySQLColumnType::MysqlTypeVarString, false),
+        PrepareParamValue::Int(_) => (MySQLColumnType::MysqlTypeLonglong, false),
+        PrepareParamValue::UInt(_) => (MySQLColumnType::MysqlTypeLonglong, true),
+        PrepareParamValue::Float(_) => (MySQLColumnType::MysqlTypeFloat, false),
+        PrepareParamValue::Double(_) => (MySQLColumnType::MysqlTypeDouble, false),
+        PrepareParamValue::Date(..) => (MySQLColumnType::MysqlTypeDatetime, false),
+        PrepareParamValue::Time(..) => (MySQLColumnType::MysqlTypeTime, false),
+    }
+}
+
 /// Writes MySql's value in binary value format.
 pub fn write_bin(value: &PrepareParamValue, payload: &mut MySQLPacketPayload) {
     match *value {
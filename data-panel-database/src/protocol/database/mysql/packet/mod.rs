@@ -146,6 +146,11 @@ impl MySQLPacketPayload {
         self.bytes_mut.get_int(n)
     }
 
+    /// The next byte, left in the buffer.
+    pub fn peek_u8(&self) -> Option<u8> {
+        self.bytes_mut.first().cloned()
+    }
+
     pub fn advance(&mut self, n: usize) {
         self.bytes_mut.advance(n);
     }
@@ -341,6 +346,31 @@ impl MySQLHandshakePacket {
     }
 }
 
+impl MySQLHandshakePacket {
+    pub fn get_server_version(&self) -> &str {
+        self.server_version.as_str()
+    }
+
+    pub fn get_thread_id(&self) -> u32 {
+        self.thread_id
+    }
+
+    pub fn get_capability_flags(&self) -> MySQLCapabilityFlag {
+        self.capability_flags
+    }
+
+    /// The whole scramble, seed 1 followed by seed 2.
+    pub fn get_seed(&self) -> Vec<u8> {
+        let mut seed = self.seed1.clone();
+        seed.extend_from_slice(self.seed2.as_slice());
+        seed
+    }
+
+    pub fn get_auth_plugin_name(&self) -> &str {
+        self.auth_plugin_name.as_str()
+    }
+}
+
 impl MySQLPacket for MySQLHandshakePacket {
     fn get_sequence_id(&self) -> u32 {
         0
@@ -381,6 +411,36 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
 
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.protocol_version = (payload.get_uint(1) & 0xff) as u8;
+        this.server_version = payload.get_string_nul();
+        this.thread_id = payload.get_uint_le(4) as u32;
+        this.seed1 = payload.get_string_fix_length(8);
+        payload.advance(1); // filler
+        let capability_flags_lower = payload.get_uint_le(2) as u32;
+        this.character_set = (payload.get_uint(1) & 0xff) as u8;
+        this.status_flag = payload.get_uint_le(2) as u32;
+        let capability_flags_upper = payload.get_uint_le(2) as u32;
+        this.capability_flags = MySQLCapabilityFlag::from_bits_truncate(capability_flags_lower | (capability_flags_upper << 16));
+        let seed_length = (payload.get_uint(1) & 0xff) as u32;
+        payload.advance(10); // reserved
+        this.seed2 = if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_SECURE_CONNECTION) {
+            // At least 13 bytes, the last one is a NUL.
+            let mut seed2 = payload.get_string_fix_length(std::cmp::max(13, seed_length.saturating_sub(8)));
+            seed2.pop();
+            seed2
+        } else {
+            vec![]
+        };
+        this.auth_plugin_name = if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_PLUGIN_AUTH) {
+            let name = payload.get_remaining_bytes();
+            String::from_utf8_lossy(name.split(|b| *b == NUL).next().unwrap_or(&[])).to_string()
+        } else {
+            String::from("")
+        };
+        this
+    }
 }
 
 /**
@@ -404,6 +464,17 @@ impl MySQLAuthSwitchRequestPacket {
             auth_plugin_name: MySQLAuthenticationMethod::SecurePasswordAuthentication.value().to_string(),
         }
     }
+
+    pub fn get_auth_plugin_name(&self) -> &str {
+        self.auth_plugin_name.as_str()
+    }
+
+    /// The whole scramble, seed 1 followed by seed 2.
+    pub fn get_seed(&self) -> Vec<u8> {
+        let mut seed = self.seed1.clone();
+        seed.extend_from_slice(self.seed2.as_slice());
+        seed
+    }
 }
 
 impl MySQLPacket for MySQLAuthSwitchRequestPacket {
@@ -421,6 +492,19 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
         payload.put_string_with_nul(this.seed2.as_slice());
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.sequence_id = header.sequence_id;
+        payload.advance(1); // 0xfe
+        this.auth_plugin_name = payload.get_string_nul();
+        let mut seed = payload.get_remaining_bytes();
+        if seed.last() == Some(&NUL) {
+            seed.pop();
+        }
+        this.seed2 = seed.split_off(std::cmp::min(8, seed.len()));
+        this.seed1 = seed;
+        this
+    }
 }
 
 /**
@@ -453,6 +537,26 @@ impl MySQLHandshakeResponse41Packet {
         }
     }
 
+    /// The response of the proxy to the handshake of a data segment.
+    pub fn new_with_auth(sequence_id: u32,
+                         capability_flags: MySQLCapabilityFlag,
+                         character_set: u8,
+                         user_name: String,
+                         auth_response: Vec<u8>,
+                         database: String,
+                         auth_plugin_name: String) -> Self {
+        MySQLHandshakeResponse41Packet {
+            sequence_id,
+            max_packet_size: 1 << 24,
+            character_set,
+            user_name,
+            auth_response,
+            capability_flags,
+            database,
+            auth_plugin_name,
+        }
+    }
+
     pub fn get_user_name(&self) -> String {
         self.user_name.clone()
     }
@@ -475,6 +579,31 @@ impl MySQLHandshakeResponse41Packet {
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLHandshakeResponse41Packet {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_u32_le(this.capability_flags.bits());
+        payload.put_u32_le(this.max_packet_size);
+        payload.put_u8(this.character_set);
+        let reserved: [u8; 23] = [0; 23];
+        payload.put_slice(&reserved);
+        payload.put_string_with_nul(this.user_name.as_bytes());
+        if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA) {
+            payload.put_string_lenenc(this.auth_response.as_slice());
+        } else if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_SECURE_CONNECTION) {
+            payload.put_u8(this.auth_response.len() as u8);
+            payload.put_slice(this.auth_response.as_slice());
+        } else {
+            payload.put_string_with_nul(this.auth_response.as_slice());
+        }
+        if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_CONNECT_WITH_DB) {
+            payload.put_string_with_nul(this.database.as_bytes());
+        }
+        if this.capability_flags.contains(MySQLCapabilityFlag::CLIENT_PLUGIN_AUTH) {
+            payload.put_string_with_nul(this.auth_plugin_name.as_bytes());
+        }
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         this.sequence_id = header.sequence_id;
         this.capability_flags = MySQLCapabilityFlag::from_bits(payload.get_uint_le(4) as u32).unwrap();
@@ -533,12 +662,25 @@ impl MySQLAuthSwitchResponsePacket {
         }
     }
 
+    pub fn new_with_auth_response(sequence_id: u32, auth_response: Vec<u8>) -> Self {
+        MySQLAuthSwitchResponsePacket {
+            sequence_id,
+            auth_response,
+        }
+    }
+
     pub fn get_auth_response(&self) -> Vec<u8> {
         self.auth_response.clone()
     }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLAuthSwitchResponsePacket {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_slice(this.auth_response.as_slice());
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         this.sequence_id = header.sequence_id;
         this.auth_response = payload.get_remaining_bytes();
@@ -580,6 +722,12 @@ impl MySQLEOFPacket {
     }
 }
 
+impl MySQLEOFPacket {
+    pub fn get_status_flags(&self) -> u16 {
+        self.status_flags
+    }
+}
+
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLEOFPacket {
     fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
         payload.put_u8(this.get_sequence_id() as u8); // seq
@@ -589,6 +737,14 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
 
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.sequence_id = header.sequence_id;
+        this.header = (payload.get_uint(1) & 0xff) as u8;
+        this.warnings = payload.get_uint_le(2) as u16;
+        this.status_flags = payload.get_uint_le(2) as u16;
+        this
+    }
 }
 
 impl MySQLPacket for MySQLEOFPacket {
@@ -629,6 +785,20 @@ impl MySQLOKPacket {
     }
 }
 
+impl MySQLOKPacket {
+    pub fn get_affected_rows(&self) -> u64 {
+        self.affected_rows
+    }
+
+    pub fn get_last_insert_id(&self) -> u64 {
+        self.last_insert_id
+    }
+
+    pub fn get_status_flag(&self) -> u32 {
+        self.status_flag
+    }
+}
+
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLOKPacket {
     fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
         payload.put_u8(this.get_sequence_id() as u8); // seq
@@ -644,6 +814,17 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
 
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.sequence_id = header.sequence_id;
+        this.header = (payload.get_uint(1) & 0xff) as u8;
+        this.affected_rows = payload.get_int_lenenc();
+        this.last_insert_id = payload.get_int_lenenc();
+        this.status_flag = payload.get_uint_le(2) as u32;
+        this.warnings = payload.get_uint_le(2) as u32;
+        this.info = String::from_utf8_lossy(payload.get_remaining_bytes().as_slice()).to_string();
+        this
+    }
 }
 
 impl MySQLPacket for MySQLOKPacket {
@@ -677,13 +858,27 @@ impl MySQLErrPacket {
             header: 0xff,
             sql_state_marker: "#".to_string(),
             sequence_id,
-            error_code: 0,
+            error_code,
             sql_state: sql_state,
             error_message: error_message,
         }
     }
 }
 
+impl MySQLErrPacket {
+    pub fn get_error_code(&self) -> u32 {
+        self.error_code
+    }
+
+    pub fn get_sql_state(&self) -> &str {
+        self.sql_state.as_str()
+    }
+
+    pub fn get_error_message(&self) -> &str {
+        self.error_message.as_str()
+    }
+}
+
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLErrPacket {
     fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
         payload.put_u8(this.get_sequence_id() as u8); // seq
@@ -696,6 +891,22 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
 
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        this.sequence_id = header.sequence_id;
+        this.header = (payload.get_uint(1) & 0xff) as u8;
+        this.error_code = payload.get_uint_le(2) as u32;
+        let rest = payload.get_remaining_bytes();
+        // The SQL state follows a `#` since 4.1.
+        let (sql_state, error_message) = if rest.first() == Some(&b'#') && rest.len() >= 6 {
+            (&rest[1..6], &rest[6..])
+        } else {
+            (&b"HY000"[..], &rest[..])
+        };
+        this.sql_state = String::from_utf8_lossy(sql_state).to_string();
+        this.error_message = String::from_utf8_lossy(error_message).to_string();
+        this
+    }
 }
 
 impl MySQLPacket for MySQLErrPacket {
@@ -793,6 +1004,48 @@ impl MySQLColumnDefinition41Packet {
     }
 }
 
+impl MySQLColumnDefinition41Packet {
+    pub fn get_character_set(&self) -> u16 {
+        self.character_set
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub fn get_schema(&self) -> &str {
+        self.schema.as_str()
+    }
+
+    pub fn get_table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    pub fn get_org_table(&self) -> &str {
+        self.org_table.as_str()
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn get_org_name(&self) -> &str {
+        self.org_name.as_str()
+    }
+
+    pub fn get_column_length(&self) -> u32 {
+        self.column_length
+    }
+
+    pub fn get_column_type(&self) -> u8 {
+        self.column_type
+    }
+
+    pub fn get_decimals(&self) -> u8 {
+        self.decimals
+    }
+}
+
 impl MySQLPacket for MySQLColumnDefinition41Packet {
     fn get_sequence_id(&self) -> u32 {
         self.sequence_id
@@ -820,6 +1073,24 @@ impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for M
 
         payload
     }
+
+    fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
+        let lenenc_string = |payload: &mut MySQLPacketPayload| String::from_utf8_lossy(payload.get_string_lenenc().as_slice()).to_string();
+        this.sequence_id = header.sequence_id;
+        this.catalog = lenenc_string(payload);
+        this.schema = lenenc_string(payload);
+        this.table = lenenc_string(payload);
+        this.org_table = lenenc_string(payload);
+        this.name = lenenc_string(payload);
+        this.org_name = lenenc_string(payload);
+        this.next_length = payload.get_int_lenenc() as u8;
+        this.character_set = payload.get_uint_le(2) as u16;
+        this.column_length = payload.get_uint_le(4) as u32;
+        this.column_type = (payload.get_uint(1) & 0xff) as u8;
+        this.flags = payload.get_uint_le(2) as u16;
+        this.decimals = (payload.get_uint(1) & 0xff) as u8;
+        this
+    }
 }
 
 /**
@@ -26,12 +26,23 @@ impl MySQLComQueryPacket {
         self.sql.clone()
     }
 
+    pub fn set_sql(&mut self, sql: Vec<u8>) {
+        self.sql = sql;
+    }
+
     pub fn get_command_type(&self) -> u8 {
         self.command_type
     }
 }
 
 impl DatabasePacket<MySQLPacketHeader, MySQLPacketPayload, SessionContext> for MySQLComQueryPacket {
+    fn encode<'p, 'd>(this: &'d mut Self, payload: &'p mut MySQLPacketPayload) -> &'p mut MySQLPacketPayload {
+        payload.put_u8(this.get_sequence_id() as u8); // seq
+        payload.put_u8(this.command_type);
+        payload.put_slice(this.sql.as_slice());
+        payload
+    }
+
     fn decode<'p, 'd>(this: &'d mut Self, header: &'p MySQLPacketHeader, payload: &'p mut MySQLPacketPayload, session_ctx: &mut SessionContext) -> &'d mut Self {
         let bytes = payload.get_remaining_bytes();
         this.sql = Vec::from(bytes.as_slice());
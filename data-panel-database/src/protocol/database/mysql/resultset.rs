@@ -0,0 +1,209 @@
+use crate::protocol::database::mysql::constant::{CHARSET, MySQLColumnFlags, MySQLColumnType};
+use crate::protocol::database::mysql::packet::binary::PrepareParamValue;
+use crate::protocol::database::mysql::packet::MySQLColumnDefinition41Packet;
+
+/// A value of a result set row. Rows of the binary protocol carry the same values as the
+/// parameters of a prepared statement, rows of the text protocol only `Bytes` and `NULL`.
+pub type Value = PrepareParamValue;
+
+/// A column of a result set, as the column definition packets describe it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    schema: String,
+    table: String,
+    org_table: String,
+    name: String,
+    org_name: String,
+    character_set: u16,
+    column_length: u32,
+    column_type: MySQLColumnType,
+    flags: MySQLColumnFlags,
+    decimals: u8,
+}
+
+impl Column {
+    pub fn new(column_type: MySQLColumnType) -> Self {
+        Column {
+            schema: String::new(),
+            table: String::new(),
+            org_table: String::new(),
+            name: String::new(),
+            org_name: String::new(),
+            character_set: CHARSET as u16,
+            column_length: 0,
+            column_type,
+            flags: MySQLColumnFlags::empty(),
+            decimals: 0,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn with_flags(mut self, flags: MySQLColumnFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_character_set(mut self, character_set: u16) -> Self {
+        self.character_set = character_set;
+        self
+    }
+
+    pub fn get_schema(&self) -> &str {
+        self.schema.as_str()
+    }
+
+    pub fn get_table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    pub fn get_org_table(&self) -> &str {
+        self.org_table.as_str()
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn get_org_name(&self) -> &str {
+        self.org_name.as_str()
+    }
+
+    pub fn get_character_set(&self) -> u16 {
+        self.character_set
+    }
+
+    pub fn get_column_length(&self) -> u32 {
+        self.column_length
+    }
+
+    pub fn get_column_type(&self) -> MySQLColumnType {
+        self.column_type
+    }
+
+    pub fn get_flags(&self) -> MySQLColumnFlags {
+        self.flags
+    }
+
+    pub fn get_decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        self.flags.contains(MySQLColumnFlags::UNSIGNED_FLAG)
+    }
+
+    pub fn to_definition_packet(&self, sequence_id: u32) -> MySQLColumnDefinition41Packet {
+        MySQLColumnDefinition41Packet::new(
+            sequence_id,
+            self.character_set,
+            self.flags.bits(),
+            self.schema.clone(),
+            self.table.clone(),
+            self.org_table.clone(),
+            self.name.clone(),
+            self.org_name.clone(),
+            self.column_length,
+            self.column_type as u8,
+            self.decimals,
+        )
+    }
+}
+
+impl From<&MySQLColumnDefinition41Packet> for Column {
+    fn from(packet: &MySQLColumnDefinition41Packet) -> Self {
+        // Types newer servers add are read as strings.
+        let column_type = match packet.get_column_type() {
+            column_type @ 0x00..=0x13 | column_type @ 0xf5..=0xff => MySQLColumnType::from(column_type),
+            _ => MySQLColumnType::MysqlTypeString,
+        };
+        Column {
+            schema: packet.get_schema().to_string(),
+            table: packet.get_table().to_string(),
+            org_table: packet.get_org_table().to_string(),
+            name: packet.get_name().to_string(),
+            org_name: packet.get_org_name().to_string(),
+            character_set: packet.get_character_set(),
+            column_length: packet.get_column_length(),
+            column_type,
+            flags: MySQLColumnFlags::from_bits_truncate(packet.get_flags()),
+            decimals: packet.get_decimals(),
+        }
+    }
+}
+
+impl PrepareParamValue {
+    /// The value as a row of the text protocol carries it.
+    pub fn to_text(&self) -> Vec<u8> {
+        match self {
+            PrepareParamValue::Bytes(bytes) => bytes.clone(),
+            PrepareParamValue::Date(..) | PrepareParamValue::Time(..) => self.to_sql().trim_matches('\'').as_bytes().to_vec(),
+            _ => self.to_sql().into_bytes(),
+        }
+    }
+
+    /// The value as a SQL literal, strings quoted without backslash escapes.
+    pub fn to_sql(&self) -> String {
+        match self {
+            PrepareParamValue::NULL => String::from("NULL"),
+            PrepareParamValue::Bytes(bytes) => format!("'{}'", String::from_utf8_lossy(bytes).replace('\'', "''")),
+            PrepareParamValue::Int(int) => int.to_string(),
+            PrepareParamValue::UInt(uint) => uint.to_string(),
+            PrepareParamValue::Float(f) => f.to_string(),
+            PrepareParamValue::Double(f) => f.to_string(),
+            PrepareParamValue::Date(year, month, day, hour, minutes, seconds, micro_seconds) => {
+                format!("'{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}'", year, month, day, hour, minutes, seconds, micro_seconds)
+            }
+            PrepareParamValue::Time(is_negative, days, hours, minutes, seconds, micro_seconds) => {
+                let sign = if *is_negative { "-" } else { "" };
+                let hours = *days * 24 + *hours as u32;
+                format!("'{}{:03}:{:02}:{:02}.{:06}'", sign, hours, minutes, seconds, micro_seconds)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::database::mysql::constant::{MySQLColumnFlags, MySQLColumnType};
+    use crate::protocol::database::mysql::packet::MySQLColumnDefinition41Packet;
+    use crate::protocol::database::mysql::resultset::{Column, Value};
+
+    #[test]
+    fn test_column_from_definition_packet() {
+        let packet = MySQLColumnDefinition41Packet::new(2, 63, 0x20, String::from("martlet"), String::from("o"),
+                                                         String::from("t_order_0"), String::from("id"), String::from("order_id"), 20, 0x08, 0);
+        let column = Column::from(&packet);
+        assert_eq!(("martlet", "o", "t_order_0", "id", "order_id"),
+                   (column.get_schema(), column.get_table(), column.get_org_table(), column.get_name(), column.get_org_name()));
+        assert_eq!(MySQLColumnType::MysqlTypeLonglong, column.get_column_type());
+        assert!(column.is_unsigned());
+
+        let packet = column.to_definition_packet(3);
+        assert_eq!((63, 0x20, 20, 0x08), (packet.get_character_set(), packet.get_flags(), packet.get_column_length(), packet.get_column_type()));
+
+        let unknown = MySQLColumnDefinition41Packet::new(2, 63, 0, String::new(), String::new(), String::new(),
+                                                         String::from("v"), String::new(), 0, 0xf2, 0);
+        assert_eq!(MySQLColumnType::MysqlTypeString, Column::from(&unknown).get_column_type());
+        assert_eq!(MySQLColumnFlags::empty(), Column::from(&unknown).get_flags());
+    }
+
+    #[test]
+    fn test_to_sql() {
+        assert_eq!("NULL", Value::NULL.to_sql());
+        assert_eq!("'it''s'", Value::Bytes(b"it's".to_vec()).to_sql());
+        assert_eq!("-1", Value::Int(-1).to_sql());
+        assert_eq!("'2021-01-02 03:04:05.000006'", Value::Date(2021, 1, 2, 3, 4, 5, 6).to_sql());
+        assert_eq!("'-025:00:01.000000'", Value::Time(true, 1, 1, 0, 1, 0).to_sql());
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(b"it's".to_vec(), Value::Bytes(b"it's".to_vec()).to_text());
+        assert_eq!(b"0".to_vec(), Value::Int(0).to_text());
+        assert_eq!(b"2021-01-02 03:04:05.000006".to_vec(), Value::Date(2021, 1, 2, 3, 4, 5, 6).to_text());
+    }
+}
@@ -11,6 +11,7 @@ use data_panel_common::service::{Service, ServiceHandler};
 use data_panel_common::service::io::Channel;
 
 use crate::handler::database::mysql::{AuthMethodMismatchHandler, AuthPhaseFastPathHandler, CommandHandler, CommandRootHandler, HandshakeHandler};
+use crate::handler::database::mysql::rdbc::end_session_transaction;
 use crate::protocol::database::{DatabasePacket, PacketPayload};
 use crate::protocol::database::mysql::codec::MySQLCodec;
 use crate::protocol::database::mysql::constant::MySQLConnectionPhase;
@@ -49,7 +50,7 @@ impl<'a> MySQLIOContext<'a> {
 
     pub async fn handshake(&mut self) -> Result<(), futures::io::Error> {
         self.session_ctx.set_connection_phase(MySQLConnectionPhase::AuthPhaseFastPath);
-        self.channel.send(HandshakeHandler::handle(None, None, &mut self.session_ctx)).await
+        self.channel.send_stream(HandshakeHandler::handle(None, None, &mut self.session_ctx).await).await
     }
 
     pub async fn auth(&mut self, mut payload: BytesMut) -> Result<(), futures::io::Error> {
@@ -62,8 +63,8 @@ impl<'a> MySQLIOContext<'a> {
             MySQLConnectionPhase::InitialHandshake => { Ok(()) }
             MySQLConnectionPhase::AuthPhaseFastPath => {
                 let handshake_response41_payload = MySQLPacketPayload::new_with_payload(payload);
-                if let Some(payloads) = AuthPhaseFastPathHandler::handle(Some(header), Some(handshake_response41_payload), &mut self.session_ctx) {
-                    self.channel.send(Option::from(payloads)).await;
+                if let Some(payloads) = AuthPhaseFastPathHandler::handle(Some(header), Some(handshake_response41_payload), &mut self.session_ctx).await {
+                    self.channel.send_stream(Some(payloads)).await;
                 }
                 if self.session_ctx.get_connection_phase() == MySQLConnectionPhase::AuthenticationMethodMismatch {
                     Err(())
@@ -73,7 +74,7 @@ impl<'a> MySQLIOContext<'a> {
             }
             MySQLConnectionPhase::AuthenticationMethodMismatch => {
                 let auth_switch_response_payload = MySQLPacketPayload::new_with_payload(payload);
-                AuthMethodMismatchHandler::handle(Some(header), Some(auth_switch_response_payload), &mut self.session_ctx);
+                AuthMethodMismatchHandler::handle(Some(header), Some(auth_switch_response_payload), &mut self.session_ctx).await;
                 Ok(())
             }
         };
@@ -98,7 +99,7 @@ impl<'a> MySQLIOContext<'a> {
         let command_packet_type = payload.get_uint(1) as u8;
         let header = MySQLPacketHeader::new(len, sequence_id, command_packet_type, self.id);
         let command_payload = MySQLPacketPayload::new_with_payload(payload);
-        if let Err(e) = self.channel.send(CommandRootHandler::handle(Some(header), Some(command_payload), &mut self.session_ctx)).await {
+        if let Err(e) = self.channel.send_stream(CommandRootHandler::handle(Some(header), Some(command_payload), &mut self.session_ctx).await).await {
             println!("error on sending response; error = {:?}", e);
         }
     }
@@ -128,6 +129,8 @@ impl<'a> MySQLIOContext<'a> {
                 }
             }
         }
+        // The transaction the client left open is rolled back.
+        let _ = end_session_transaction(self.id, false).await;
     }
 }
 
@@ -17,6 +17,15 @@ pub struct SessionContext {
     user_name: String,
     auth_response: Vec<u8>,
     database: String,
+    /// Between `START TRANSACTION` and `COMMIT` or `ROLLBACK`.
+    in_transaction: bool,
+    /// Off, every statement is in a transaction.
+    autocommit: bool,
+    /// `SET dbmesh_read_your_writes = ON`: the reads after a write of the session go to the
+    /// primaries, the mirrors may not have the write yet.
+    read_your_writes: bool,
+    /// The session sent a write.
+    written: bool,
 }
 
 impl SessionContext {
@@ -37,6 +46,10 @@ impl SessionContext {
             user_name: "".to_string(),
             auth_response: vec![],
             database: "".to_string(),
+            in_transaction: false,
+            autocommit: true,
+            read_your_writes: false,
+            written: false,
         }
     }
 
@@ -84,6 +97,50 @@ impl SessionContext {
         self.database = database;
     }
 
+    pub fn is_in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    pub fn set_in_transaction(&mut self, in_transaction: bool) {
+        self.in_transaction = in_transaction;
+    }
+
+    pub fn is_autocommit(&self) -> bool {
+        self.autocommit
+    }
+
+    pub fn set_autocommit(&mut self, autocommit: bool) {
+        self.autocommit = autocommit;
+    }
+
+    pub fn is_read_your_writes(&self) -> bool {
+        self.read_your_writes
+    }
+
+    pub fn set_read_your_writes(&mut self, read_your_writes: bool) {
+        self.read_your_writes = read_your_writes;
+    }
+
+    pub fn is_written(&self) -> bool {
+        self.written
+    }
+
+    pub fn set_written(&mut self, written: bool) {
+        self.written = written;
+    }
+
+    /// Whether the statements of the session run in a transaction, started by `START
+    /// TRANSACTION` or because autocommit is off.
+    pub fn has_transaction(&self) -> bool {
+        self.in_transaction || !self.autocommit
+    }
+
+    /// Whether the reads of the session have to see its own writes, which only the
+    /// primaries are sure to have.
+    pub fn is_read_from_primary(&self) -> bool {
+        self.has_transaction() || (self.read_your_writes && self.written)
+    }
+
     pub fn cache_prepare_stmt_ctx(&mut self, sql: String, prepare_stmt_ctx: PrepareStatementContext) {
         self.prepare_stmt_ctx_id.insert(sql, prepare_stmt_ctx.statement_id);
         self.prepare_stmt_ctx_map.insert(prepare_stmt_ctx.statement_id, prepare_stmt_ctx);
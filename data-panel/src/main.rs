@@ -12,6 +12,9 @@ use clap::{App, Arg, SubCommand};
 use toml::Value;
 
 use data_panel_common::config::config::MeshConfig;
+use data_panel_database::discovery::database::Cluster;
+use data_panel_database::handler::database::mysql::rebalance;
+use data_panel_database::handler::database::parser::sql::route::algorithm;
 
 mod protocol;
 mod handler;
@@ -32,9 +35,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .multiple(true)
             .help("verbosity level"))
         .args_from_usage("-c, --config=[FILE] 'Target file you want to change'")
+        .args_from_usage("-r, --rules=[FILE] 'Cluster segments and distribution rules'")
         .subcommand(SubCommand::with_name("test")
             .about("does testing things")
             .arg_from_usage("-l, --list 'lists test values'"))
+        .subcommand(SubCommand::with_name("moved-rows")
+            .about("lists the rows of the dis_ring tables which move to other segments under new rules")
+            .arg_from_usage("-t, --to=<FILE> 'Cluster segments and distribution rules to move to'"))
         .get_matches();
 
     println!("args : {:#?}", matches);
@@ -78,6 +85,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("{:#?}", MeshConfig::current());
 
+    let rules_path = matches.value_of("rules").unwrap_or("./data-panel/etc/dbmesh.yaml");
+    println!("rules_path : {}", rules_path);
+
+    let cluster = Cluster::from_file(rules_path).map_err(|e| format!("{:?}", e))?;
+    algorithm::compile_dis_rules(cluster.get_dis_rules()).map_err(|e| format!("{:?}", e))?;
+    cluster.make_current();
+
+    if let Some(matches) = matches.subcommand_matches("moved-rows") {
+        let after = Cluster::from_file(matches.value_of("to").unwrap()).map_err(|e| format!("{:?}", e))?;
+        algorithm::compile_dis_rules(after.get_dis_rules()).map_err(|e| format!("{:?}", e))?;
+        let moved_rows = rebalance::moved_rows(&Cluster::current(), &after).await.map_err(|e| format!("{:?}", e))?;
+        for moved_row in moved_rows.iter() {
+            let dis_values: Vec<String> = moved_row.get_dis_values().iter()
+                .map(|(dis_key, value)| format!("{}={}", dis_key, String::from_utf8_lossy(&value.to_text())))
+                .collect();
+            println!("{} {} -> {} {}", moved_row.get_table(), moved_row.get_from(), moved_row.get_to(), dis_values.join(", "));
+        }
+        println!("{} rows move", moved_rows.len());
+        return Ok(());
+    }
+
     let service = service::new_service();
 
     service.serve().await